@@ -0,0 +1,488 @@
+use crate::condition::Condition;
+use crate::dwarf_data::{DwarfData, Type};
+use crate::inferior::Inferior;
+use crate::watchpoint::{self, WatchHit, WatchKind, WatchScope, Watchpoint, DR_CONTROL, NUM_DEBUG_REGISTERS};
+use std::collections::{HashMap, HashSet};
+
+pub struct Breakpoint {
+    pub number: usize,
+    /// The location as the user typed it (`*address`, `line` or `func`).
+    pub spec: String,
+    /// None until the location turns up, e.g. in a shared library that isn't loaded yet.
+    pub addr: Option<usize>,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Only stop if this evaluates to true.
+    pub condition: Option<Condition>,
+    /// Number of upcoming hits to pass over without stopping.
+    pub ignore_count: usize,
+}
+
+/// The numbered breakpoints and watchpoints known to the debugger, along with the original
+/// bytes of the addresses where a 0xcc is (or, if the inferior isn't running yet, will be)
+/// inserted.
+pub struct Breakpoints {
+    next_number: usize,
+    list: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    saved_bytes: HashMap<usize, u8>,
+    /// Internal breakpoints used while stepping (e.g. at a return address).
+    temporary: HashSet<usize>,
+    /// Breakpoints that caused the most recent stop.
+    hits: Vec<usize>,
+    /// Watchpoints that caused the most recent stop.
+    watch_hits: Vec<WatchHit>,
+    /// Watchpoints whose frame returned at the most recent stop.
+    scope_exits: Vec<usize>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            next_number: 1,
+            list: Vec::new(),
+            watchpoints: Vec::new(),
+            saved_bytes: HashMap::new(),
+            temporary: HashSet::new(),
+            hits: Vec::new(),
+            watch_hits: Vec::new(),
+            scope_exits: Vec::new(),
+        }
+    }
+
+    /// Maps each address that should hold a 0xcc to the byte it replaced. This is what
+    /// `Inferior` uses to insert breakpoints and step over them.
+    pub fn saved_bytes(&self) -> &HashMap<usize, u8> {
+        &self.saved_bytes
+    }
+
+    /// Creates a new breakpoint and inserts it into the inferior if one is running.
+    pub fn add(
+        &mut self,
+        spec: &str,
+        addr: usize,
+        condition: Option<Condition>,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<&Breakpoint, nix::Error> {
+        self.insert(addr, inferior)?;
+        self.list.push(Breakpoint {
+            number: self.next_number,
+            spec: spec.to_string(),
+            addr: Some(addr),
+            enabled: true,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+        });
+        self.next_number += 1;
+        Ok(self.list.last().unwrap())
+    }
+
+    /// Creates a breakpoint whose location can't be found yet. It is inserted once `resolve`
+    /// finds it, e.g. after a dlopen or exec.
+    pub fn add_pending(&mut self, spec: &str, condition: Option<Condition>) -> &Breakpoint {
+        self.list.push(Breakpoint {
+            number: self.next_number,
+            spec: spec.to_string(),
+            addr: None,
+            enabled: true,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+        });
+        self.next_number += 1;
+        self.list.last().unwrap()
+    }
+
+    /// Deletes breakpoint `number`, restoring the original byte if no other enabled breakpoint
+    /// shares its address. Returns false if there is no such breakpoint.
+    pub fn delete(&mut self, number: usize, inferior: Option<&mut Inferior>) -> Result<bool, nix::Error> {
+        if let Some(index) = self.watchpoints.iter().position(|wp| wp.number == number) {
+            let wp = self.watchpoints.remove(index);
+            self.assign_slots();
+            if let Some(inferior) = &inferior {
+                self.install_watchpoints(inferior)?;
+            }
+            if let Some(scope) = wp.scope {
+                self.remove(scope.ret_addr, inferior)?;
+            }
+            return Ok(true);
+        }
+        let index = match self.list.iter().position(|bp| bp.number == number) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let bp = self.list.remove(index);
+        if let Some(addr) = bp.addr.filter(|_| bp.enabled) {
+            self.remove(addr, inferior)?;
+        }
+        Ok(true)
+    }
+
+    /// Enables or disables breakpoint `number`. Returns false if there is no such breakpoint.
+    pub fn set_enabled(
+        &mut self,
+        number: usize,
+        enabled: bool,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<bool, nix::Error> {
+        let free_slots = self.free_slots();
+        if let Some(wp) = self.watchpoints.iter_mut().find(|wp| wp.number == number) {
+            if enabled && !wp.enabled && free_slots == 0 {
+                println!("Cannot enable watchpoint {}: all {} debug registers are in use.", number, NUM_DEBUG_REGISTERS);
+                return Ok(true);
+            }
+            wp.enabled = enabled;
+            self.assign_slots();
+            if let Some(inferior) = inferior {
+                self.install_watchpoints(inferior)?;
+            }
+            return Ok(true);
+        }
+        let bp = match self.list.iter_mut().find(|bp| bp.number == number) {
+            Some(bp) => bp,
+            None => return Ok(false),
+        };
+        if bp.enabled == enabled {
+            return Ok(true);
+        }
+        bp.enabled = enabled;
+        let addr = match bp.addr {
+            Some(addr) => addr,
+            None => return Ok(true),
+        };
+        if enabled {
+            self.insert(addr, inferior)?;
+        } else {
+            self.remove(addr, inferior)?;
+        }
+        Ok(true)
+    }
+
+    /// Re-resolves every breakpoint for a program that has just started (or exec'd), whose
+    /// memory holds none of our 0xcc bytes and may have been loaded somewhere else than last
+    /// time, and inserts them again. Breakpoints that `resolve` can't find stay unresolved until
+    /// a shared library defines them.
+    pub fn reset<F>(&mut self, resolve: F, inferior: &mut Inferior) -> Result<(), nix::Error>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        self.saved_bytes.clear();
+        self.temporary.clear();
+        for bp in self.list.iter_mut() {
+            bp.addr = None;
+        }
+        self.resolve(resolve, inferior)?;
+        self.install_watchpoints(inferior)
+    }
+
+    /// Tries to find the breakpoints that are still unresolved with `resolve`, e.g. after
+    /// shared libraries were loaded, and inserts the ones that turn up.
+    pub fn resolve<F>(&mut self, resolve: F, inferior: &mut Inferior) -> Result<(), nix::Error>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        let mut addrs = Vec::new();
+        for bp in self.list.iter_mut().filter(|bp| bp.addr.is_none()) {
+            bp.addr = resolve(&bp.spec);
+            addrs.extend(bp.addr.filter(|_| bp.enabled));
+        }
+        for addr in addrs {
+            self.insert(addr, Some(inferior))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if an enabled user breakpoint is at `addr`.
+    pub fn has_breakpoint_at(&self, addr: usize) -> bool {
+        self.list.iter().any(|bp| bp.enabled && bp.addr == Some(addr))
+    }
+
+    /// Returns the numbers of all breakpoints and watchpoints, in creation order.
+    pub fn numbers(&self) -> Vec<usize> {
+        let mut numbers: Vec<usize> = self.list.iter().map(|bp| bp.number).collect();
+        numbers.extend(self.watchpoints.iter().map(|wp| wp.number));
+        numbers.sort();
+        numbers
+    }
+
+    /// Creates a hardware watchpoint on the `entity_type.size` bytes at `addr`, and programs the
+    /// debug registers if the inferior is running. A watchpoint with a `scope` is deleted when
+    /// that frame returns.
+    pub fn add_watchpoint(
+        &mut self,
+        expr: &str,
+        addr: usize,
+        entity_type: Type,
+        kind: WatchKind,
+        scope: Option<WatchScope>,
+        mut inferior: Option<&mut Inferior>,
+    ) -> Result<&Watchpoint, String> {
+        watchpoint::check_watchable(addr, entity_type.size)?;
+        if self.free_slots() == 0 {
+            return Err(format!("all {} debug registers are in use", NUM_DEBUG_REGISTERS));
+        }
+        self.watchpoints.push(Watchpoint {
+            number: self.next_number,
+            expr: expr.to_string(),
+            addr,
+            entity_type,
+            kind,
+            enabled: true,
+            hit_count: 0,
+            slot: None,
+            old_value: Vec::new(),
+            scope,
+        });
+        self.next_number += 1;
+        self.assign_slots();
+        if let Some(scope) = scope {
+            self.insert(scope.ret_addr, inferior.as_deref_mut()).map_err(|err| err.to_string())?;
+        }
+        if let Some(inferior) = inferior {
+            self.install_watchpoints(inferior).map_err(|err| err.to_string())?;
+        }
+        Ok(self.watchpoints.last().unwrap())
+    }
+
+    fn free_slots(&self) -> usize {
+        NUM_DEBUG_REGISTERS - self.watchpoints.iter().filter(|wp| wp.enabled).count()
+    }
+
+    /// Hands out the debug registers to the enabled watchpoints.
+    fn assign_slots(&mut self) {
+        let mut next_slot = 0;
+        for wp in self.watchpoints.iter_mut() {
+            if wp.enabled && next_slot < NUM_DEBUG_REGISTERS {
+                wp.slot = Some(next_slot);
+                next_slot += 1;
+            } else {
+                wp.slot = None;
+            }
+        }
+    }
+
+    /// Programs DR0-DR3 and DR7 of `inferior` for the enabled watchpoints and records the
+    /// current values of the watched memory. Needs to be redone for every new process.
+    pub fn install_watchpoints(&mut self, inferior: &Inferior) -> Result<(), nix::Error> {
+        let mut control = 0;
+        for wp in self.watchpoints.iter_mut() {
+            if let Some(slot) = wp.slot {
+                inferior.set_debug_register(slot, wp.addr as u64)?;
+                control |= wp.control_bits();
+                wp.old_value = inferior.read_bytes(wp.addr, wp.entity_type.size).unwrap_or_default();
+            }
+        }
+        inferior.set_debug_register(DR_CONTROL, control)
+    }
+
+    /// Decides whether to stop for the watchpoints whose DR6 bits are set in `triggered`. Write
+    /// watchpoints only stop if the value actually changed.
+    pub fn watchpoints_should_stop(&mut self, triggered: u8, inferior: &Inferior) -> bool {
+        self.watch_hits.clear();
+        for wp in self.watchpoints.iter_mut() {
+            let slot = match wp.slot {
+                Some(slot) if triggered & (1 << slot) != 0 => slot,
+                _ => continue,
+            };
+            let new_value = match inferior.read_bytes(wp.addr, wp.entity_type.size) {
+                Ok(value) => value,
+                Err(err) => {
+                    println!("Cannot read watchpoint {} (DR{}): {}", wp.number, slot, err);
+                    continue;
+                }
+            };
+            if wp.kind == WatchKind::Write && new_value == wp.old_value {
+                continue;
+            }
+            wp.hit_count += 1;
+            self.watch_hits.push(WatchHit {
+                number: wp.number,
+                expr: wp.expr.clone(),
+                kind: wp.kind,
+                old_value: wp.entity_type.format_value(&wp.old_value),
+                new_value: wp.entity_type.format_value(&new_value),
+            });
+            wp.old_value = new_value;
+        }
+        !self.watch_hits.is_empty()
+    }
+
+    pub fn has_watch_hits(&self) -> bool {
+        !self.watch_hits.is_empty()
+    }
+
+    /// Returns the watchpoints responsible for the most recent stop, and forgets them.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Makes breakpoint `number` pass over its next `count` hits. Returns false if there is no
+    /// such breakpoint.
+    pub fn set_ignore_count(&mut self, number: usize, count: usize) -> bool {
+        match self.list.iter_mut().find(|bp| bp.number == number) {
+            Some(bp) => {
+                bp.ignore_count = count;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Decides whether the inferior, stopped on the 0xcc at `addr`, should stay stopped. Each
+    /// enabled breakpoint there whose condition holds counts a hit and, unless it is still
+    /// being ignored, asks to stop. Temporary breakpoints always stop, as do watchpoints whose
+    /// frame has just returned here.
+    pub fn should_stop(&mut self, addr: usize, inferior: &Inferior, debug_data: &DwarfData) -> bool {
+        self.hits.clear();
+        self.scope_exits.clear();
+        let tid = inferior.current_thread().tid;
+        let rsp = inferior.get_registers().map_or(0, |regs| regs.rsp as usize);
+        for wp in self.watchpoints.iter() {
+            // Recursive calls return to the same address from deeper down the stack
+            if let Some(scope) = wp.scope
+                && scope.ret_addr == addr
+                && scope.tid == tid
+                && rsp >= scope.cfa
+            {
+                self.scope_exits.push(wp.number);
+            }
+        }
+        for bp in self.list.iter_mut().filter(|bp| bp.enabled && bp.addr == Some(addr)) {
+            if let Some(condition) = &bp.condition {
+                match condition.evaluate(inferior, debug_data) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(err) => {
+                        println!("Error in testing condition for breakpoint {}: {}", bp.number, err);
+                    }
+                }
+            }
+            bp.hit_count += 1;
+            if bp.ignore_count > 0 {
+                bp.ignore_count -= 1;
+                continue;
+            }
+            self.hits.push(bp.number);
+        }
+        !self.hits.is_empty() || !self.scope_exits.is_empty() || self.temporary.contains(&addr)
+    }
+
+    /// Returns the breakpoints responsible for the most recent stop, and forgets them.
+    pub fn take_hits(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.hits)
+    }
+
+    /// Returns the watchpoints that went out of scope at the most recent stop, and forgets them.
+    /// They are left for the caller to delete.
+    pub fn take_scope_exits(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.scope_exits)
+    }
+
+    /// Returns the numbers of the watchpoints on locals of some frame.
+    pub fn scoped_watchpoints(&self) -> Vec<usize> {
+        self.watchpoints.iter().filter(|wp| wp.scope.is_some()).map(|wp| wp.number).collect()
+    }
+
+    /// Returns true if a user breakpoint asked for the most recent stop.
+    pub fn was_hit(&self) -> bool {
+        !self.hits.is_empty()
+    }
+
+    /// Inserts an internal breakpoint at `addr` that isn't shown to the user.
+    pub fn insert_temporary(&mut self, addr: usize, inferior: &mut Inferior) -> Result<(), nix::Error> {
+        self.temporary.insert(addr);
+        self.insert(addr, Some(inferior))
+    }
+
+    /// Removes an internal breakpoint. `inferior` is None if the process is already gone.
+    pub fn remove_temporary(&mut self, addr: usize, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        self.temporary.remove(&addr);
+        self.remove(addr, inferior)
+    }
+
+    /// Writes a 0xcc at `addr` unless an enabled breakpoint already put one there.
+    fn insert(&mut self, addr: usize, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        if self.saved_bytes.contains_key(&addr) {
+            return Ok(());
+        }
+        let orig = match inferior {
+            Some(inferior) => inferior.write_byte(addr, 0xcc)?,
+            None => 0,
+        };
+        self.saved_bytes.insert(addr, orig);
+        Ok(())
+    }
+
+    /// Restores the original byte at `addr` once no enabled breakpoint refers to it anymore.
+    fn remove(&mut self, addr: usize, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        if self.temporary.contains(&addr)
+            || self.has_breakpoint_at(addr)
+            || self.watchpoints.iter().any(|wp| wp.scope.is_some_and(|scope| scope.ret_addr == addr))
+        {
+            return Ok(());
+        }
+        if let Some(orig) = self.saved_bytes.remove(&addr)
+            && let Some(inferior) = inferior
+        {
+            inferior.write_byte(addr, orig)?;
+        }
+        Ok(())
+    }
+
+    pub fn print(&self, debug_data: &DwarfData) {
+        if self.list.is_empty() && self.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+            return;
+        }
+        println!("{:<8}{:<17}{:<5}{:<20}What", "Num", "Type", "Enb", "Address");
+        for number in self.numbers() {
+            if let Some(wp) = self.watchpoints.iter().find(|wp| wp.number == number) {
+                let kind = match wp.kind {
+                    WatchKind::Write => "hw watchpoint",
+                    WatchKind::Read => "read watchpoint",
+                };
+                print_row(wp.number, kind, wp.enabled, &format!("{:#x}", wp.addr), &wp.expr);
+                print_hit_count(wp.hit_count);
+            } else if let Some(bp) = self.list.iter().find(|bp| bp.number == number) {
+                let what = match bp.addr.and_then(|addr| debug_data.get_line_from_addr(addr)) {
+                    Some(line) => format!("{} at {}", bp.spec, line),
+                    None => bp.spec.clone(),
+                };
+                let addr = match bp.addr {
+                    Some(addr) => format!("{:#x}", addr),
+                    None => "<PENDING>".to_string(),
+                };
+                print_row(bp.number, "breakpoint", bp.enabled, &addr, &what);
+                if let Some(condition) = &bp.condition {
+                    println!("\tstop only if {}", condition);
+                }
+                print_hit_count(bp.hit_count);
+                if bp.ignore_count > 0 {
+                    println!("\tWill ignore next {} crossings of breakpoint.", bp.ignore_count);
+                }
+            }
+        }
+    }
+}
+
+fn print_row(number: usize, kind: &str, enabled: bool, addr: &str, what: &str) {
+    println!(
+        "{:<8}{:<17}{:<5}{:<20}{}",
+        number,
+        kind,
+        if enabled { "y" } else { "n" },
+        addr,
+        what
+    );
+}
+
+fn print_hit_count(hit_count: usize) {
+    if hit_count > 0 {
+        println!(
+            "\tbreakpoint already hit {} time{}",
+            hit_count,
+            if hit_count == 1 { "" } else { "s" }
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use crate::dwarf_data::DwarfData;
+use crate::expression::{Expression, Scope};
+use crate::inferior::Inferior;
+use std::fmt;
+
+/// A breakpoint condition: an expression that has to be nonzero for the breakpoint to stop,
+/// e.g. `i == 3` or `list->next && list->val > sum`.
+#[derive(Clone)]
+pub struct Condition {
+    expression: Expression,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        Ok(Condition {
+            expression: Expression::parse(text)?,
+        })
+    }
+
+    /// Evaluates the condition in the innermost frame of the stopped inferior.
+    pub fn evaluate(&self, inferior: &Inferior, debug_data: &DwarfData) -> Result<bool, String> {
+        // The caller is only needed for the entry values of parameters
+        let mut frames = inferior.frames(debug_data, 2).map_err(|err| err.to_string())?.into_iter();
+        let scope = Scope {
+            debug_data,
+            inferior: Some(inferior),
+            frame: frames.next(),
+            caller: frames.next(),
+        };
+        let value = self.expression.evaluate(&scope)?;
+        scope.is_true(&value)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
@@ -0,0 +1,210 @@
+//! Reading ELF core dumps, for post-mortem debugging. A core has a PT_LOAD segment for each
+//! memory mapping of the dead process and notes with the registers of each of its threads.
+
+use crate::inferior::Mapping;
+use nix::libc;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use object::elf;
+use object::read::elf::{ElfFile64, FileHeader, ProgramHeader};
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+use std::ptr;
+
+/// Offsets into the `elf_prstatus` and `elf_prpsinfo` structs of x86-64 Linux.
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REG: usize = 112;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_PSARGS: usize = 56;
+const PRPSINFO_PSARGS_LEN: usize = 80;
+
+/// A thread of the dumped process.
+pub struct CoreThread {
+    pub tid: Pid,
+    pub regs: libc::user_regs_struct,
+    pub fp_regs: Option<libc::user_fpregs_struct>,
+}
+
+/// A PT_LOAD segment: memory at `addr`, of which the first `file_size` bytes were dumped at
+/// `offset` in the core. Unmodified file mappings such as code usually aren't dumped.
+struct Segment {
+    addr: u64,
+    mem_size: u64,
+    offset: u64,
+    file_size: u64,
+}
+
+pub struct Core {
+    data: memmap2::Mmap,
+    segments: Vec<Segment>,
+    /// The thread that got the fatal signal comes first.
+    pub threads: Vec<CoreThread>,
+    /// The files the process had mapped, from the NT_FILE note.
+    pub mappings: Vec<Mapping>,
+    /// The auxiliary vector the kernel passed to the process, as (type, value) pairs.
+    pub auxv: Vec<(u64, u64)>,
+    pub pid: Pid,
+    /// The signal that killed the process.
+    pub signal: Option<Signal>,
+    /// The command line, e.g. `./segfault 1 2`.
+    pub command: String,
+}
+
+impl Core {
+    pub fn load(path: &str) -> Result<Core, String> {
+        let file = File::open(path).map_err(|err| format!("{}: {}.", path, err))?;
+        let data = unsafe { memmap2::Mmap::map(&file) }.map_err(|err| format!("{}: {}.", path, err))?;
+        let not_core = || format!("\"{}\" is not a core dump: file format not recognized", path);
+        let elf = ElfFile64::<object::Endianness>::parse(&*data).map_err(|_| not_core())?;
+        let endian = elf.endian();
+        if elf.elf_header().e_type(endian) != elf::ET_CORE {
+            return Err(not_core());
+        }
+
+        // Notes are small, so copy them out to let the core keep the mapping
+        let mut segments = Vec::new();
+        let mut notes = Vec::new();
+        for header in elf.elf_program_headers() {
+            match header.p_type(endian) {
+                elf::PT_LOAD => segments.push(Segment {
+                    addr: header.p_vaddr(endian),
+                    mem_size: header.p_memsz(endian),
+                    offset: header.p_offset(endian),
+                    file_size: header.p_filesz(endian),
+                }),
+                elf::PT_NOTE => {
+                    let mut iter = match header.notes(endian, &*data) {
+                        Ok(Some(iter)) => iter,
+                        _ => continue,
+                    };
+                    while let Ok(Some(note)) = iter.next() {
+                        if note.name() == b"CORE" {
+                            notes.push((note.n_type(endian), note.desc().to_vec()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut core = Core {
+            data,
+            segments,
+            threads: Vec::new(),
+            mappings: Vec::new(),
+            auxv: Vec::new(),
+            pid: Pid::from_raw(0),
+            signal: None,
+            command: String::new(),
+        };
+        for (note_type, desc) in notes {
+            core.read_note(note_type, &desc);
+        }
+        if core.threads.is_empty() {
+            return Err(format!("\"{}\": no threads found in core file", path));
+        }
+        if core.pid.as_raw() == 0 {
+            core.pid = core.threads[0].tid;
+        }
+        Ok(core)
+    }
+
+    fn read_note(&mut self, note_type: u32, desc: &[u8]) {
+        match note_type {
+            elf::NT_PRSTATUS if desc.len() >= PRSTATUS_REG + size_of::<libc::user_regs_struct>() => {
+                if self.threads.is_empty() {
+                    let signal = u16::from_le_bytes([desc[PRSTATUS_CURSIG], desc[PRSTATUS_CURSIG + 1]]);
+                    self.signal = Signal::try_from(signal as i32).ok();
+                }
+                self.threads.push(CoreThread {
+                    tid: Pid::from_raw(read_u32(desc, PRSTATUS_PID) as i32),
+                    regs: unsafe { ptr::read_unaligned(desc[PRSTATUS_REG..].as_ptr() as *const _) },
+                    fp_regs: None,
+                });
+            }
+            // The floating point registers of each thread follow its NT_PRSTATUS
+            elf::NT_PRFPREG if desc.len() >= size_of::<libc::user_fpregs_struct>() => {
+                if let Some(thread) = self.threads.last_mut() {
+                    thread.fp_regs = Some(unsafe { ptr::read_unaligned(desc.as_ptr() as *const _) });
+                }
+            }
+            elf::NT_PRPSINFO if desc.len() >= PRPSINFO_PSARGS + PRPSINFO_PSARGS_LEN => {
+                self.pid = Pid::from_raw(read_u32(desc, PRPSINFO_PID) as i32);
+                let args = &desc[PRPSINFO_PSARGS..PRPSINFO_PSARGS + PRPSINFO_PSARGS_LEN];
+                let len = args.iter().position(|&byte| byte == 0).unwrap_or(args.len());
+                self.command = String::from_utf8_lossy(&args[..len]).trim_end().to_string();
+            }
+            elf::NT_AUXV => {
+                self.auxv = desc.chunks_exact(16).map(|entry| (read_u64(entry, 0), read_u64(entry, 8))).collect();
+            }
+            // A count and the page size, then (start, end, page offset) for each mapping, then
+            // their paths
+            elf::NT_FILE if desc.len() >= 16 => {
+                let count = read_u64(desc, 0) as usize;
+                let page_size = read_u64(desc, 8);
+                let mut paths = desc.get(16 + count * 24..).unwrap_or(&[]).split(|&byte| byte == 0);
+                for index in 0..count {
+                    let entry = 16 + index * 24;
+                    let path = match paths.next() {
+                        Some(path) if desc.len() >= entry + 24 => String::from_utf8_lossy(path).into_owned(),
+                        _ => break,
+                    };
+                    self.mappings.push(Mapping {
+                        path,
+                        start: read_u64(desc, entry),
+                        end: read_u64(desc, entry + 8),
+                        offset: read_u64(desc, entry + 16) * page_size,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn thread(&self, tid: Pid) -> Option<&CoreThread> {
+        self.threads.iter().find(|thread| thread.tid == tid)
+    }
+
+    /// Reads the dumped process's memory. What wasn't dumped is read from the mapped file it
+    /// came from, as long as that file is still around.
+    pub fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let addr = (addr + bytes.len()) as u64;
+            let remaining = (len - bytes.len()) as u64;
+            let segment = self
+                .segments
+                .iter()
+                .find(|segment| segment.addr <= addr && addr < segment.addr + segment.mem_size)?;
+            let offset = addr - segment.addr;
+            if offset < segment.file_size {
+                let count = remaining.min(segment.file_size - offset);
+                let start = (segment.offset + offset) as usize;
+                bytes.extend_from_slice(self.data.get(start..start + count as usize)?);
+            } else {
+                let count = remaining.min(segment.mem_size - offset);
+                bytes.extend(self.read_mapped_file(addr, count as usize)?);
+            }
+        }
+        Some(bytes)
+    }
+
+    fn read_mapped_file(&self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let mapping = self.mappings.iter().find(|mapping| mapping.start <= addr && addr < mapping.end)?;
+        let len = len.min((mapping.end - addr) as usize);
+        let mut bytes = vec![0; len];
+        let file = File::open(&mapping.path).ok()?;
+        file.read_exact_at(&mut bytes, mapping.offset + addr - mapping.start).ok()?;
+        Some(bytes)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
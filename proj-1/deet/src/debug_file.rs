@@ -0,0 +1,56 @@
+//! Debug info kept apart from the program. Distributions ship stripped binaries and install
+//! their DWARF separately, where it can be found by the build ID the linker stamped on the
+//! binary (`<dir>/.build-id/ab/cdef....debug`), or by the name and CRC recorded in its
+//! `.gnu_debuglink` section.
+
+use object::Object;
+use std::fs;
+use std::path::Path;
+
+/// Where separate debug files are looked for, unless `set debug-file-directory` says otherwise.
+pub const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Finds the separate debug file of `object`, which was loaded from `path`: by build ID under
+/// each of `debug_dirs`, or else by debuglink next to the program, in its `.debug` directory,
+/// or in the same place under each of `debug_dirs`.
+pub fn find(object: &object::File, path: &str, debug_dirs: &[String]) -> Option<String> {
+    if let Ok(Some(build_id)) = object.build_id() {
+        let hex: String = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        if hex.len() > 2 {
+            let name = format!(".build-id/{}/{}.debug", &hex[..2], &hex[2..]);
+            let found = debug_dirs.iter().map(|dir| Path::new(dir).join(&name)).find(|file| file.is_file());
+            if let Some(file) = found {
+                return Some(file.to_string_lossy().into_owned());
+            }
+        }
+    }
+    let (name, crc) = object.gnu_debuglink().ok()??;
+    let name = std::str::from_utf8(name).ok()?;
+    let program = fs::canonicalize(path).ok()?;
+    let dir = program.parent()?;
+    let mut candidates = vec![dir.join(name), dir.join(".debug").join(name)];
+    for debug_dir in debug_dirs {
+        candidates.push(Path::new(debug_dir).join(dir.strip_prefix("/").unwrap_or(dir)).join(name));
+    }
+    // The debug file may have the same name as the program, next to which it can't be
+    candidates
+        .into_iter()
+        .filter(|file| *file != program)
+        .find(|file| fs::read(file).is_ok_and(|data| crc32(&data) == crc))
+        .map(|file| file.to_string_lossy().into_owned())
+}
+
+/// The CRC-32 that `.gnu_debuglink` records of the debug file, as computed by zlib.
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (byte, entry) in table.iter_mut().enumerate() {
+        let mut crc = byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    !data
+        .iter()
+        .fold(!0u32, |crc, &byte| table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
@@ -1,37 +1,68 @@
+use crate::breakpoint::Breakpoints;
+use crate::condition::Condition;
+use crate::coredump::Core;
+use crate::debug_file;
 use crate::debugger_command::DebuggerCommand;
-use crate::inferior::Inferior;
+use crate::inferior::{FollowForkMode, Frame, Inferior};
 use crate::inferior::Status;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use rustyline::history::FileHistory;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::disassembler;
+use crate::examine::{self, ExamineFormat};
+use crate::expression::{Expression, Scope, Value};
+use crate::dwarf_data::{DwarfData, Encoding, Error as DwarfError, Line, LocationError, Type, TypeKind, Variable};
+use crate::registers;
+use crate::signals::{self, SignalTable};
+use crate::solib::SharedLibrary;
+use crate::source::{SourceFiles, LIST_WINDOW};
+use crate::watchpoint::{WatchKind, WatchScope};
+use std::path::Path;
 
-pub struct Debugger<'a> {
+pub struct Debugger {
     target: String,
     history_path: String,
     readline: Editor<(), FileHistory>,
     inferior: Option<Inferior>,
-    debug_data: DwarfData<'a>,
-    breakpoints: HashMap<usize, u8>
+    debug_data: DwarfData,
+    breakpoints: Breakpoints,
+    source_files: SourceFiles,
+    /// Source line of the selected frame.
+    stop_line: Option<Line>,
+    /// Next line to be shown by a bare `list`, so that repeated calls page forward.
+    list_position: Option<Line>,
+    follow_fork_mode: FollowForkMode,
+    /// What to do with each signal the inferior receives, applied to every inferior we start.
+    signals: SignalTable,
+    /// Where the debug info of stripped programs and libraries is looked for.
+    debug_file_directories: Vec<String>,
+    /// Backtrace index of the frame whose variables and source we look at; 0 is the innermost.
+    selected_frame: usize,
+    /// Format of the last `x` command, and the address a bare `x` continues from.
+    examine_format: ExamineFormat,
+    examine_next: Option<usize>,
+    /// Expressions shown every time the program stops, with their numbers.
+    displays: Vec<(usize, Expression)>,
+    next_display_number: usize,
 }
 
-impl Debugger<'_> {
+impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
         // TODO (milestone 3): initialize the DwarfData
 
-        let debug_data = match DwarfData::from_file(target) {
+        let debug_file_directories = vec![debug_file::DEFAULT_DEBUG_FILE_DIRECTORY.to_string()];
+        let debug_data = match Self::load_debug_data(target, &debug_file_directories) {
             Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
+            Err(err) => {
+                println!("{}", err);
                 std::process::exit(1);
             }
         };
         debug_data.print();
+        Self::report_symbols(&debug_data, target);
 
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
         let mut readline = Editor::<(), FileHistory>::new().expect("Create Editor Failed");
@@ -43,28 +74,228 @@ impl Debugger<'_> {
             history_path,
             readline,
             inferior: None,
-            debug_data
+            debug_data,
+            breakpoints: Breakpoints::new(),
+            source_files: SourceFiles::new(),
+            stop_line: None,
+            list_position: None,
+            follow_fork_mode: FollowForkMode::Parent,
+            signals: SignalTable::new(),
+            debug_file_directories,
+            selected_frame: 0,
+            examine_format: ExamineFormat::new(),
+            examine_next: None,
+            displays: Vec::new(),
+            next_display_number: 1,
+        }
+    }
+
+    fn load_debug_data(path: &str, debug_dirs: &[String]) -> Result<DwarfData, String> {
+        match DwarfData::from_file(path, debug_dirs) {
+            Ok(val) => Ok(val),
+            Err(DwarfError::ErrorOpeningFile) => Err(format!("Could not open file {}", path)),
+            Err(DwarfError::DwarfFormatError(err)) => {
+                Err(format!("Could not debugging symbols from {}: {:?}", path, err))
+            }
+        }
+    }
+
+    /// Says where the debug info of `target` came from if it was in a separate file, or that
+    /// there was none.
+    fn report_symbols(debug_data: &DwarfData, target: &str) {
+        if let Some(debug_file) = debug_data.debug_file() {
+            println!("Reading symbols from {}...", debug_file);
+        }
+        if !debug_data.has_debug_info() {
+            println!("(No debugging symbols found in {})", target);
+        }
+    }
+
+    /// Replaces the debug info of the target, relocating it to where the inferior (if any) was
+    /// loaded and resolving the breakpoints that are still pending.
+    fn install_debug_data(&mut self, mut debug_data: DwarfData) {
+        Self::report_symbols(&debug_data, &self.target);
+        if let Some(inferior) = &self.inferior {
+            debug_data.relocate(inferior.load_bias(debug_data.entry_point()));
+        }
+        self.debug_data = debug_data;
+        if let Some(inferior) = Self::live_process(&mut self.inferior) {
+            let debug_data = &self.debug_data;
+            let resolve = |spec: &str| Self::lookup_location(debug_data, spec, None).ok();
+            if let Err(err) = self.breakpoints.resolve(resolve, inferior) {
+                println!("Could not insert breakpoints: {}", err);
+            }
+        }
+    }
+
+    /// `symbol-file FILE`: reads the target's debug info from FILE, e.g. one that was split off
+    /// with `objcopy --only-keep-debug`.
+    fn load_symbol_file(&mut self, path: &str) {
+        if !Path::new(path).is_file() {
+            println!("{}: No such file or directory.", path);
+            return;
+        }
+        match DwarfData::from_debug_file(&self.target, path) {
+            Ok(debug_data) => self.install_debug_data(debug_data),
+            Err(DwarfError::ErrorOpeningFile) => println!("Could not open file {}", path),
+            Err(DwarfError::DwarfFormatError(err)) => {
+                println!("Could not debugging symbols from {}: {:?}", path, err)
+            }
+        }
+    }
+
+    /// `set debug-file-directory`: looks in `dirs` from now on, and right away for the debug
+    /// info of the target if it has none yet.
+    fn set_debug_file_directories(&mut self, dirs: Vec<String>) {
+        self.debug_file_directories = dirs;
+        if let Some(inferior) = &mut self.inferior {
+            inferior.debug_file_directories = self.debug_file_directories.clone();
+        }
+        if self.debug_data.has_debug_info() {
+            return;
+        }
+        if let Ok(debug_data) = Self::load_debug_data(&self.target, &self.debug_file_directories)
+            && debug_data.has_debug_info()
+        {
+            self.install_debug_data(debug_data);
+        }
+    }
+
+    /// Attaches to the running process `pid`. If it runs a different executable than the one
+    /// we were started with, its debugging symbols are loaded from /proc/<pid>/exe.
+    pub fn attach(&mut self, pid: i32) {
+        if self.inferior.is_some() {
+            println!("A program is already being debugged. Detach from it first.");
+            return;
+        }
+        let exe = format!("/proc/{}/exe", pid);
+        let target = match std::fs::read_link(&exe) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(err) => {
+                println!("Could not find the executable of process {}: {}", pid, err);
+                return;
+            }
+        };
+        if target != self.target {
+            match Self::load_debug_data(&exe, &self.debug_file_directories) {
+                Ok(debug_data) => self.debug_data = debug_data,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+            self.target = target;
+        }
+        match Inferior::attach(Pid::from_raw(pid)) {
+            Ok(mut inferior) => {
+                inferior.follow_fork_mode = self.follow_fork_mode;
+                inferior.signals = self.signals.clone();
+                inferior.debug_file_directories = self.debug_file_directories.clone();
+                self.prepare_inferior(&mut inferior, true);
+                println!("Attached to process {} ({})", pid, self.target);
+                let rip = inferior.get_registers().map(|regs| regs.rip as usize).unwrap_or(0);
+                self.inferior = Some(inferior);
+                self.report_stop(rip);
+            }
+            Err(err) => println!("Could not attach to process {}: {}", pid, err),
+        }
+    }
+
+    /// Loads a core dump of the target to look at how it crashed: where each thread was, its
+    /// registers and its memory.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match Core::load(path) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        // The target is normally one of the files the process had mapped
+        let target = std::fs::canonicalize(&self.target).unwrap_or_default();
+        let matches = core.mappings.iter().any(|mapping| target.as_os_str() == mapping.path.as_str());
+        if !core.mappings.is_empty() && !matches {
+            println!("warning: core file may not match specified executable file.");
+        }
+        if !core.command.is_empty() {
+            println!("Core was generated by `{}'.", core.command);
+        }
+        if let Some(signal) = core.signal {
+            println!("Program terminated with signal {}, {}.", signal.as_str(), signals::description(signal));
+        }
+        let mut inferior = Inferior::from_core(core);
+        inferior.debug_file_directories = self.debug_file_directories.clone();
+        self.debug_data.relocate(inferior.load_bias(self.debug_data.entry_point()));
+        if let Err(err) = inferior.load_libraries(&mut self.breakpoints) {
+            println!("Could not read the list of shared libraries: {}", err);
+        }
+        let rip = inferior.get_registers().map(|regs| regs.rip as usize).unwrap_or(0);
+        self.inferior = Some(inferior);
+        self.report_stop(rip);
+    }
+
+    /// The inferior, if it's a live process. Breakpoints can't be inserted into a core dump, so
+    /// they're only recorded until the program is run.
+    fn live_process(inferior: &mut Option<Inferior>) -> Option<&mut Inferior> {
+        inferior.as_mut().filter(|inferior| !inferior.is_core())
+    }
+
+    /// Gets rid of the inferior before quitting or starting the program again. A process we
+    /// attached to is detached from and left running; one we started is killed.
+    fn discard_inferior(&mut self) {
+        if self.inferior.as_ref().is_some_and(|inferior| inferior.is_attached()) {
+            self.detach();
+        } else if let Some(mut inferior) = self.inferior.take() {
+            // It's fine if the process already died on its own
+            match inferior.kill() {
+                Ok(()) | Err(nix::Error::ESRCH) => {}
+                Err(err) => println!("Could not kill process {}: {}", inferior.pid(), err),
+            }
+        }
+    }
+
+    /// Detaches from the inferior, removing all breakpoints so that it keeps running unharmed.
+    fn detach(&mut self) {
+        let inferior = match self.inferior.take() {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        if inferior.is_core() {
+            println!("No core file now.");
+            self.stop_line = None;
+            return;
+        }
+        let pid = inferior.pid();
+        match inferior.detach(self.breakpoints.saved_bytes()) {
+            Ok(()) => println!("Detached from process {}", pid),
+            Err(err) => println!("Error detaching from process {}: {}", pid, err),
         }
+        self.stop_line = None;
     }
 
     fn parse_address(addr: &str) -> Option<usize> {
         let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
             &addr[2..]
         } else {
-            &addr
+            addr
         };
         usize::from_str_radix(addr_without_0x, 16).ok()
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
+            let command = self.get_next_command();
+            match command {
                 DebuggerCommand::Run(args) => {
-                    if let Some(inferior) = &mut self.inferior {
-                        inferior.kill();
-                        self.inferior = None;
-                    }
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &self.breakpoints) {
+                    self.discard_inferior();
+                    if let Some(mut inferior) = Inferior::new(&self.target, &args) {
+                        inferior.follow_fork_mode = self.follow_fork_mode;
+                        inferior.signals = self.signals.clone();
+                        inferior.debug_file_directories = self.debug_file_directories.clone();
+                        self.prepare_inferior(&mut inferior, true);
                         // Create the inferior
                         self.inferior = Some(inferior);
                         // TODO (milestone 1): make the inferior run
@@ -75,80 +306,297 @@ impl Debugger<'_> {
                         println!("Error starting subprocess");
                     }
                 },
+                DebuggerCommand::Attach(pid) => {
+                    self.attach(pid);
+                }
+                DebuggerCommand::Detach => {
+                    self.detach();
+                }
+                DebuggerCommand::SetFollowForkMode(mode) => {
+                    self.follow_fork_mode = mode;
+                    if let Some(inferior) = &mut self.inferior {
+                        inferior.follow_fork_mode = mode;
+                    }
+                }
+                DebuggerCommand::SetDebugFileDirectory(dirs) => self.set_debug_file_directories(dirs),
+                DebuggerCommand::ShowDebugFileDirectory => {
+                    println!(
+                        "The directory where separate debug symbols are searched for is \"{}\".",
+                        self.debug_file_directories.join(":")
+                    );
+                }
+                DebuggerCommand::SymbolFile(path) => self.load_symbol_file(&path),
+                DebuggerCommand::InfoInferiors => {
+                    println!("  Num  Description       Executable");
+                    let description = match &self.inferior {
+                        Some(inferior) => format!("process {}", inferior.pid()),
+                        None => "<null>".to_string(),
+                    };
+                    println!("* {:<4} {:<17} {}", 1, description, self.target);
+                    println!("Follow-fork mode: {}", self.follow_fork_mode);
+                }
+                DebuggerCommand::InfoThreads => {
+                    self.print_threads();
+                }
+                DebuggerCommand::Thread(number) => {
+                    self.select_thread(number);
+                }
+                DebuggerCommand::InfoRegisters(names) => {
+                    self.print_registers(&names, false);
+                }
+                DebuggerCommand::InfoAllRegisters => {
+                    self.print_registers(&[], true);
+                }
+                DebuggerCommand::SetRegister(name, value) => {
+                    self.set_register(&name, &value);
+                }
+                DebuggerCommand::Examine(spec, address) => {
+                    self.examine(&spec, address.as_deref());
+                }
+                DebuggerCommand::Disassemble(raw, function) => {
+                    self.disassemble(raw, function.as_deref());
+                }
                 DebuggerCommand::Cont => {
                     self.inferior_cont();
                 }
                 DebuggerCommand::Quit => {
-                    if let Some(inferior) = &mut self.inferior {
-                        inferior.kill();
-                        self.inferior = None;
-                    }
+                    self.discard_inferior();
                     return;
                 },
                 DebuggerCommand::Backtrace => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_ref().unwrap().print_backtrace(&self.debug_data).unwrap();
-                    }
+                    self.print_backtrace();
                 },
-                DebuggerCommand::Breakpoint(location) => {
-                    let breakpoint_addr;
-                    if location.starts_with("*") {
-                        if let Some(address) = self.parse_address(&location[1..]) {
-                            breakpoint_addr = address;
-                        } else {
-                            println!("Invalid address!");
+                DebuggerCommand::Up(count) => {
+                    self.select_frame(Some(self.selected_frame + count), true);
+                }
+                DebuggerCommand::Down(count) => {
+                    if count > self.selected_frame {
+                        println!("Bottom (innermost) frame selected; you cannot go down.");
+                    } else {
+                        self.select_frame(Some(self.selected_frame - count), false);
+                    }
+                }
+                DebuggerCommand::Frame(number) => {
+                    self.select_frame(number, false);
+                }
+                DebuggerCommand::Breakpoint(location, condition) => {
+                    let condition = match condition.map(|text| Condition::parse(&text)).transpose() {
+                        Ok(condition) => condition,
+                        Err(err) => {
+                            println!("Invalid condition: {}", err);
+                            continue;
+                        }
+                    };
+                    let location = self.qualify_line(location);
+                    let breakpoint_addr = match self.resolve_location(&location) {
+                        Ok(addr) => addr,
+                        // The function may be defined by a library that is dlopened later, or by
+                        // the program the inferior execs
+                        Err(LocationError::NotFound(err)) => {
+                            println!("{}", err);
+                            let bp = self.breakpoints.add_pending(&location, condition);
+                            println!("Breakpoint {} ({}) pending.", bp.number, bp.spec);
                             continue;
                         }
-                    } else if let Some(line) = usize::from_str_radix(&location, 10).ok() {
-                        if let Some(addr) = self.debug_data.get_addr_for_line(None, line) {
-                            breakpoint_addr = addr;
-                        } else {
-                            println!("Invalid line number!");
+                        Err(err) => {
+                            println!("{}", err);
                             continue;
                         }
-                    } else if let Some(addr) = self.debug_data.get_addr_for_function(None, &location) {
-                        breakpoint_addr = addr;
+                    };
+                    match self.breakpoints.add(&location, breakpoint_addr, condition, Self::live_process(&mut self.inferior)) {
+                        Ok(bp) => println!("Set breakpoint {} at {:#x}", bp.number, breakpoint_addr),
+                        Err(_) => println!("Invalid breakpoint address {:#x}", breakpoint_addr),
+                    }
+                }
+                DebuggerCommand::Watch(expr, kind) => {
+                    let (addr, entity_type, scope) = match self.resolve_watch_expression(&expr) {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    match self.breakpoints.add_watchpoint(&expr, addr, entity_type, kind, scope, Self::live_process(&mut self.inferior)) {
+                        Ok(wp) => match kind {
+                            WatchKind::Write => println!("Hardware watchpoint {}: {}", wp.number, expr),
+                            WatchKind::Read => println!("Hardware read watchpoint {}: {}", wp.number, expr),
+                        },
+                        Err(err) => println!("Cannot watch {}: {}", expr, err),
+                    }
+                }
+                DebuggerCommand::Ignore(number, count) => {
+                    if !self.breakpoints.set_ignore_count(number, count) {
+                        println!("No breakpoint number {}.", number);
+                    } else if count == 0 {
+                        println!("Will stop next time breakpoint {} is reached.", number);
                     } else {
-                        println!("Usage: b|break|breakpoint *address|line|func");
+                        println!("Will ignore next {} crossings of breakpoint {}.", count, number);
+                    }
+                }
+                DebuggerCommand::InfoBreakpoints => {
+                    self.breakpoints.print(&self.debug_data);
+                }
+                DebuggerCommand::Delete(numbers) => {
+                    let numbers = if numbers.is_empty() { self.breakpoints.numbers() } else { numbers };
+                    for number in numbers {
+                        match self.breakpoints.delete(number, Self::live_process(&mut self.inferior)) {
+                            Ok(true) => {}
+                            Ok(false) => println!("No breakpoint number {}.", number),
+                            Err(err) => println!("Could not remove breakpoint {}: {}", number, err),
+                        }
+                    }
+                }
+                DebuggerCommand::Enable(ref numbers) | DebuggerCommand::Disable(ref numbers) => {
+                    let enabled = matches!(command, DebuggerCommand::Enable(_));
+                    let numbers = numbers.clone();
+                    let numbers = if numbers.is_empty() { self.breakpoints.numbers() } else { numbers };
+                    for number in numbers {
+                        match self.breakpoints.set_enabled(number, enabled, Self::live_process(&mut self.inferior)) {
+                            Ok(true) => {}
+                            Ok(false) => println!("No breakpoint number {}.", number),
+                            Err(err) => println!("Could not update breakpoint {}: {}", number, err),
+                        }
+                    }
+                }
+                DebuggerCommand::Step => {
+                    self.warn_no_line_info();
+                    self.inferior_resume(false, |inf, debug_data, breakpoints| {
+                        inf.step_line(debug_data, breakpoints, true)
+                    });
+                }
+                DebuggerCommand::Next => {
+                    self.warn_no_line_info();
+                    self.inferior_resume(false, |inf, debug_data, breakpoints| {
+                        inf.step_line(debug_data, breakpoints, false)
+                    });
+                }
+                DebuggerCommand::Finish => {
+                    let live = Self::live_process(&mut self.inferior).is_some();
+                    if live && self.inferior.as_ref().is_some_and(|inf| inf.in_outermost_frame(&self.debug_data).unwrap_or(false)) {
+                        println!("\"finish\" not meaningful in the outermost frame.");
                         continue;
                     }
-
-                    if self.inferior.is_some() {
-                        if let Some(val) = self.inferior.as_mut().unwrap().write_byte(breakpoint_addr, 0xcc).ok() {
-                            self.breakpoints.insert(breakpoint_addr, val);
-                            println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), breakpoint_addr);
-                        } else {
-                            println!("Invalid breakpoint address {:#x}", breakpoint_addr);
+                    if let Some(func) = self.current_function_name().filter(|_| live) {
+                        println!("Run till exit from {}", func);
+                    }
+                    self.inferior_resume(false, |inf, debug_data, breakpoints| inf.finish(debug_data, breakpoints));
+                }
+                DebuggerCommand::List(spec) => {
+                    self.list_source(spec.as_deref());
+                }
+                DebuggerCommand::Print(expr) => {
+                    self.print_expression(&expr);
+                }
+                DebuggerCommand::Ptype(expr) => {
+                    self.print_type(&expr);
+                }
+                DebuggerCommand::InfoLocals => {
+                    self.print_locals();
+                }
+                DebuggerCommand::Display(Some(expr)) => {
+                    self.add_display(&expr);
+                }
+                DebuggerCommand::Display(None) => {
+                    self.show_displays();
+                }
+                DebuggerCommand::Undisplay(numbers) => {
+                    if numbers.is_empty() {
+                        self.displays.clear();
+                    }
+                    for number in numbers {
+                        match self.displays.iter().position(|(n, _)| *n == number) {
+                            Some(index) => {
+                                self.displays.remove(index);
+                            }
+                            None => println!("No display number {}.", number),
                         }
-                    } else {
-                        println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), breakpoint_addr);
-                        self.breakpoints.insert(breakpoint_addr, 0);
                     }
                 }
+                DebuggerCommand::InfoDisplay => {
+                    self.print_displays();
+                }
+                DebuggerCommand::Handle(args) => {
+                    self.handle_signals(&args);
+                }
+                DebuggerCommand::InfoSignals(None) => {
+                    self.signals.print(&[]);
+                }
+                DebuggerCommand::InfoSignals(Some(name)) => match signals::parse_signal(&name) {
+                    Some(signal) => self.signals.print(&[signal]),
+                    None => println!("Only signals 1-15 are valid as numeric signals."),
+                },
+                DebuggerCommand::InfoSharedLibrary => match &self.inferior {
+                    Some(inferior) => inferior.libraries().print(),
+                    None => println!("No shared libraries loaded at this time."),
+                },
             }
         }
     }
 
     fn inferior_cont(&mut self) {
-        if let Some(inf) = &mut self.inferior {
-            match inf.cont() {
+        self.inferior_resume(true, |inf, debug_data, breakpoints| inf.cont(debug_data, breakpoints));
+    }
+
+    /// Resumes the inferior with `resume` and reports where it stopped. Breakpoint and
+    /// single-step traps are only announced as signals if `report_trap` is set.
+    fn inferior_resume<F>(&mut self, report_trap: bool, resume: F)
+    where
+        F: FnOnce(&mut Inferior, &DwarfData, &mut Breakpoints) -> Result<Status, nix::Error>,
+    {
+        if let Some(inf) = Self::live_process(&mut self.inferior) {
+            let thread = inf.current_thread().number;
+            let mut result = resume(inf, &self.debug_data, &mut self.breakpoints);
+            // An exec doesn't stop the program; we just pick up the new image and keep going.
+            while let Ok(Status::Exec) = result {
+                self.follow_exec();
+                let inf = self.inferior.as_mut().unwrap();
+                result = inf.cont(&self.debug_data, &mut self.breakpoints);
+            }
+            let hits = self.breakpoints.take_hits();
+            let watch_hits = self.breakpoints.take_watch_hits();
+            let scope_exits = self.breakpoints.take_scope_exits();
+            match result {
                 Ok(status) => {
                     match status {
                         Status::Exited(exit_code) => {
                             println!("Process exited with exit code: {}", exit_code);
                             self.inferior = None;
+                            self.stop_line = None;
+                            self.delete_scoped_watchpoints(self.breakpoints.scoped_watchpoints());
                         },
-                        Status::Stopped(signal, ip) => {
-                            println!("Process stopped by signal {} at 0x{:X}", signal, ip);
-                            let line = self.debug_data.get_line_from_addr(rip);
-                            let func = self.debug_data.get_function_from_addr(rip);
-                            if line.is_some() && func.is_some() {
-                                println!("Stopped at {} ({})", func.unwrap(), line.unwrap());
-                            } 
+                        Status::Stopped(signal, rip) => {
+                            let current = self.inferior.as_ref().unwrap().current_thread();
+                            if current.number != thread {
+                                println!("[Switching to thread {} ({})]", current.number, current.tid);
+                            }
+                            if report_trap || signal != Signal::SIGTRAP {
+                                println!("Process stopped by signal {} at {:#x}", signal, rip);
+                            }
+                            if signal == Signal::SIGTRAP {
+                                for number in hits {
+                                    println!("Hit breakpoint {}", number);
+                                }
+                                for hit in watch_hits {
+                                    match hit.kind {
+                                        WatchKind::Write => println!(
+                                            "Hardware watchpoint {}: {}\n\nOld value = {}\nNew value = {}\n",
+                                            hit.number, hit.expr, hit.old_value, hit.new_value
+                                        ),
+                                        WatchKind::Read => println!(
+                                            "Hardware read watchpoint {}: {}\n\nValue = {}\n",
+                                            hit.number, hit.expr, hit.new_value
+                                        ),
+                                    }
+                                }
+                                self.delete_scoped_watchpoints(scope_exits);
+                            }
+                            self.report_stop(rip);
+                            self.show_displays();
                         },
                         Status::Signaled(signal) => {
-                            println!("Process got a signal, {}", signal)
+                            println!("Process got a signal, {}", signal);
+                            self.inferior = None;
+                            self.stop_line = None;
+                            self.delete_scoped_watchpoints(self.breakpoints.scoped_watchpoints());
                         },
+                        Status::Exec => unreachable!(),
                     };
                 },
                 Err(err) => {
@@ -156,7 +604,854 @@ impl Debugger<'_> {
                 }
             }
         } else {
-            println!("Error continuing subprocess");
+            println!("The program is not being run.");
+        }
+    }
+
+    /// Deletes watchpoints whose frame is gone, as the program can't get back into it.
+    fn delete_scoped_watchpoints(&mut self, numbers: Vec<usize>) {
+        for number in numbers {
+            println!(
+                "\nWatchpoint {} deleted because the program has left the block in\nwhich its expression is valid.\n",
+                number
+            );
+            if let Err(err) = self.breakpoints.delete(number, Self::live_process(&mut self.inferior)) {
+                println!("Could not remove watchpoint {}: {}", number, err);
+            }
+        }
+    }
+
+    /// Loads the debugging symbols of the program the inferior just exec'd and re-inserts the
+    /// breakpoints into its new image.
+    fn follow_exec(&mut self) {
+        let mut inferior = self.inferior.take().unwrap();
+        let exe = format!("/proc/{}/exe", inferior.pid());
+        let target = match std::fs::read_link(&exe) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => exe.clone(),
+        };
+        println!("process {} is executing new program: {}", inferior.pid(), target);
+        let loaded = match Self::load_debug_data(&exe, &self.debug_file_directories) {
+            Ok(debug_data) => {
+                self.debug_data = debug_data;
+                true
+            }
+            Err(err) => {
+                println!("{}", err);
+                false
+            }
+        };
+        self.target = target;
+        self.prepare_inferior(&mut inferior, loaded);
+        self.inferior = Some(inferior);
+    }
+
+    /// Gets a program that has just started, exec'd or been attached to ready for debugging:
+    /// relocates its symbols to where it was loaded, inserts the breakpoints and watchpoints,
+    /// and catches up with the shared libraries that are already loaded. `symbols_loaded` is
+    /// false if the program's debug data couldn't be read, so none of it applies.
+    fn prepare_inferior(&mut self, inferior: &mut Inferior, symbols_loaded: bool) {
+        // None of the old process's frames are around anymore
+        self.delete_scoped_watchpoints(self.breakpoints.scoped_watchpoints());
+        if symbols_loaded {
+            self.debug_data.relocate(inferior.load_bias(self.debug_data.entry_point()));
+        }
+        let debug_data = &self.debug_data;
+        let resolve = |spec: &str| {
+            if symbols_loaded {
+                Self::lookup_location(debug_data, spec, None).ok()
+            } else {
+                None
+            }
+        };
+        if let Err(err) = self.breakpoints.reset(resolve, inferior) {
+            println!("Could not insert breakpoints: {}", err);
+        }
+        if let Err(err) = inferior.load_libraries(&mut self.breakpoints) {
+            println!("Could not read the list of shared libraries: {}", err);
+        }
+    }
+
+    /// Lists the threads of the inferior and where each of them is, marking the selected one.
+    fn print_threads(&self) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("No threads.");
+                return;
+            }
+        };
+        println!("  Id   Target Id         Frame");
+        let current = inferior.current_thread().tid;
+        for thread in inferior.threads() {
+            let marker = if thread.tid == current { "*" } else { " " };
+            let rip = match inferior.get_thread_registers(thread.tid) {
+                Ok(regs) => regs.rip as usize,
+                Err(err) => {
+                    println!("{} {:<4} Thread {:<10} <unavailable: {}>", marker, thread.number, thread.tid, err);
+                    continue;
+                }
+            };
+            let frame = match (self.function_name(rip), self.source_line(rip)) {
+                (Some(func), Some(line)) => format!("{} ({})", func, line),
+                (Some(func), None) => format!("{} ({:#x})", func, rip),
+                (None, _) => format!("{:#x}", rip),
+            };
+            println!("{} {:<4} Thread {:<10} {}", marker, thread.number, thread.tid, frame);
+        }
+    }
+
+    /// Makes thread `number` the one whose registers and stack are used from now on.
+    fn select_thread(&mut self, number: usize) {
+        let inferior = match &mut self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        if !inferior.select_thread(number) {
+            println!("Invalid thread ID: {}", number);
+            return;
+        }
+        let tid = inferior.current_thread().tid;
+        let rip = inferior.get_registers().map(|regs| regs.rip as usize).unwrap_or(0);
+        println!("[Switching to thread {} ({})]", number, tid);
+        self.report_stop(rip);
+    }
+
+    /// Prints the function and source line the inferior stopped at, with a few lines of context.
+    fn report_stop(&mut self, rip: usize) {
+        let line = self.source_line(rip);
+        let func = self.function_name(rip);
+        match (&func, &line) {
+            (Some(func), Some(line)) => println!("Stopped at {} ({})", func, line),
+            (Some(func), None) => println!("Stopped at {} ({:#x})", func, rip),
+            (None, _) => println!("Stopped at {:#x}", rip),
+        }
+        if let Some(line) = &line {
+            self.source_files.print_lines(
+                &line.file,
+                line.number.saturating_sub(2),
+                line.number + 2,
+                Some(line.number),
+            );
+        }
+        self.stop_line = line;
+        self.list_position = None;
+        self.selected_frame = 0;
+    }
+
+    /// Resolves a breakpoint location (`*address` or `[file:]line|function`) to an address.
+    /// Locations may also be in loaded shared libraries.
+    fn resolve_location(&self, location: &str) -> Result<usize, LocationError> {
+        let default_file = self.default_source_file();
+        match Self::lookup_location(&self.debug_data, location, default_file.as_deref()) {
+            Err(LocationError::NotFound(err)) => self
+                .inferior
+                .as_ref()
+                .and_then(|inferior| inferior.libraries().location_address(location))
+                .ok_or(LocationError::NotFound(err)),
+            result => result,
+        }
+    }
+
+    /// The file that bare line numbers refer to: the one last listed or stopped in. Without
+    /// either, it's the file that defines `main`.
+    fn default_source_file(&self) -> Option<String> {
+        self.list_position
+            .as_ref()
+            .or(self.stop_line.as_ref())
+            .map(|line| line.file.clone())
+    }
+
+    /// Qualifies a bare line number with the file it refers to now, so that it still means the
+    /// same line when the breakpoint is re-set for the next run.
+    fn qualify_line(&self, location: String) -> String {
+        let file = match self.default_source_file() {
+            Some(file) if location.parse::<usize>().is_ok() => file,
+            _ => return location,
+        };
+        // The file name alone is enough unless other files have the same one
+        if let Some(name) = Path::new(&file).file_name() {
+            let short = format!("{}:{}", name.to_string_lossy(), location);
+            let unique = self.debug_data.get_locations(&short, None).is_ok_and(|locations| locations.len() == 1);
+            if unique {
+                return short;
+            }
+        }
+        format!("{}:{}", file, location)
+    }
+
+    /// The shared library whose code contains `addr`, if the program isn't there.
+    fn library_at(&self, addr: usize) -> Option<&SharedLibrary> {
+        self.inferior.as_ref()?.libraries().containing(addr)
+    }
+
+    /// Names the function containing `addr`, in the program or one of its shared libraries.
+    fn function_name(&self, addr: usize) -> Option<String> {
+        self.debug_data
+            .get_function_from_addr(addr)
+            .or_else(|| self.library_at(addr)?.function_name(addr))
+    }
+
+    /// The source line at `addr`, in the program or one of its shared libraries.
+    fn source_line(&self, addr: usize) -> Option<Line> {
+        self.debug_data
+            .get_line_from_addr(addr)
+            .or_else(|| self.library_at(addr)?.debug_data.get_line_from_addr(addr))
+    }
+
+    fn lookup_location(debug_data: &DwarfData, location: &str, default_file: Option<&str>) -> Result<usize, LocationError> {
+        if let Some(address) = location.strip_prefix('*') {
+            return Self::parse_address(address).ok_or_else(|| LocationError::Invalid("Invalid address!".to_string()));
+        }
+        let name = location.rsplit(':').next().unwrap_or(location);
+        if name.parse::<usize>().is_err() && !Self::is_function_name(name) {
+            return Err(LocationError::Invalid("Usage: b|break|breakpoint *address|[file:]line|[file:]func".to_string()));
+        }
+        let locations = debug_data.get_locations(location, default_file)?;
+        if let [line] = &locations[..] {
+            return Ok(line.address);
+        }
+        let mut message = format!("\"{}\" matches {} locations:", location, locations.len());
+        for line in locations.iter() {
+            message.push_str(&format!("\n  {:#x} at {}", line.address, line));
+        }
+        message.push_str("\nUse file:line or file:function to pick one.");
+        Err(LocationError::Invalid(message))
+    }
+
+    /// Returns true if a breakpoint location names a function, rather than an address or line.
+    fn is_function_name(location: &str) -> bool {
+        let mut chars = location.chars();
+        chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    /// Resolves the operand of `watch`/`rwatch`: an expression for an object in memory such as
+    /// `p->count`, evaluated in the selected frame, or else `*address`, watched as a 64-bit word.
+    fn resolve_watch_expression(&self, expr: &str) -> Option<(usize, Type, Option<WatchScope>)> {
+        let object = self.evaluate(expr).and_then(|value| match value.address() {
+            Some(addr) => Ok((addr, value.ty, self.watch_scope(expr, addr))),
+            None => Err(format!("Cannot watch constant value `{}'.", expr)),
+        });
+        let address = expr.strip_prefix("*").and_then(Self::parse_address);
+        match (object, address) {
+            (Ok(object), _) => Some(object),
+            (Err(_), Some(addr)) => Some((addr, Type::with_encoding("long".to_string(), 8, Encoding::Pointer), None)),
+            (Err(err), None) => {
+                println!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Finds the frame a watched expression belongs to: if it doesn't come out the same
+    /// without the selected frame, it refers to that frame's locals. None for expressions on
+    /// globals, and for the outermost frame, which never returns.
+    fn watch_scope(&self, expr: &str, addr: usize) -> Option<WatchScope> {
+        let globals = Scope {
+            frame: None,
+            caller: None,
+            ..self.scope()
+        };
+        let global_addr = Expression::parse(expr).and_then(|expr| expr.evaluate(&globals));
+        if global_addr.is_ok_and(|value| value.address() == Some(addr)) {
+            return None;
+        }
+        let inferior = self.inferior.as_ref()?;
+        let (frame, caller) = self.selected_frames(inferior)?;
+        Some(WatchScope {
+            tid: inferior.current_thread().tid,
+            cfa: frame.cfa?,
+            ret_addr: caller?.rip,
+        })
+    }
+
+    /// Implements `list [file:]line|function`. Without an argument, continues where the last
+    /// listing stopped, or shows the lines around the current stop location (or main).
+    fn list_source(&mut self, spec: Option<&str>) {
+        let center = match spec {
+            None => {
+                if let Some(position) = self.list_position.take() {
+                    self.list_lines(&position.file, position.number);
+                    return;
+                }
+                match self.stop_line.clone().or_else(|| self.function_line(None, "main")) {
+                    Some(line) => line,
+                    None => {
+                        println!("No source information available.");
+                        return;
+                    }
+                }
+            }
+            Some(spec) => {
+                let (file, location) = match spec.rsplit_once(':') {
+                    Some((file, location)) => (Some(file), location),
+                    None => (None, spec),
+                };
+                if let Ok(number) = location.parse::<usize>() {
+                    let file = match file {
+                        Some(file) => self.debug_data.get_file_path(file),
+                        None => self
+                            .default_source_file()
+                            .or_else(|| self.function_line(None, "main").map(|line| line.file)),
+                    };
+                    match file {
+                        Some(file) => Line { file, number, address: 0 },
+                        None => {
+                            println!("No source file named {}.", spec);
+                            return;
+                        }
+                    }
+                } else {
+                    match self.function_line(file, location) {
+                        Some(line) => line,
+                        None => {
+                            println!("Function \"{}\" not defined.", spec);
+                            return;
+                        }
+                    }
+                }
+            }
+        };
+        let first = center.number.saturating_sub(LIST_WINDOW / 2).max(1);
+        self.list_lines(&center.file, first);
+    }
+
+    /// Prints a window of lines starting at `first` and remembers where to continue.
+    fn list_lines(&mut self, file: &str, first: usize) {
+        let current = self
+            .stop_line
+            .as_ref()
+            .filter(|line| line.file == file)
+            .map(|line| line.number);
+        if let Some(last) = self
+            .source_files
+            .print_lines(file, first, first + LIST_WINDOW - 1, current)
+        {
+            self.list_position = Some(Line {
+                file: file.to_string(),
+                number: last + 1,
+                address: 0,
+            });
+        }
+    }
+
+    /// Returns the source line at which `func_name` begins.
+    fn function_line(&self, file: Option<&str>, func_name: &str) -> Option<Line> {
+        let addr = self.debug_data.get_addr_for_function(file, func_name)?;
+        self.debug_data.get_line_from_addr(addr)
+    }
+
+    /// Tells the user that `step`/`next` will run to the end of the current function, since
+    /// there are no lines to step through.
+    fn warn_no_line_info(&self) {
+        let rip = match self.inferior.as_ref().filter(|inferior| !inferior.is_core()).map(|inferior| inferior.get_registers()) {
+            Some(Ok(regs)) => regs.rip as usize,
+            _ => return,
+        };
+        if self.source_line(rip).is_none() {
+            match self.function_name(rip) {
+                Some(name) => println!("Single stepping until exit from function {},", name),
+                None => println!("Single stepping until exit from the current function,"),
+            }
+            println!("which has no line number information.");
+        }
+    }
+
+    fn current_function_name(&self) -> Option<String> {
+        let regs = self.inferior.as_ref()?.get_registers().ok()?;
+        self.function_name(regs.rip as usize)
+    }
+
+    /// Returns the selected frame and its caller, if it has one. Locations of variables can
+    /// depend on the caller, for the values parameters were passed.
+    fn selected_frames(&self, inferior: &Inferior) -> Option<(Frame, Option<Frame>)> {
+        let frames = inferior.frames(&self.debug_data, self.selected_frame + 2).ok()?;
+        let mut frames = frames.into_iter().skip(self.selected_frame);
+        Some((frames.next()?, frames.next()))
+    }
+
+    /// Implements `backtrace`, numbering the frames and showing their arguments.
+    fn print_backtrace(&self) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("No stack.");
+                return;
+            }
+        };
+        match inferior.backtrace(&self.debug_data) {
+            Ok(frames) => {
+                for number in 0..frames.len() {
+                    println!("{}", self.describe_frame(inferior, &frames, number));
+                }
+            }
+            Err(err) => println!("Could not unwind the stack: {}", err),
+        }
+    }
+
+    /// Formats a backtrace line: `#1  0x401189 in func1 (a=1) at file.c:18`. The address is
+    /// left out for the innermost frame, since that's where the program is stopped.
+    fn describe_frame(&self, inferior: &Inferior, frames: &[Frame], number: usize) -> String {
+        let frame = &frames[number];
+        let func = match self.debug_data.get_function_containing(frame.pc) {
+            Some(func) => func,
+            None => {
+                let library = self.library_at(frame.pc);
+                let name = self.function_name(frame.pc).unwrap_or("??".to_string());
+                let from = library.map(|lib| format!(" from {}", lib.path)).unwrap_or_default();
+                return format!("#{:<2} {:#018x} in {} (){}", number, frame.rip, name, from);
+            }
+        };
+        let args: Vec<String> = func
+            .variables
+            .iter()
+            .filter(|var| var.is_parameter)
+            .map(|var| {
+                // Like gdb, only scalars are shown in full
+                let value = match self.debug_data.strip_typedefs(&var.entity_type).kind {
+                    TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => "...".to_string(),
+                    _ => self.format_variable(inferior, var, frame, frames.get(number + 1)),
+                };
+                format!("{}={}", var.name, value)
+            })
+            .collect();
+        let address = if number == 0 { String::new() } else { format!("{:#018x} in ", frame.rip) };
+        match self.debug_data.get_line_from_addr(frame.pc) {
+            Some(line) => format!("#{:<2} {}{} ({}) at {}", number, address, func.name, args.join(", "), line),
+            None => format!("#{:<2} {}{} ({})", number, address, func.name, args.join(", ")),
+        }
+    }
+
+    /// Implements `frame [N]`, `up` and `down`: selects frame `number` and shows where it is.
+    /// Without a number, shows the selected frame. `up` sets `clamp` to stop at the outermost
+    /// frame rather than fail.
+    fn select_frame(&mut self, number: Option<usize>, clamp: bool) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("No stack.");
+                return;
+            }
+        };
+        let frames = match inferior.backtrace(&self.debug_data) {
+            Ok(frames) => frames,
+            Err(err) => {
+                println!("Could not unwind the stack: {}", err);
+                return;
+            }
+        };
+        let mut number = number.unwrap_or(self.selected_frame);
+        if clamp && number >= frames.len() {
+            if self.selected_frame + 1 >= frames.len() {
+                println!("Initial frame selected; you cannot go up.");
+                return;
+            }
+            number = frames.len() - 1;
+        }
+        let frame = match frames.get(number) {
+            Some(frame) => frame,
+            None => {
+                println!("No frame at level {}.", number);
+                return;
+            }
+        };
+        println!("{}", self.describe_frame(inferior, &frames, number));
+        let line = self.debug_data.get_line_from_addr(frame.pc);
+        if let Some(line) = &line {
+            self.source_files.print_lines(
+                &line.file,
+                line.number.saturating_sub(2),
+                line.number + 2,
+                Some(line.number),
+            );
+        }
+        self.selected_frame = number;
+        self.stop_line = line;
+        self.list_position = None;
+    }
+
+    /// Reads a variable from the inferior and formats it according to its DWARF type.
+    fn format_variable(&self, inferior: &Inferior, var: &Variable, frame: &Frame, caller: Option<&Frame>) -> String {
+        let scope = Scope {
+            debug_data: &self.debug_data,
+            inferior: Some(inferior),
+            frame: Some(frame.clone()),
+            caller: caller.cloned(),
+        };
+        match scope.variable(var).and_then(|value| scope.format(&value)) {
+            Ok(formatted) => formatted,
+            Err(err) => format!("<error: {}>", err),
+        }
+    }
+
+    /// The context expressions are evaluated in: the selected frame of the inferior, if there is
+    /// one, or else just the global variables.
+    fn scope(&self) -> Scope<'_> {
+        let inferior = self.inferior.as_ref();
+        let (frame, caller) = match inferior.and_then(|inferior| self.selected_frames(inferior)) {
+            Some((frame, caller)) => (Some(frame), caller),
+            None => (None, None),
+        };
+        Scope {
+            debug_data: &self.debug_data,
+            inferior,
+            frame,
+            caller,
+        }
+    }
+
+    fn evaluate(&self, expr: &str) -> Result<Value, String> {
+        Expression::parse(expr)?.evaluate(&self.scope())
+    }
+
+    fn print_expression(&self, expr: &str) {
+        let scope = self.scope();
+        let value = Expression::parse(expr).and_then(|expr| expr.evaluate(&scope));
+        match value.and_then(|value| Ok((scope.format(&value)?, value))) {
+            Ok((formatted, value)) => println!("{} = ({}) {}", expr.trim(), value.ty.name, formatted),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Implements `ptype`: shows the declaration of the type of an expression, or of a type
+    /// given by name.
+    fn print_type(&self, expr: &str) {
+        let scope = self.scope();
+        let entity_type = match Expression::parse(expr) {
+            Ok(parsed) => parsed
+                .evaluate(&scope)
+                .map(|value| value.ty)
+                .or_else(|err| scope.parse_type(expr).map_err(|_| err)),
+            // Only complain about the type if it parsed as one
+            Err(err) => scope
+                .parse_type(expr)
+                .map_err(|type_err| if type_err.starts_with("No ") { type_err } else { err }),
+        };
+        match entity_type {
+            Ok(entity_type) => println!("type = {}", self.debug_data.type_declaration(&entity_type)),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Implements `x/Nfu addr`.
+    fn examine(&mut self, spec: &str, address: Option<&str>) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let fmt = match ExamineFormat::parse(spec, self.examine_format) {
+            Ok(fmt) => fmt,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let addr = match address {
+            Some(expr) => match self.resolve_address(expr) {
+                Ok(addr) => addr,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+            None => match self.examine_next {
+                Some(addr) => addr,
+                None => {
+                    println!("Argument required (starting display address).");
+                    return;
+                }
+            },
+        };
+        let read = |addr, len| self.read_memory(inferior, addr, len);
+        let pc = inferior.get_registers().ok().map(|regs| regs.rip as usize);
+        match examine::examine(&fmt, addr, read, &self.debug_data, pc) {
+            Ok(next) => self.examine_next = Some(next),
+            Err(err) => println!("{}", err),
+        }
+        self.examine_format = fmt;
+    }
+
+    /// Implements `disassemble [/r] [function]`, defaulting to the function the current thread
+    /// is stopped in. `=>` marks the current instruction and `B` the instructions we've put a
+    /// breakpoint into; what's decoded (and shown by `/r`) is the original code, not the `0xcc`.
+    fn disassemble(&self, raw: bool, function: Option<&str>) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let rip = inferior.get_registers().ok().map(|regs| regs.rip as usize);
+        let addr = match function {
+            Some(expr) => match self.resolve_address(expr) {
+                Ok(addr) => Some(addr),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+            None => rip,
+        };
+        // Code without debug info can still be found through the symbol table
+        let func = addr.and_then(|addr| match self.debug_data.get_function_containing(addr) {
+            Some(func) => Some((func.name.clone(), func.address, func.text_length)),
+            None => self
+                .debug_data
+                .get_symbol_containing(addr)
+                .filter(|symbol| symbol.size > 0)
+                .map(|symbol| (symbol.name.clone(), symbol.address, symbol.size)),
+        });
+        let (name, start, length) = match func {
+            Some(func) => func,
+            None => {
+                println!("No function contains specified address.");
+                return;
+            }
+        };
+        let bytes = match self.read_memory(inferior, start, length) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                println!("Cannot access memory at address {:#x}", start);
+                return;
+            }
+        };
+        println!("Dump of assembler code for function {}:", name);
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let addr = start + offset;
+            let instruction =
+                disassembler::decode(&bytes[offset..], addr, |addr| self.debug_data.describe_address(addr));
+            let end = (offset + instruction.len).min(bytes.len());
+            let breakpoint = self
+                .breakpoints
+                .saved_bytes()
+                .keys()
+                .any(|bp_addr| (addr..start + end).contains(bp_addr));
+            let marker = match (breakpoint, rip == Some(addr)) {
+                (true, true) => "B=> ",
+                (false, true) => " => ",
+                (true, false) => "B   ",
+                (false, false) => "    ",
+            };
+            let raw_bytes = if raw {
+                let hex: Vec<String> = bytes[offset..end].iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("{}\t", hex.join(" "))
+            } else {
+                String::new()
+            };
+            println!("{}{:#018x} <+{}>:\t{}{}", marker, addr, offset, raw_bytes, instruction.text);
+            offset = end;
+        }
+        println!("End of assembler dump.");
+    }
+
+    /// Reads inferior memory as the program sees it, i.e. with the original bytes in place of
+    /// our breakpoints.
+    fn read_memory(&self, inferior: &Inferior, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = inferior.read_bytes(addr, len)?;
+        if inferior.is_core() {
+            return Ok(bytes);
+        }
+        for (bp_addr, orig) in self.breakpoints.saved_bytes() {
+            if *bp_addr >= addr && *bp_addr < addr + len {
+                bytes[*bp_addr - addr] = *orig;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Evaluates the address operand of `x` and `disassemble`: any expression, where pointers
+    /// and numbers give the address directly and arrays and functions stand for their own.
+    fn resolve_address(&self, expr: &str) -> Result<usize, String> {
+        let scope = self.scope();
+        let value = Expression::parse(expr)?.evaluate(&scope)?;
+        scope.to_address(&value)
+    }
+
+    /// Prints the registers called `names`, or all general-purpose registers (plus the x87 and
+    /// SSE ones if `all` is set) if no names are given.
+    fn print_registers(&self, names: &[String], all: bool) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let regs = inferior.get_registers();
+        let fpregs = inferior.get_fp_registers();
+        let (regs, fpregs) = match (regs, fpregs) {
+            (Ok(regs), Ok(fpregs)) => (regs, fpregs),
+            (Err(err), _) | (_, Err(err)) => {
+                println!("Could not read registers of the inferior: {}", err);
+                return;
+            }
+        };
+        if names.is_empty() {
+            registers::print_general_registers(&regs, &self.debug_data);
+            if all {
+                registers::print_fp_registers(&fpregs);
+            }
+        }
+        for name in names {
+            if !registers::print_register(name, &regs, &fpregs, &self.debug_data) {
+                println!("Invalid register `{}'", name);
+                return;
+            }
+        }
+    }
+
+    fn set_register(&mut self, name: &str, value: &str) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let result = inferior.get_registers().and_then(|regs| Ok((regs, inferior.get_fp_registers()?)));
+        let (mut regs, mut fpregs) = match result {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Could not read registers of the inferior: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = registers::set_register(name, value, &mut regs, &mut fpregs) {
+            println!("{}", err);
+            return;
+        }
+        let result = inferior.set_registers(regs).and_then(|_| inferior.set_fp_registers(&fpregs));
+        if let Err(err) = result {
+            println!("Could not write registers of the inferior: {}", err);
+            return;
+        }
+        // Moving %rip changes where we are
+        if name == "rip" || name == "pc" {
+            self.stop_line = self.debug_data.get_line_from_addr(regs.rip as usize);
+        }
+    }
+
+    /// Implements `display <expr>`. The expression is shown right away if the program is running.
+    fn add_display(&mut self, expr: &str) {
+        let expression = match Expression::parse(expr) {
+            Ok(expression) => expression,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let number = self.next_display_number;
+        self.next_display_number += 1;
+        if self.inferior.is_some() {
+            self.show_display(&self.scope(), number, &expression);
+        }
+        self.displays.push((number, expression));
+    }
+
+    /// Re-evaluates and prints every display expression, e.g. after the program stops.
+    fn show_displays(&self) {
+        if self.displays.is_empty() || self.inferior.is_none() {
+            return;
+        }
+        let scope = self.scope();
+        for (number, expression) in &self.displays {
+            self.show_display(&scope, *number, expression);
+        }
+    }
+
+    fn show_display(&self, scope: &Scope, number: usize, expression: &Expression) {
+        let value = expression.evaluate(scope);
+        match value.and_then(|value| Ok((scope.format(&value)?, value))) {
+            Ok((formatted, value)) => println!("{}: {} = ({}) {}", number, expression, value.ty.name, formatted),
+            Err(err) => println!("{}: {} = <error: {}>", number, expression, err),
+        }
+    }
+
+    /// Implements `info display`.
+    fn print_displays(&self) {
+        if self.displays.is_empty() {
+            println!("There are no auto-display expressions now.");
+            return;
+        }
+        println!("Auto-display expressions now in effect:");
+        println!("Num Expression");
+        for (number, expression) in &self.displays {
+            println!("{:<3} {}", format!("{}:", number), expression);
+        }
+    }
+
+    /// Implements `handle SIG... [keyword...]`: applies the keywords to each signal and shows
+    /// the resulting policies. `all` stands for every signal but the ones the debugger uses.
+    fn handle_signals(&mut self, args: &[String]) {
+        let mut selected = Vec::new();
+        let mut keywords = Vec::new();
+        for arg in args {
+            if arg == "all" {
+                selected.extend(
+                    Signal::iterator().filter(|signal| *signal != Signal::SIGTRAP && *signal != Signal::SIGINT),
+                );
+            } else if let Some(signal) = signals::parse_signal(arg) {
+                selected.push(signal);
+            } else {
+                keywords.push(arg.as_str());
+            }
+        }
+        if selected.is_empty() {
+            println!("Argument required (signal and action to apply).");
+            return;
+        }
+        let mut table = self.signals.clone();
+        for signal in selected.iter() {
+            if *signal == Signal::SIGTRAP && !keywords.is_empty() {
+                println!("SIGTRAP is used by the debugger.");
+                continue;
+            }
+            for keyword in keywords.iter() {
+                if let Err(err) = table.apply(*signal, keyword) {
+                    println!("{}", err);
+                    return;
+                }
+            }
+        }
+        self.signals = table;
+        if let Some(inferior) = &mut self.inferior {
+            inferior.signals = self.signals.clone();
+        }
+        self.signals.print(&selected);
+    }
+
+    fn print_locals(&self) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let (frame, caller) = match self.selected_frames(inferior) {
+            Some(frames) => frames,
+            None => {
+                println!("Could not read registers of the inferior");
+                return;
+            }
+        };
+        match self.debug_data.get_function_containing(frame.pc) {
+            Some(func) if !func.variables.is_empty() => {
+                for var in &func.variables {
+                    println!("{} = {}", var.name, self.format_variable(inferior, var, &frame, caller.as_ref()));
+                }
+            }
+            Some(_) => println!("No locals."),
+            None => println!("No symbol table info available."),
         }
     }
 
@@ -180,10 +1475,10 @@ impl Debugger<'_> {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
-                    self.readline.add_history_entry(line.as_str());
+                    let _ = self.readline.add_history_entry(line.as_str());
                     if let Err(err) = self.readline.save_history(&self.history_path) {
                         println!(
                             "Warning: failed to save history file at {}: {}",
@@ -201,3 +1496,4 @@ impl Debugger<'_> {
         }
     }
 }
+
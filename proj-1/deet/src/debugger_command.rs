@@ -1,9 +1,61 @@
+use crate::inferior::FollowForkMode;
+use crate::watchpoint::WatchKind;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Cont,
+    Attach(i32),
+    Detach,
+    SetFollowForkMode(FollowForkMode),
+    /// `set debug-file-directory DIR[:DIR...]`
+    SetDebugFileDirectory(Vec<String>),
+    ShowDebugFileDirectory,
+    /// `symbol-file FILE`: reads the program's debug info from FILE.
+    SymbolFile(String),
+    InfoInferiors,
+    InfoThreads,
+    /// `info registers [name...]`; all general-purpose registers if no names are given.
+    InfoRegisters(Vec<String>),
+    InfoAllRegisters,
+    /// `set $name = value`
+    SetRegister(String, String),
+    /// `x/Nfu addr`: the `Nfu` part (possibly empty) and the address, if given.
+    Examine(String, Option<String>),
+    /// `disassemble [/r] [function]`: whether to show raw bytes, and the function to show.
+    Disassemble(bool, Option<String>),
+    Thread(usize),
     Backtrace,
-    Breakpoint<String>
+    Up(usize),
+    Down(usize),
+    /// `frame [N]`; shows the selected frame if no number is given.
+    Frame(Option<usize>),
+    /// A location and an optional condition (`break <loc> if <expr>`).
+    Breakpoint(String, Option<String>),
+    Ignore(usize, usize),
+    Watch(String, WatchKind),
+    InfoBreakpoints,
+    Delete(Vec<usize>),
+    Disable(Vec<usize>),
+    Enable(Vec<usize>),
+    Step,
+    Next,
+    Finish,
+    List(Option<String>),
+    Print(String),
+    /// `ptype <expr|type>`
+    Ptype(String),
+    InfoLocals,
+    /// `display [expr]`; shows all displays again if no expression is given.
+    Display(Option<String>),
+    /// `undisplay [N...]`; removes all displays if no numbers are given.
+    Undisplay(Vec<usize>),
+    InfoDisplay,
+    /// `handle SIG... [keyword...]`: the signals and keywords as typed.
+    Handle(Vec<String>),
+    /// `info signals [SIG]`
+    InfoSignals(Option<String>),
+    InfoSharedLibrary,
 }
 
 impl DebuggerCommand {
@@ -16,17 +68,146 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             },
+            "attach" => {
+                Some(DebuggerCommand::Attach(tokens.get(1)?.parse().ok()?))
+            },
+            "detach" => {
+                Some(DebuggerCommand::Detach)
+            },
+            "set" => {
+                if let Some(assignment) = tokens[1..].join(" ").strip_prefix('$') {
+                    let (name, value) = assignment.split_once('=')?;
+                    return Some(DebuggerCommand::SetRegister(name.trim().to_string(), value.trim().to_string()));
+                }
+                match (*tokens.get(1)?, *tokens.get(2)?) {
+                    ("follow-fork-mode", "parent") => Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Parent)),
+                    ("follow-fork-mode", "child") => Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Child)),
+                    ("debug-file-directory", dirs) => Some(DebuggerCommand::SetDebugFileDirectory(
+                        dirs.split(':').filter(|dir| !dir.is_empty()).map(|dir| dir.to_string()).collect(),
+                    )),
+                    _ => None,
+                }
+            },
+            "show" => {
+                match *tokens.get(1)? {
+                    "debug-file-directory" => Some(DebuggerCommand::ShowDebugFileDirectory),
+                    _ => None,
+                }
+            },
+            "symbol-file" => {
+                Some(DebuggerCommand::SymbolFile(tokens.get(1)?.to_string()))
+            },
+            "thread" => {
+                Some(DebuggerCommand::Thread(tokens.get(1)?.parse().ok()?))
+            },
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                let address = if tokens.len() > 1 { Some(tokens[1..].join(" ")) } else { None };
+                Some(DebuggerCommand::Examine(cmd.trim_start_matches('x').trim_start_matches('/').to_string(), address))
+            },
+            "disas" | "disassemble" => {
+                let raw = tokens.get(1) == Some(&"/r");
+                let first = if raw { 2 } else { 1 };
+                let function = if tokens.len() > first { Some(tokens[first..].join(" ")) } else { None };
+                Some(DebuggerCommand::Disassemble(raw, function))
+            },
             "c" | "cont" | "continue" => {
                 Some(DebuggerCommand::Cont)
             },
             "bt" | "back" | "backtrace" => {
                 Some(DebuggerCommand::Backtrace)
             },
+            "up" => {
+                Some(DebuggerCommand::Up(tokens.get(1).map_or(Some(1), |count| count.parse().ok())?))
+            },
+            "down" => {
+                Some(DebuggerCommand::Down(tokens.get(1).map_or(Some(1), |count| count.parse().ok())?))
+            },
+            "f" | "frame" => {
+                match tokens.get(1) {
+                    Some(number) => Some(DebuggerCommand::Frame(Some(number.parse().ok()?))),
+                    None => Some(DebuggerCommand::Frame(None)),
+                }
+            },
             "b" | "break" => {
-                Some(DebuggerCommand::Breakpoint(tokens[1].to_string()))
+                let condition = match tokens.get(2) {
+                    Some(&"if") if tokens.len() > 3 => Some(tokens[3..].join(" ")),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::Breakpoint(tokens.get(1)?.to_string(), condition))
+            },
+            "watch" => {
+                Some(DebuggerCommand::Watch(tokens.get(1)?.to_string(), WatchKind::Write))
+            },
+            "rwatch" => {
+                Some(DebuggerCommand::Watch(tokens.get(1)?.to_string(), WatchKind::Read))
+            },
+            "ignore" => {
+                Some(DebuggerCommand::Ignore(tokens.get(1)?.parse().ok()?, tokens.get(2)?.parse().ok()?))
+            },
+            "d" | "delete" => {
+                Some(DebuggerCommand::Delete(parse_numbers(&tokens[1..])?))
+            },
+            "disable" => {
+                Some(DebuggerCommand::Disable(parse_numbers(&tokens[1..])?))
+            },
+            "enable" => {
+                Some(DebuggerCommand::Enable(parse_numbers(&tokens[1..])?))
+            },
+            "s" | "step" => {
+                Some(DebuggerCommand::Step)
+            },
+            "n" | "next" => {
+                Some(DebuggerCommand::Next)
+            },
+            "fin" | "finish" => {
+                Some(DebuggerCommand::Finish)
+            },
+            "l" | "list" => {
+                Some(DebuggerCommand::List(tokens.get(1).map(|s| s.to_string())))
+            },
+            "p" | "print" => {
+                tokens.get(1)?;
+                Some(DebuggerCommand::Print(tokens[1..].join(" ")))
+            },
+            "ptype" => {
+                tokens.get(1)?;
+                Some(DebuggerCommand::Ptype(tokens[1..].join(" ")))
+            },
+            "display" => {
+                let expr = if tokens.len() > 1 { Some(tokens[1..].join(" ")) } else { None };
+                Some(DebuggerCommand::Display(expr))
+            },
+            "handle" => {
+                tokens.get(1)?;
+                Some(DebuggerCommand::Handle(tokens[1..].iter().map(|s| s.to_string()).collect()))
+            },
+            "undisplay" => {
+                Some(DebuggerCommand::Undisplay(parse_numbers(&tokens[1..])?))
+            },
+            "i" | "info" => {
+                match *tokens.get(1)? {
+                    "locals" => Some(DebuggerCommand::InfoLocals),
+                    "inferiors" => Some(DebuggerCommand::InfoInferiors),
+                    "threads" => Some(DebuggerCommand::InfoThreads),
+                    "r" | "registers" => Some(DebuggerCommand::InfoRegisters(
+                        tokens[2..].iter().map(|name| name.trim_start_matches('$').to_string()).collect(),
+                    )),
+                    "all-registers" => Some(DebuggerCommand::InfoAllRegisters),
+                    "b" | "break" | "breakpoints" => Some(DebuggerCommand::InfoBreakpoints),
+                    "display" => Some(DebuggerCommand::InfoDisplay),
+                    "signals" | "handle" => Some(DebuggerCommand::InfoSignals(tokens.get(2).map(|s| s.to_string()))),
+                    "sharedlibrary" | "dll" => Some(DebuggerCommand::InfoSharedLibrary),
+                    _ => None,
+                }
             }
             // Default case:
             _ => None,
         }
     }
 }
+
+/// Parses a list of breakpoint numbers. Returns None if any of them isn't a number.
+fn parse_numbers(tokens: &[&str]) -> Option<Vec<usize>> {
+    tokens.iter().map(|token| token.parse().ok()).collect()
+}
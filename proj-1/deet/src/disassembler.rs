@@ -0,0 +1,1800 @@
+//! A small x86-64 instruction decoder for `disassemble` and `x/i`. It prints AT&T syntax the
+//! way gdb does, and knows the general-purpose, x87, SSE and common AVX instructions that
+//! compilers and libc use. Anything else decodes as a one-byte `(bad)`.
+
+/// Longest possible x86 instruction.
+pub const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+const GPR64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15",
+];
+const GPR32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d",
+    "r12d", "r13d", "r14d", "r15d",
+];
+const GPR16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w",
+    "r13w", "r14w", "r15w",
+];
+const GPR8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+/// Byte registers 4-7 without a REX prefix.
+const GPR8_LEGACY: [&str; 4] = ["ah", "ch", "dh", "bh"];
+const SEGMENTS: [&str; 8] = ["es", "cs", "ss", "ds", "fs", "gs", "?", "?"];
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "shl", "sar"];
+const CMP_PREDICATES: [&str; 8] = ["eq", "lt", "le", "unord", "neq", "nlt", "nle", "ord"];
+
+/// A decoded instruction.
+pub struct Instruction {
+    pub len: usize,
+    /// The instruction in AT&T syntax, e.g. `mov    %edi,-0x14(%rbp)`.
+    pub text: String,
+}
+
+/// Decodes the instruction at the start of `bytes`, which live at address `addr`. `symbolize`
+/// names addresses (branch targets and %rip-relative operands), e.g. as `main+4`.
+pub fn decode<F>(bytes: &[u8], addr: usize, symbolize: F) -> Instruction
+where
+    F: Fn(usize) -> Option<String>,
+{
+    let bytes = &bytes[..bytes.len().min(MAX_INSTRUCTION_LENGTH)];
+    let mut decoder = Decoder::new(bytes);
+    match decoder.instruction() {
+        Some(decoded) => {
+            let len = decoder.pos;
+            Instruction {
+                len,
+                text: decoder.format(decoded, addr + len, &symbolize),
+            }
+        }
+        None => Instruction {
+            len: 1,
+            text: "(bad)".to_string(),
+        },
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Size {
+    fn bytes(self) -> usize {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+            Size::Qword => 8,
+        }
+    }
+
+    fn suffix(self) -> char {
+        match self {
+            Size::Byte => 'b',
+            Size::Word => 'w',
+            Size::Dword => 'l',
+            Size::Qword => 'q',
+        }
+    }
+}
+
+/// The kind of register a ModRM field names.
+#[derive(Clone, Copy)]
+enum Kind {
+    Gpr(Size),
+    Xmm,
+    Ymm,
+    Mmx,
+    Segment,
+}
+
+#[derive(Clone)]
+struct Memory {
+    segment: Option<&'static str>,
+    disp: i64,
+    has_disp: bool,
+    base: Option<&'static str>,
+    index: Option<(&'static str, u8)>,
+    rip: bool,
+}
+
+#[derive(Clone)]
+enum Operand {
+    Reg(String),
+    Mem(Memory),
+    Imm(i64, Size),
+    Target(i64),
+    Indirect(Box<Operand>),
+}
+
+struct ModRm {
+    md: u8,
+    reg: u8,
+    rm: u8,
+    mem: Option<Memory>,
+}
+
+struct Decoded {
+    mnemonic: String,
+    operands: Vec<Operand>,
+    /// Operand size to append to the mnemonic (`movl`, `addq`) when no register operand
+    /// already implies it.
+    suffix: Option<Size>,
+}
+
+fn ins(mnemonic: &str, operands: Vec<Operand>) -> Option<Decoded> {
+    Some(Decoded {
+        mnemonic: mnemonic.to_string(),
+        operands,
+        suffix: None,
+    })
+}
+
+fn sized(mnemonic: &str, operands: Vec<Operand>, size: Size) -> Option<Decoded> {
+    Some(Decoded {
+        mnemonic: mnemonic.to_string(),
+        operands,
+        suffix: Some(size),
+    })
+}
+
+fn reg(name: &str) -> Operand {
+    Operand::Reg(name.to_string())
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    rex: u8,
+    opsize: bool,
+    /// Number of 0x66 prefixes, as padding nops repeat them.
+    opsize_count: usize,
+    adsize: bool,
+    /// 0xf2 or 0xf3, which select an instruction for SSE opcodes and mean rep elsewhere.
+    rep: Option<u8>,
+    rep_used: bool,
+    /// Whether 0x66 selected an SSE instruction rather than the operand size.
+    opsize_used: bool,
+    lock: bool,
+    segment: Option<&'static str>,
+    /// Set while decoding a VEX instruction: the extra register operand (vvvv) and whether the
+    /// vector length is 256 bits.
+    vex: Option<(u8, bool)>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            bytes,
+            pos: 0,
+            rex: 0,
+            opsize: false,
+            opsize_count: 0,
+            adsize: false,
+            rep: None,
+            rep_used: false,
+            opsize_used: false,
+            lock: false,
+            segment: None,
+            vex: None,
+        }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Reads a little-endian immediate of `len` bytes, sign-extended.
+    fn imm(&mut self, len: usize) -> Option<i64> {
+        let bytes = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(bytes);
+        let shift = 64 - 8 * len;
+        Some(((u64::from_le_bytes(buf) << shift) as i64) >> shift)
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 8 != 0
+    }
+
+    fn rex_r(&self) -> u8 {
+        (self.rex >> 2) & 1
+    }
+
+    fn rex_x(&self) -> u8 {
+        (self.rex >> 1) & 1
+    }
+
+    fn rex_b(&self) -> u8 {
+        self.rex & 1
+    }
+
+    /// The operand size of instructions whose size depends on REX.W and the 0x66 prefix.
+    fn op_size(&self) -> Size {
+        if self.rex_w() {
+            Size::Qword
+        } else if self.opsize {
+            Size::Word
+        } else {
+            Size::Dword
+        }
+    }
+
+    /// Size of an immediate that is 32 bits unless the operand size is 16 (Intel's "Iz").
+    fn imm_z(&self) -> usize {
+        if self.op_size() == Size::Word { 2 } else { 4 }
+    }
+
+    /// Returns the mandatory prefix of an SSE instruction (0, 0x66, 0xf3 or 0xf2), marking it
+    /// as consumed.
+    fn sse_prefix(&mut self) -> u8 {
+        if let Some(rep) = self.rep {
+            self.rep_used = true;
+            rep
+        } else if self.opsize {
+            self.opsize_used = true;
+            0x66
+        } else {
+            0
+        }
+    }
+
+    fn gpr(&self, num: u8, size: Size) -> String {
+        let num = num as usize;
+        match size {
+            Size::Qword => GPR64[num],
+            Size::Dword => GPR32[num],
+            Size::Word => GPR16[num],
+            Size::Byte if self.rex == 0 && (4..8).contains(&num) => GPR8_LEGACY[num - 4],
+            Size::Byte => GPR8[num],
+        }
+        .to_string()
+    }
+
+    fn register(&self, num: u8, kind: Kind) -> Operand {
+        Operand::Reg(match kind {
+            Kind::Gpr(size) => self.gpr(num, size),
+            Kind::Xmm => format!("xmm{}", num),
+            Kind::Ymm => format!("ymm{}", num),
+            Kind::Mmx => format!("mm{}", num & 7),
+            Kind::Segment => SEGMENTS[(num & 7) as usize].to_string(),
+        })
+    }
+
+    /// Reads a ModRM byte along with its SIB byte and displacement, if any.
+    fn modrm(&mut self) -> Option<ModRm> {
+        let byte = self.byte()?;
+        let md = byte >> 6;
+        let reg = ((byte >> 3) & 7) | (self.rex_r() << 3);
+        let low = byte & 7;
+        let rm = low | (self.rex_b() << 3);
+        if md == 3 {
+            return Some(ModRm {
+                md,
+                reg,
+                rm,
+                mem: None,
+            });
+        }
+        let names = if self.adsize { &GPR32 } else { &GPR64 };
+        let mut mem = Memory {
+            // Other segment overrides are ignored in 64-bit mode and shown as prefixes instead
+            segment: self.segment.filter(|segment| matches!(*segment, "fs" | "gs")),
+            disp: 0,
+            has_disp: false,
+            base: None,
+            index: None,
+            rip: false,
+        };
+        if low == 4 {
+            let sib = self.byte()?;
+            let scale = 1 << (sib >> 6);
+            let index = ((sib >> 3) & 7) | (self.rex_x() << 3);
+            let base = (sib & 7) | (self.rex_b() << 3);
+            if index != 4 {
+                mem.index = Some((names[index as usize], scale));
+            }
+            if sib & 7 == 5 && md == 0 {
+                mem.disp = self.imm(4)?;
+                mem.has_disp = true;
+            } else {
+                mem.base = Some(names[base as usize]);
+            }
+        } else if low == 5 && md == 0 {
+            mem.rip = true;
+            mem.disp = self.imm(4)?;
+            mem.has_disp = true;
+        } else {
+            mem.base = Some(names[rm as usize]);
+        }
+        match md {
+            1 => {
+                mem.disp = self.imm(1)?;
+                mem.has_disp = true;
+            }
+            2 => {
+                mem.disp = self.imm(4)?;
+                mem.has_disp = true;
+            }
+            _ => {}
+        }
+        Some(ModRm {
+            md,
+            reg,
+            rm,
+            mem: Some(mem),
+        })
+    }
+
+    /// The operand named by the r/m field.
+    fn rm(&self, m: &ModRm, kind: Kind) -> Operand {
+        match &m.mem {
+            Some(mem) => Operand::Mem(mem.clone()),
+            None => match kind {
+                // Without REX.B, an r/m register can't be one of mm8-mm15
+                Kind::Mmx => self.register(m.rm & 7, kind),
+                _ => self.register(m.rm, kind),
+            },
+        }
+    }
+
+    /// The operand named by the reg field.
+    fn reg(&self, m: &ModRm, kind: Kind) -> Operand {
+        self.register(m.reg, kind)
+    }
+
+    /// The register named by VEX.vvvv.
+    fn vvvv(&self, kind: Kind) -> Operand {
+        self.register(self.vex.map(|(vvvv, _)| vvvv).unwrap_or(0), kind)
+    }
+
+    fn rel(&mut self, len: usize) -> Option<Operand> {
+        let offset = self.imm(len)?;
+        // Relative to the end of the instruction, which is here for every branch
+        Some(Operand::Target(self.pos as i64 + offset))
+    }
+
+    fn instruction(&mut self) -> Option<Decoded> {
+        loop {
+            match self.peek()? {
+                0x66 => {
+                    self.opsize = true;
+                    self.opsize_count += 1;
+                }
+                0x67 => self.adsize = true,
+                0xf0 => self.lock = true,
+                0xf2 | 0xf3 => self.rep = self.peek(),
+                0x2e => self.segment = Some("cs"),
+                0x36 => self.segment = Some("ss"),
+                0x3e => self.segment = Some("ds"),
+                0x26 => self.segment = Some("es"),
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if let Some(byte @ 0x40..=0x4f) = self.peek() {
+            self.rex = byte;
+            self.pos += 1;
+        }
+        let op = self.byte()?;
+        match op {
+            0x0f => self.two_byte(),
+            0xc4 | 0xc5 => self.vex_instruction(op),
+            _ => self.one_byte(op),
+        }
+    }
+
+    fn one_byte(&mut self, op: u8) -> Option<Decoded> {
+        let v = Kind::Gpr(self.op_size());
+        let b = Kind::Gpr(Size::Byte);
+        let q = Kind::Gpr(Size::Qword);
+        match op {
+            0x00..=0x3f if op & 7 < 6 => {
+                let name = ALU[(op >> 3) as usize];
+                match op & 7 {
+                    0 => {
+                        let m = self.modrm()?;
+                        ins(name, vec![self.reg(&m, b), self.rm(&m, b)])
+                    }
+                    1 => {
+                        let m = self.modrm()?;
+                        ins(name, vec![self.reg(&m, v), self.rm(&m, v)])
+                    }
+                    2 => {
+                        let m = self.modrm()?;
+                        ins(name, vec![self.rm(&m, b), self.reg(&m, b)])
+                    }
+                    3 => {
+                        let m = self.modrm()?;
+                        ins(name, vec![self.rm(&m, v), self.reg(&m, v)])
+                    }
+                    4 => ins(name, vec![Operand::Imm(self.imm(1)?, Size::Byte), reg("al")]),
+                    _ => {
+                        let imm = self.imm(self.imm_z())?;
+                        ins(name, vec![Operand::Imm(imm, self.op_size()), self.register(0, v)])
+                    }
+                }
+            }
+            0x50..=0x57 => ins("push", vec![self.register((op & 7) | (self.rex_b() << 3), q)]),
+            0x58..=0x5f => ins("pop", vec![self.register((op & 7) | (self.rex_b() << 3), q)]),
+            0x63 => {
+                let m = self.modrm()?;
+                let name = if self.rex_w() { "movslq" } else { "movsxd" };
+                ins(name, vec![self.rm(&m, Kind::Gpr(Size::Dword)), self.reg(&m, v)])
+            }
+            0x68 => ins("push", vec![Operand::Imm(self.imm(4)?, Size::Qword)]),
+            0x6a => ins("push", vec![Operand::Imm(self.imm(1)?, Size::Qword)]),
+            0x69 | 0x6b => {
+                let m = self.modrm()?;
+                let len = if op == 0x69 { self.imm_z() } else { 1 };
+                let imm = Operand::Imm(self.imm(len)?, self.op_size());
+                ins("imul", vec![imm, self.rm(&m, v), self.reg(&m, v)])
+            }
+            0x70..=0x7f => {
+                let target = self.rel(1)?;
+                ins(&format!("j{}", CONDITIONS[(op & 0xf) as usize]), vec![target])
+            }
+            0x80 | 0x81 | 0x83 => {
+                let m = self.modrm()?;
+                let (kind, size, len) = match op {
+                    0x80 => (b, Size::Byte, 1),
+                    0x81 => (v, self.op_size(), self.imm_z()),
+                    _ => (v, self.op_size(), 1),
+                };
+                let imm = Operand::Imm(self.imm(len)?, size);
+                sized(ALU[(m.reg & 7) as usize], vec![imm, self.rm(&m, kind)], size)
+            }
+            0x84..=0x89 => {
+                let m = self.modrm()?;
+                let kind = if op & 1 == 0 { b } else { v };
+                let name = match op {
+                    0x84 | 0x85 => "test",
+                    0x86 | 0x87 => "xchg",
+                    _ => "mov",
+                };
+                ins(name, vec![self.reg(&m, kind), self.rm(&m, kind)])
+            }
+            0x8a | 0x8b => {
+                let m = self.modrm()?;
+                let kind = if op == 0x8a { b } else { v };
+                ins("mov", vec![self.rm(&m, kind), self.reg(&m, kind)])
+            }
+            0x8c => {
+                let m = self.modrm()?;
+                let kind = if m.md == 3 { v } else { Kind::Gpr(Size::Word) };
+                ins("mov", vec![self.reg(&m, Kind::Segment), self.rm(&m, kind)])
+            }
+            0x8d => {
+                let m = self.modrm()?;
+                m.mem.as_ref()?;
+                ins("lea", vec![self.rm(&m, v), self.reg(&m, v)])
+            }
+            0x8e => {
+                let m = self.modrm()?;
+                ins("mov", vec![self.rm(&m, Kind::Gpr(Size::Word)), self.reg(&m, Kind::Segment)])
+            }
+            0x8f => {
+                let m = self.modrm()?;
+                sized("pop", vec![self.rm(&m, q)], Size::Qword)
+            }
+            0x90 if self.rex_b() == 0 && !self.opsize => {
+                if self.rep == Some(0xf3) {
+                    self.rep_used = true;
+                    ins("pause", vec![])
+                } else {
+                    ins("nop", vec![])
+                }
+            }
+            0x90..=0x97 => {
+                let other = self.register((op & 7) | (self.rex_b() << 3), v);
+                ins("xchg", vec![self.register(0, v), other])
+            }
+            0x98 => ins(
+                match self.op_size() {
+                    Size::Qword => "cltq",
+                    Size::Word => "cbtw",
+                    _ => "cwtl",
+                },
+                vec![],
+            ),
+            0x99 => ins(
+                match self.op_size() {
+                    Size::Qword => "cqto",
+                    Size::Word => "cwtd",
+                    _ => "cltd",
+                },
+                vec![],
+            ),
+            0x9b => {
+                // fwait followed by a no-wait x87 control instruction is its waiting form
+                let start = self.pos;
+                match self.peek() {
+                    Some(0xd8..=0xdf) => {
+                        let next = self.byte()?;
+                        match self.x87(next) {
+                            Some(mut decoded) if decoded.mnemonic.starts_with("fn") => {
+                                decoded.mnemonic.remove(1);
+                                Some(decoded)
+                            }
+                            _ => {
+                                self.pos = start;
+                                ins("fwait", vec![])
+                            }
+                        }
+                    }
+                    _ => ins("fwait", vec![]),
+                }
+            }
+            0x9c => ins("pushf", vec![]),
+            0x9d => ins("popf", vec![]),
+            0x9e => ins("sahf", vec![]),
+            0x9f => ins("lahf", vec![]),
+            0xa0..=0xa3 => {
+                let kind = if op & 1 == 0 { b } else { v };
+                let moffs = Operand::Mem(Memory {
+                    segment: self.segment.filter(|segment| matches!(*segment, "fs" | "gs")),
+                    disp: self.imm(8)?,
+                    has_disp: true,
+                    base: None,
+                    index: None,
+                    rip: false,
+                });
+                let acc = self.register(0, kind);
+                let operands = if op < 0xa2 { vec![moffs, acc] } else { vec![acc, moffs] };
+                ins("movabs", operands)
+            }
+            0xa4..=0xa7 | 0xaa..=0xaf => self.string_instruction(op),
+            0xa8 => ins("test", vec![Operand::Imm(self.imm(1)?, Size::Byte), reg("al")]),
+            0xa9 => {
+                let imm = self.imm(self.imm_z())?;
+                ins("test", vec![Operand::Imm(imm, self.op_size()), self.register(0, v)])
+            }
+            0xb0..=0xb7 => {
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins("mov", vec![imm, self.register((op & 7) | (self.rex_b() << 3), b)])
+            }
+            0xb8..=0xbf => {
+                let dest = self.register((op & 7) | (self.rex_b() << 3), v);
+                if self.rex_w() {
+                    ins("movabs", vec![Operand::Imm(self.imm(8)?, Size::Qword), dest])
+                } else {
+                    let imm = self.imm(self.imm_z())?;
+                    ins("mov", vec![Operand::Imm(imm, self.op_size()), dest])
+                }
+            }
+            0xc0 | 0xc1 | 0xd0..=0xd3 => {
+                let m = self.modrm()?;
+                let (kind, size) = if op & 1 == 0 { (b, Size::Byte) } else { (v, self.op_size()) };
+                let name = SHIFTS[(m.reg & 7) as usize];
+                let target = self.rm(&m, kind);
+                match op {
+                    0xc0 | 0xc1 => {
+                        let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                        sized(name, vec![imm, target], size)
+                    }
+                    0xd0 | 0xd1 => sized(name, vec![target], size),
+                    _ => sized(name, vec![reg("cl"), target], size),
+                }
+            }
+            0xc2 => ins("ret", vec![Operand::Imm(self.imm(2)?, Size::Word)]),
+            0xc3 => ins("ret", vec![]),
+            0xc6 if self.peek() == Some(0xf8) => {
+                self.pos += 1;
+                ins("xabort", vec![Operand::Imm(self.imm(1)?, Size::Byte)])
+            }
+            0xc7 if self.peek() == Some(0xf8) => {
+                self.pos += 1;
+                let target = self.rel(4)?;
+                ins("xbegin", vec![target])
+            }
+            0xc6 | 0xc7 => {
+                let m = self.modrm()?;
+                if m.reg & 7 != 0 {
+                    return None;
+                }
+                let (kind, size, len) = if op == 0xc6 {
+                    (b, Size::Byte, 1)
+                } else {
+                    (v, self.op_size(), self.imm_z())
+                };
+                let imm = Operand::Imm(self.imm(len)?, size);
+                sized("mov", vec![imm, self.rm(&m, kind)], size)
+            }
+            0xc8 => {
+                let frame = Operand::Imm(self.imm(2)?, Size::Word);
+                let level = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins("enter", vec![frame, level])
+            }
+            0xc9 => ins("leave", vec![]),
+            0xcc => ins("int3", vec![]),
+            0xcd => ins("int", vec![Operand::Imm(self.imm(1)?, Size::Byte)]),
+            0xd8..=0xdf => self.x87(op),
+            0xe0..=0xe3 => {
+                let target = self.rel(1)?;
+                ins(["loopne", "loope", "loop", "jrcxz"][(op & 3) as usize], vec![target])
+            }
+            0xe8 => {
+                let target = self.rel(4)?;
+                ins("call", vec![target])
+            }
+            0xe9 => {
+                let target = self.rel(4)?;
+                ins("jmp", vec![target])
+            }
+            0xeb => {
+                let target = self.rel(1)?;
+                ins("jmp", vec![target])
+            }
+            0xf4 => ins("hlt", vec![]),
+            0xf5 => ins("cmc", vec![]),
+            0xf6 | 0xf7 => {
+                let m = self.modrm()?;
+                let (kind, size) = if op == 0xf6 { (b, Size::Byte) } else { (v, self.op_size()) };
+                let target = self.rm(&m, kind);
+                match m.reg & 7 {
+                    0 | 1 => {
+                        let len = if op == 0xf6 { 1 } else { self.imm_z() };
+                        let imm = Operand::Imm(self.imm(len)?, size);
+                        sized("test", vec![imm, target], size)
+                    }
+                    n => sized(["", "", "not", "neg", "mul", "imul", "div", "idiv"][n as usize], vec![target], size),
+                }
+            }
+            0xf8 => ins("clc", vec![]),
+            0xf9 => ins("stc", vec![]),
+            0xfa => ins("cli", vec![]),
+            0xfb => ins("sti", vec![]),
+            0xfc => ins("cld", vec![]),
+            0xfd => ins("std", vec![]),
+            0xfe => {
+                let m = self.modrm()?;
+                match m.reg & 7 {
+                    0 => sized("inc", vec![self.rm(&m, b)], Size::Byte),
+                    1 => sized("dec", vec![self.rm(&m, b)], Size::Byte),
+                    _ => None,
+                }
+            }
+            0xff => {
+                let m = self.modrm()?;
+                match m.reg & 7 {
+                    0 => sized("inc", vec![self.rm(&m, v)], self.op_size()),
+                    1 => sized("dec", vec![self.rm(&m, v)], self.op_size()),
+                    2 => ins("call", vec![Operand::Indirect(Box::new(self.rm(&m, q)))]),
+                    3 => ins("lcall", vec![Operand::Indirect(Box::new(self.rm(&m, q)))]),
+                    4 => ins("jmp", vec![Operand::Indirect(Box::new(self.rm(&m, q)))]),
+                    5 => ins("ljmp", vec![Operand::Indirect(Box::new(self.rm(&m, q)))]),
+                    6 => ins("push", vec![self.rm(&m, q)]),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// movs, cmps, stos, lods and scas, which take their operands from %rsi and %rdi.
+    fn string_instruction(&mut self, op: u8) -> Option<Decoded> {
+        let (kind, size) = if op & 1 == 0 {
+            (Kind::Gpr(Size::Byte), Size::Byte)
+        } else {
+            (Kind::Gpr(self.op_size()), self.op_size())
+        };
+        let (si, di) = if self.adsize { ("esi", "edi") } else { ("rsi", "rdi") };
+        // Reg operands get a % prepended, which gives %ds:(%rsi)
+        let src = Operand::Reg(format!("{}:(%{})", self.segment.unwrap_or("ds"), si));
+        let dst = Operand::Reg(format!("es:(%{})", di));
+        let acc = self.register(0, kind);
+        let suffix = size.suffix();
+        match op {
+            0xa4 | 0xa5 => ins(&format!("movs{}", suffix), vec![src, dst]),
+            0xa6 | 0xa7 => ins(&format!("cmps{}", suffix), vec![dst, src]),
+            0xaa | 0xab => ins("stos", vec![acc, dst]),
+            0xac | 0xad => ins("lods", vec![src, acc]),
+            _ => ins("scas", vec![dst, acc]),
+        }
+    }
+
+    fn x87(&mut self, op: u8) -> Option<Decoded> {
+        let m = self.modrm()?;
+        let row = (m.reg & 7) as usize;
+        let index = m.rm & 7;
+        let st = |i: u8| Operand::Reg(format!("st({})", i));
+        let st0 = || reg("st");
+        if m.mem.is_some() {
+            let names: [&str; 8] = match op {
+                0xd8 => ["fadds", "fmuls", "fcoms", "fcomps", "fsubs", "fsubrs", "fdivs", "fdivrs"],
+                0xd9 => ["flds", "", "fsts", "fstps", "fldenv", "fldcw", "fnstenv", "fnstcw"],
+                0xda => ["fiaddl", "fimull", "ficoml", "ficompl", "fisubl", "fisubrl", "fidivl", "fidivrl"],
+                0xdb => ["fildl", "fisttpl", "fistl", "fistpl", "", "fldt", "", "fstpt"],
+                0xdc => ["faddl", "fmull", "fcoml", "fcompl", "fsubl", "fsubrl", "fdivl", "fdivrl"],
+                0xdd => ["fldl", "fisttpll", "fstl", "fstpl", "frstor", "", "fnsave", "fnstsw"],
+                0xde => ["fiadds", "fimuls", "ficoms", "ficomps", "fisubs", "fisubrs", "fidivs", "fidivrs"],
+                _ => ["filds", "fisttps", "fists", "fistps", "fbld", "fildll", "fbstp", "fistpll"],
+            };
+            return match names[row] {
+                "" => None,
+                name => ins(name, vec![self.rm(&m, Kind::Gpr(Size::Qword))]),
+            };
+        }
+        match (op, row) {
+            (0xd8, _) => {
+                let name = ["fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr"][row];
+                ins(name, vec![st(index), st0()])
+            }
+            (0xd9, 0) => ins("fld", vec![st(index)]),
+            (0xd9, 1) => ins("fxch", vec![st(index)]),
+            (0xd9, _) => {
+                let name = match 0xc0 | (row as u8) << 3 | index {
+                    0xd0 => "fnop",
+                    0xe0 => "fchs",
+                    0xe1 => "fabs",
+                    0xe4 => "ftst",
+                    0xe5 => "fxam",
+                    0xe8 => "fld1",
+                    0xe9 => "fldl2t",
+                    0xea => "fldl2e",
+                    0xeb => "fldpi",
+                    0xec => "fldlg2",
+                    0xed => "fldln2",
+                    0xee => "fldz",
+                    0xf0 => "f2xm1",
+                    0xf1 => "fyl2x",
+                    0xf2 => "fptan",
+                    0xf3 => "fpatan",
+                    0xf4 => "fxtract",
+                    0xf5 => "fprem1",
+                    0xf6 => "fdecstp",
+                    0xf7 => "fincstp",
+                    0xf8 => "fprem",
+                    0xf9 => "fyl2xp1",
+                    0xfa => "fsqrt",
+                    0xfb => "fsincos",
+                    0xfc => "frndint",
+                    0xfd => "fscale",
+                    0xfe => "fsin",
+                    0xff => "fcos",
+                    _ => return None,
+                };
+                ins(name, vec![])
+            }
+            (0xda, 5) if index == 1 => ins("fucompp", vec![]),
+            (0xda, 0..=3) => {
+                let name = ["fcmovb", "fcmove", "fcmovbe", "fcmovu"][row];
+                ins(name, vec![st(index), st0()])
+            }
+            (0xdb, 4) if index == 2 => ins("fnclex", vec![]),
+            (0xdb, 4) if index == 3 => ins("fninit", vec![]),
+            (0xdb, 0..=3) => {
+                let name = ["fcmovnb", "fcmovne", "fcmovnbe", "fcmovnu"][row];
+                ins(name, vec![st(index), st0()])
+            }
+            (0xdb, 5) => ins("fucomi", vec![st(index), st0()]),
+            (0xdb, 6) => ins("fcomi", vec![st(index), st0()]),
+            (0xdc, _) => {
+                let name = ["fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr"][row];
+                ins(name, vec![st0(), st(index)])
+            }
+            (0xdd, 0) => ins("ffree", vec![st(index)]),
+            (0xdd, 2) => ins("fst", vec![st(index)]),
+            (0xdd, 3) => ins("fstp", vec![st(index)]),
+            (0xdd, 4) => ins("fucom", vec![st(index)]),
+            (0xdd, 5) => ins("fucomp", vec![st(index)]),
+            (0xde, 3) if index == 1 => ins("fcompp", vec![]),
+            (0xde, 0 | 1 | 4..=7) => {
+                let name = ["faddp", "fmulp", "", "", "fsubp", "fsubrp", "fdivp", "fdivrp"][row];
+                ins(name, vec![st0(), st(index)])
+            }
+            (0xdf, 4) if index == 0 => ins("fnstsw", vec![reg("ax")]),
+            (0xdf, 5) => ins("fucomip", vec![st(index), st0()]),
+            (0xdf, 6) => ins("fcomip", vec![st(index), st0()]),
+            _ => None,
+        }
+    }
+
+    /// The xmm (with a 0x66 prefix) or mm register kind of the MMX/SSE integer instructions.
+    fn mmx_or_xmm(&mut self) -> Kind {
+        if self.sse_prefix() == 0x66 { Kind::Xmm } else { Kind::Mmx }
+    }
+
+    fn two_byte(&mut self) -> Option<Decoded> {
+        let op = self.byte()?;
+        let v = Kind::Gpr(self.op_size());
+        let x = Kind::Xmm;
+        match op {
+            0x01 => match self.byte()? {
+                0xd0 => ins("xgetbv", vec![]),
+                0xd5 => ins("xend", vec![]),
+                0xd6 => ins("xtest", vec![]),
+                0xee => ins("rdpkru", vec![]),
+                0xef => ins("wrpkru", vec![]),
+                0xf9 => ins("rdtscp", vec![]),
+                _ => None,
+            },
+            0x05 => ins("syscall", vec![]),
+            0x0b => ins("ud2", vec![]),
+            0x0d => {
+                let m = self.modrm()?;
+                m.mem.as_ref()?;
+                let name = if m.reg & 7 == 1 { "prefetchw" } else { "prefetch" };
+                ins(name, vec![self.rm(&m, v)])
+            }
+            0x10 | 0x11 | 0x28 | 0x29 => {
+                let prefix = self.sse_prefix();
+                let name = match (op, prefix) {
+                    (0x10 | 0x11, 0) => "movups",
+                    (0x10 | 0x11, 0x66) => "movupd",
+                    (0x10 | 0x11, 0xf3) => "movss",
+                    (0x10 | 0x11, _) => "movsd",
+                    (_, 0x66) => "movapd",
+                    _ => "movaps",
+                };
+                let m = self.modrm()?;
+                if op & 1 == 0 {
+                    ins(name, vec![self.rm(&m, x), self.reg(&m, x)])
+                } else {
+                    ins(name, vec![self.reg(&m, x), self.rm(&m, x)])
+                }
+            }
+            0x12 | 0x13 | 0x16 | 0x17 => {
+                let prefix = self.sse_prefix();
+                let m = self.modrm()?;
+                let high = op >= 0x16;
+                let name = match (prefix, high, m.mem.is_some()) {
+                    (0xf2, false, _) => "movddup",
+                    (0xf3, false, _) => "movsldup",
+                    (0xf3, true, _) => "movshdup",
+                    (0x66, false, _) => "movlpd",
+                    (0x66, true, _) => "movhpd",
+                    (_, false, false) => "movhlps",
+                    (_, true, false) => "movlhps",
+                    (_, false, true) => "movlps",
+                    (_, true, true) => "movhps",
+                };
+                if op & 1 == 0 {
+                    ins(name, vec![self.rm(&m, x), self.reg(&m, x)])
+                } else {
+                    ins(name, vec![self.reg(&m, x), self.rm(&m, x)])
+                }
+            }
+            0x14 | 0x15 => {
+                let packed = if self.sse_prefix() == 0x66 { "pd" } else { "ps" };
+                let name = format!("{}{}", if op == 0x14 { "unpckl" } else { "unpckh" }, packed);
+                let m = self.modrm()?;
+                ins(&name, vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            0x18 => {
+                let m = self.modrm()?;
+                let name = match m.reg & 7 {
+                    0 => "prefetchnta",
+                    1 => "prefetcht0",
+                    2 => "prefetcht1",
+                    3 => "prefetcht2",
+                    _ => "nop",
+                };
+                ins(name, vec![self.rm(&m, v)])
+            }
+            0x1e if self.rep == Some(0xf3) && self.peek() == Some(0xfa) => {
+                self.pos += 1;
+                self.rep_used = true;
+                ins("endbr64", vec![])
+            }
+            0x1e if self.rep == Some(0xf3) && self.peek() == Some(0xfb) => {
+                self.pos += 1;
+                self.rep_used = true;
+                ins("endbr32", vec![])
+            }
+            0x19..=0x1f => {
+                let m = self.modrm()?;
+                sized("nop", vec![self.rm(&m, v)], self.op_size())
+            }
+            0x2a => {
+                let m = self.modrm()?;
+                let (name, kind) = match self.sse_prefix() {
+                    0xf3 => ("cvtsi2ss", v),
+                    0xf2 => ("cvtsi2sd", v),
+                    0x66 => ("cvtpi2pd", Kind::Mmx),
+                    _ => ("cvtpi2ps", Kind::Mmx),
+                };
+                let size = if self.rex_w() { Size::Qword } else { Size::Dword };
+                let kind = match kind {
+                    Kind::Gpr(_) => Kind::Gpr(size),
+                    kind => kind,
+                };
+                let decoded = Decoded {
+                    mnemonic: name.to_string(),
+                    operands: vec![self.rm(&m, kind), self.reg(&m, x)],
+                    suffix: if m.mem.is_some() && matches!(kind, Kind::Gpr(_)) { Some(size) } else { None },
+                };
+                // The xmm destination would otherwise hide the need for a suffix
+                Some(match decoded.suffix {
+                    Some(size) => Decoded {
+                        mnemonic: format!("{}{}", decoded.mnemonic, size.suffix()),
+                        suffix: None,
+                        ..decoded
+                    },
+                    None => decoded,
+                })
+            }
+            0x2b => {
+                let name = if self.sse_prefix() == 0x66 { "movntpd" } else { "movntps" };
+                let m = self.modrm()?;
+                ins(name, vec![self.reg(&m, x), self.rm(&m, x)])
+            }
+            0x2c | 0x2d => {
+                let truncate = if op == 0x2c { "t" } else { "" };
+                let name = match self.sse_prefix() {
+                    0xf3 => format!("cvt{}ss2si", truncate),
+                    0xf2 => format!("cvt{}sd2si", truncate),
+                    0x66 => format!("cvt{}pd2pi", truncate),
+                    _ => format!("cvt{}ps2pi", truncate),
+                };
+                let m = self.modrm()?;
+                let dest = if self.rex_w() { Size::Qword } else { Size::Dword };
+                ins(&name, vec![self.rm(&m, x), self.reg(&m, Kind::Gpr(dest))])
+            }
+            0x2e | 0x2f => {
+                let packed = if self.sse_prefix() == 0x66 { "sd" } else { "ss" };
+                let name = format!("{}{}", if op == 0x2e { "ucomi" } else { "comi" }, packed);
+                let m = self.modrm()?;
+                ins(&name, vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            0x31 => ins("rdtsc", vec![]),
+            0x38 => self.three_byte_38(),
+            0x3a => self.three_byte_3a(),
+            0x40..=0x4f => {
+                let m = self.modrm()?;
+                let name = format!("cmov{}", CONDITIONS[(op & 0xf) as usize]);
+                ins(&name, vec![self.rm(&m, v), self.reg(&m, v)])
+            }
+            0x50 => {
+                let packed = if self.sse_prefix() == 0x66 { "pd" } else { "ps" };
+                let m = self.modrm()?;
+                ins(&format!("movmsk{}", packed), vec![self.rm(&m, x), self.reg(&m, Kind::Gpr(Size::Dword))])
+            }
+            0x51..=0x5f => {
+                let prefix = self.sse_prefix();
+                let name = match (op, prefix) {
+                    (0x5a, 0) => "cvtps2pd".to_string(),
+                    (0x5a, 0x66) => "cvtpd2ps".to_string(),
+                    (0x5a, 0xf3) => "cvtss2sd".to_string(),
+                    (0x5a, _) => "cvtsd2ss".to_string(),
+                    (0x5b, 0) => "cvtdq2ps".to_string(),
+                    (0x5b, 0x66) => "cvtps2dq".to_string(),
+                    (0x5b, 0xf3) => "cvttps2dq".to_string(),
+                    (0x5b, _) => return None,
+                    _ => {
+                        let base = [
+                            "sqrt", "rsqrt", "rcp", "and", "andn", "or", "xor", "add", "mul", "", "",
+                            "sub", "min", "div", "max",
+                        ][(op - 0x51) as usize];
+                        let logical = (0x54..=0x57).contains(&op);
+                        let packed = match prefix {
+                            0x66 => "pd",
+                            0xf3 if !logical => "ss",
+                            0xf2 if !logical => "sd",
+                            _ => "ps",
+                        };
+                        format!("{}{}", base, packed)
+                    }
+                };
+                let m = self.modrm()?;
+                ins(&name, vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            0x60..=0x6d | 0x74..=0x76 | 0xd1..=0xd5 | 0xd8..=0xdf | 0xe0..=0xe5 | 0xe8..=0xef | 0xf1..=0xf6 | 0xf8..=0xfe => {
+                let name = match op {
+                    0x60 => "punpcklbw",
+                    0x61 => "punpcklwd",
+                    0x62 => "punpckldq",
+                    0x63 => "packsswb",
+                    0x64 => "pcmpgtb",
+                    0x65 => "pcmpgtw",
+                    0x66 => "pcmpgtd",
+                    0x67 => "packuswb",
+                    0x68 => "punpckhbw",
+                    0x69 => "punpckhwd",
+                    0x6a => "punpckhdq",
+                    0x6b => "packssdw",
+                    0x6c => "punpcklqdq",
+                    0x6d => "punpckhqdq",
+                    0x74 => "pcmpeqb",
+                    0x75 => "pcmpeqw",
+                    0x76 => "pcmpeqd",
+                    0xd1 => "psrlw",
+                    0xd2 => "psrld",
+                    0xd3 => "psrlq",
+                    0xd4 => "paddq",
+                    0xd5 => "pmullw",
+                    0xd8 => "psubusb",
+                    0xd9 => "psubusw",
+                    0xda => "pminub",
+                    0xdb => "pand",
+                    0xdc => "paddusb",
+                    0xdd => "paddusw",
+                    0xde => "pmaxub",
+                    0xdf => "pandn",
+                    0xe0 => "pavgb",
+                    0xe1 => "psraw",
+                    0xe2 => "psrad",
+                    0xe3 => "pavgw",
+                    0xe4 => "pmulhuw",
+                    0xe5 => "pmulhw",
+                    0xe8 => "psubsb",
+                    0xe9 => "psubsw",
+                    0xea => "pminsw",
+                    0xeb => "por",
+                    0xec => "paddsb",
+                    0xed => "paddsw",
+                    0xee => "pmaxsw",
+                    0xef => "pxor",
+                    0xf1 => "psllw",
+                    0xf2 => "pslld",
+                    0xf3 => "psllq",
+                    0xf4 => "pmuludq",
+                    0xf5 => "pmaddwd",
+                    0xf6 => "psadbw",
+                    0xf8 => "psubb",
+                    0xf9 => "psubw",
+                    0xfa => "psubd",
+                    0xfb => "psubq",
+                    0xfc => "paddb",
+                    0xfd => "paddw",
+                    _ => "paddd",
+                };
+                let kind = self.mmx_or_xmm();
+                let m = self.modrm()?;
+                ins(name, vec![self.rm(&m, kind), self.reg(&m, kind)])
+            }
+            0x6e | 0x7e => {
+                let prefix = self.sse_prefix();
+                let m = self.modrm()?;
+                let gpr = Kind::Gpr(if self.rex_w() { Size::Qword } else { Size::Dword });
+                let name = if self.rex_w() { "movq" } else { "movd" };
+                match (op, prefix) {
+                    (0x7e, 0xf3) => ins("movq", vec![self.rm(&m, x), self.reg(&m, x)]),
+                    (0x6e, 0x66) => ins(name, vec![self.rm(&m, gpr), self.reg(&m, x)]),
+                    (0x6e, _) => ins(name, vec![self.rm(&m, gpr), self.reg(&m, Kind::Mmx)]),
+                    (_, 0x66) => ins(name, vec![self.reg(&m, x), self.rm(&m, gpr)]),
+                    _ => ins(name, vec![self.reg(&m, Kind::Mmx), self.rm(&m, gpr)]),
+                }
+            }
+            0x6f | 0x7f => {
+                let (name, kind) = match self.sse_prefix() {
+                    0x66 => ("movdqa", x),
+                    0xf3 => ("movdqu", x),
+                    _ => ("movq", Kind::Mmx),
+                };
+                let m = self.modrm()?;
+                if op == 0x6f {
+                    ins(name, vec![self.rm(&m, kind), self.reg(&m, kind)])
+                } else {
+                    ins(name, vec![self.reg(&m, kind), self.rm(&m, kind)])
+                }
+            }
+            0x70 => {
+                let (name, kind) = match self.sse_prefix() {
+                    0x66 => ("pshufd", x),
+                    0xf3 => ("pshufhw", x),
+                    0xf2 => ("pshuflw", x),
+                    _ => ("pshufw", Kind::Mmx),
+                };
+                let m = self.modrm()?;
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins(name, vec![imm, self.rm(&m, kind), self.reg(&m, kind)])
+            }
+            0x71..=0x73 => {
+                let kind = self.mmx_or_xmm();
+                let m = self.modrm()?;
+                let name = match (op, m.reg & 7) {
+                    (0x71, 2) => "psrlw",
+                    (0x71, 4) => "psraw",
+                    (0x71, 6) => "psllw",
+                    (0x72, 2) => "psrld",
+                    (0x72, 4) => "psrad",
+                    (0x72, 6) => "pslld",
+                    (0x73, 2) => "psrlq",
+                    (0x73, 3) => "psrldq",
+                    (0x73, 6) => "psllq",
+                    (0x73, 7) => "pslldq",
+                    _ => return None,
+                };
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins(name, vec![imm, self.rm(&m, kind)])
+            }
+            0x77 => ins("emms", vec![]),
+            0x80..=0x8f => {
+                let target = self.rel(4)?;
+                ins(&format!("j{}", CONDITIONS[(op & 0xf) as usize]), vec![target])
+            }
+            0x90..=0x9f => {
+                let m = self.modrm()?;
+                let name = format!("set{}", CONDITIONS[(op & 0xf) as usize]);
+                ins(&name, vec![self.rm(&m, Kind::Gpr(Size::Byte))])
+            }
+            0xa2 => ins("cpuid", vec![]),
+            0xa3 | 0xab | 0xb3 | 0xbb => {
+                let name = match op {
+                    0xa3 => "bt",
+                    0xab => "bts",
+                    0xb3 => "btr",
+                    _ => "btc",
+                };
+                let m = self.modrm()?;
+                ins(name, vec![self.reg(&m, v), self.rm(&m, v)])
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => {
+                let name = if op < 0xac { "shld" } else { "shrd" };
+                let m = self.modrm()?;
+                let count = if op & 1 == 0 { Operand::Imm(self.imm(1)?, Size::Byte) } else { reg("cl") };
+                ins(name, vec![count, self.reg(&m, v), self.rm(&m, v)])
+            }
+            0xae => {
+                let m = self.modrm()?;
+                if m.mem.is_none() {
+                    return match m.reg & 7 {
+                        5 => ins("lfence", vec![]),
+                        6 => ins("mfence", vec![]),
+                        7 => ins("sfence", vec![]),
+                        _ => None,
+                    };
+                }
+                let name = ["fxsave", "fxrstor", "ldmxcsr", "stmxcsr", "xsave", "xrstor", "xsaveopt", "clflush"]
+                    [(m.reg & 7) as usize];
+                ins(name, vec![self.rm(&m, v)])
+            }
+            0xaf => {
+                let m = self.modrm()?;
+                ins("imul", vec![self.rm(&m, v), self.reg(&m, v)])
+            }
+            0xb0 | 0xb1 | 0xc0 | 0xc1 => {
+                let name = if op < 0xc0 { "cmpxchg" } else { "xadd" };
+                let kind = if op & 1 == 0 { Kind::Gpr(Size::Byte) } else { v };
+                let m = self.modrm()?;
+                ins(name, vec![self.reg(&m, kind), self.rm(&m, kind)])
+            }
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let source = if op & 1 == 0 { Size::Byte } else { Size::Word };
+                let extend = if op < 0xbe { "movz" } else { "movs" };
+                let name = format!("{}{}{}", extend, source.suffix(), self.op_size().suffix());
+                let m = self.modrm()?;
+                ins(&name, vec![self.rm(&m, Kind::Gpr(source)), self.reg(&m, v)])
+            }
+            0xb8 if self.rep == Some(0xf3) => {
+                self.rep_used = true;
+                let m = self.modrm()?;
+                ins("popcnt", vec![self.rm(&m, v), self.reg(&m, v)])
+            }
+            0xba => {
+                let m = self.modrm()?;
+                let name = match m.reg & 7 {
+                    4 => "bt",
+                    5 => "bts",
+                    6 => "btr",
+                    7 => "btc",
+                    _ => return None,
+                };
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                sized(name, vec![imm, self.rm(&m, v)], self.op_size())
+            }
+            0xbc | 0xbd => {
+                let name = match (op, self.rep == Some(0xf3)) {
+                    (0xbc, true) => "tzcnt",
+                    (0xbc, false) => "bsf",
+                    (_, true) => "lzcnt",
+                    (_, false) => "bsr",
+                };
+                if self.rep == Some(0xf3) {
+                    self.rep_used = true;
+                }
+                let m = self.modrm()?;
+                ins(name, vec![self.rm(&m, v), self.reg(&m, v)])
+            }
+            0xc2 => {
+                let packed = match self.sse_prefix() {
+                    0x66 => "pd",
+                    0xf3 => "ss",
+                    0xf2 => "sd",
+                    _ => "ps",
+                };
+                let m = self.modrm()?;
+                let predicate = self.imm(1)? as u8;
+                let operands = vec![self.rm(&m, x), self.reg(&m, x)];
+                match CMP_PREDICATES.get(predicate as usize) {
+                    Some(name) => ins(&format!("cmp{}{}", name, packed), operands),
+                    None => {
+                        let mut operands = operands;
+                        operands.insert(0, Operand::Imm(predicate as i64, Size::Byte));
+                        ins(&format!("cmp{}", packed), operands)
+                    }
+                }
+            }
+            0xc6 => {
+                let packed = if self.sse_prefix() == 0x66 { "pd" } else { "ps" };
+                let m = self.modrm()?;
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins(&format!("shuf{}", packed), vec![imm, self.rm(&m, x), self.reg(&m, x)])
+            }
+            0xc4 => {
+                let kind = self.mmx_or_xmm();
+                let m = self.modrm()?;
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins("pinsrw", vec![imm, self.rm(&m, Kind::Gpr(Size::Dword)), self.reg(&m, kind)])
+            }
+            0xc7 => {
+                let m = self.modrm()?;
+                match (m.reg & 7, m.md) {
+                    (1, 0..=2) => {
+                        let name = if self.rex_w() { "cmpxchg16b" } else { "cmpxchg8b" };
+                        ins(name, vec![self.rm(&m, v)])
+                    }
+                    (6, 3) => ins("rdrand", vec![self.rm(&m, v)]),
+                    (7, 3) => ins("rdseed", vec![self.rm(&m, v)]),
+                    _ => None,
+                }
+            }
+            0xc5 => {
+                let kind = self.mmx_or_xmm();
+                let m = self.modrm()?;
+                let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+                ins("pextrw", vec![imm, self.rm(&m, kind), self.reg(&m, Kind::Gpr(Size::Dword))])
+            }
+            0xc8..=0xcf => ins("bswap", vec![self.register((op & 7) | (self.rex_b() << 3), v)]),
+            0xd6 if self.opsize => {
+                let m = self.modrm()?;
+                ins("movq", vec![self.reg(&m, x), self.rm(&m, x)])
+            }
+            0xd7 => {
+                let kind = self.mmx_or_xmm();
+                let m = self.modrm()?;
+                ins("pmovmskb", vec![self.rm(&m, kind), self.reg(&m, Kind::Gpr(Size::Dword))])
+            }
+            0xe6 => {
+                let name = match self.sse_prefix() {
+                    0xf3 => "cvtdq2pd",
+                    0x66 => "cvttpd2dq",
+                    0xf2 => "cvtpd2dq",
+                    _ => return None,
+                };
+                let m = self.modrm()?;
+                ins(name, vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            0xe7 => {
+                let (name, kind) = if self.sse_prefix() == 0x66 { ("movntdq", x) } else { ("movntq", Kind::Mmx) };
+                let m = self.modrm()?;
+                ins(name, vec![self.reg(&m, kind), self.rm(&m, kind)])
+            }
+            _ => None,
+        }
+    }
+
+    /// The 0x0f 0x38 opcode map.
+    fn three_byte_38(&mut self) -> Option<Decoded> {
+        let op = self.byte()?;
+        let prefix = self.sse_prefix();
+        let m = self.modrm()?;
+        let x = Kind::Xmm;
+        match (prefix, op) {
+            (0xf2, 0xf0 | 0xf1) => {
+                let size = if op == 0xf0 { Size::Byte } else { self.op_size() };
+                let dest = if self.rex_w() { Size::Qword } else { Size::Dword };
+                sized("crc32", vec![self.rm(&m, Kind::Gpr(size)), self.reg(&m, Kind::Gpr(dest))], size)
+            }
+            (_, 0xf0) => ins("movbe", vec![self.rm(&m, Kind::Gpr(self.op_size())), self.reg(&m, Kind::Gpr(self.op_size()))]),
+            (_, 0xf1) => ins("movbe", vec![self.reg(&m, Kind::Gpr(self.op_size())), self.rm(&m, Kind::Gpr(self.op_size()))]),
+            (0x66, _) => {
+                let name = match op {
+                    0x00 => "pshufb",
+                    0x17 => "ptest",
+                    0x1c => "pabsb",
+                    0x1d => "pabsw",
+                    0x1e => "pabsd",
+                    0x29 => "pcmpeqq",
+                    0x2b => "packusdw",
+                    0x30 => "pmovzxbw",
+                    0x31 => "pmovzxbd",
+                    0x33 => "pmovzxwd",
+                    0x37 => "pcmpgtq",
+                    0x38 => "pminsb",
+                    0x39 => "pminsd",
+                    0x3a => "pminuw",
+                    0x3b => "pminud",
+                    0x3c => "pmaxsb",
+                    0x3d => "pmaxsd",
+                    0x3e => "pmaxuw",
+                    0x3f => "pmaxud",
+                    0x40 => "pmulld",
+                    _ => return None,
+                };
+                ins(name, vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            (0, 0x00) => ins("pshufb", vec![self.rm(&m, Kind::Mmx), self.reg(&m, Kind::Mmx)]),
+            _ => None,
+        }
+    }
+
+    /// The 0x0f 0x3a opcode map, whose instructions all take an 8-bit immediate.
+    fn three_byte_3a(&mut self) -> Option<Decoded> {
+        let op = self.byte()?;
+        if self.sse_prefix() != 0x66 {
+            return None;
+        }
+        let m = self.modrm()?;
+        let imm = Operand::Imm(self.imm(1)?, Size::Byte);
+        let x = Kind::Xmm;
+        let gpr = Kind::Gpr(if self.rex_w() { Size::Qword } else { Size::Dword });
+        match op {
+            0x0a => ins("roundss", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x0b => ins("roundsd", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x0f => ins("palignr", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x16 => {
+                let name = if self.rex_w() { "pextrq" } else { "pextrd" };
+                ins(name, vec![imm, self.reg(&m, x), self.rm(&m, gpr)])
+            }
+            0x20 => ins("pinsrb", vec![imm, self.rm(&m, Kind::Gpr(Size::Dword)), self.reg(&m, x)]),
+            0x22 => {
+                let name = if self.rex_w() { "pinsrq" } else { "pinsrd" };
+                ins(name, vec![imm, self.rm(&m, gpr), self.reg(&m, x)])
+            }
+            0x60 => ins("pcmpestrm", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x61 => ins("pcmpestri", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x62 => ins("pcmpistrm", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            0x63 => ins("pcmpistri", vec![imm, self.rm(&m, x), self.reg(&m, x)]),
+            _ => None,
+        }
+    }
+
+    /// VEX-encoded (AVX) instructions. Operands are listed in AT&T order: r/m, vvvv, reg.
+    fn vex_instruction(&mut self, first: u8) -> Option<Decoded> {
+        let (map, w, vvvv, long, pp) = if first == 0xc5 {
+            let byte = self.byte()?;
+            self.rex = 0x40 | ((!byte >> 5) & 4);
+            (1, false, (!byte >> 3) & 0xf, byte & 4 != 0, byte & 3)
+        } else {
+            let byte1 = self.byte()?;
+            let byte2 = self.byte()?;
+            self.rex = 0x40 | ((!byte1 >> 5) & 7) | ((byte2 >> 4) & 8);
+            (byte1 & 0x1f, byte2 & 0x80 != 0, (!byte2 >> 3) & 0xf, byte2 & 4 != 0, byte2 & 3)
+        };
+        self.vex = Some((vvvv, long));
+        let op = self.byte()?;
+        if map == 1 && op == 0x77 {
+            return ins(if long { "vzeroall" } else { "vzeroupper" }, vec![]);
+        }
+        let m = self.modrm()?;
+        let vec_kind = if long { Kind::Ymm } else { Kind::Xmm };
+        let x = Kind::Xmm;
+        let gpr = Kind::Gpr(if w { Size::Qword } else { Size::Dword });
+        // Maps 0x0f 0x3a and a few 0x0f opcodes carry an 8-bit immediate
+        let imm = if map == 3 || (map == 1 && matches!(op, 0x70..=0x73 | 0xc2 | 0xc4..=0xc6)) {
+            Some(Operand::Imm(self.imm(1)?, Size::Byte))
+        } else {
+            None
+        };
+        let three = |name: &str, this: &Self| {
+            ins(name, vec![this.rm(&m, vec_kind), this.vvvv(vec_kind), this.reg(&m, vec_kind)])
+        };
+        let load = |name: &str, this: &Self| ins(name, vec![this.rm(&m, vec_kind), this.reg(&m, vec_kind)]);
+        let store = |name: &str, this: &Self| ins(name, vec![this.reg(&m, vec_kind), this.rm(&m, vec_kind)]);
+        match (map, pp, op) {
+            (1, 0, 0x10) => load("vmovups", self),
+            (1, 0, 0x11) => store("vmovups", self),
+            (1, 1, 0x10) => load("vmovupd", self),
+            (1, 1, 0x11) => store("vmovupd", self),
+            (1, 2 | 3, 0x10 | 0x11) => {
+                let name = if pp == 2 { "vmovss" } else { "vmovsd" };
+                match (&m.mem, op) {
+                    (Some(_), 0x10) => ins(name, vec![self.rm(&m, x), self.reg(&m, x)]),
+                    (Some(_), _) => ins(name, vec![self.reg(&m, x), self.rm(&m, x)]),
+                    (None, _) => ins(name, vec![self.rm(&m, x), self.vvvv(x), self.reg(&m, x)]),
+                }
+            }
+            (1, 0, 0x28) => load("vmovaps", self),
+            (1, 0, 0x29) => store("vmovaps", self),
+            (1, 1, 0x28) => load("vmovapd", self),
+            (1, 1, 0x29) => store("vmovapd", self),
+            (1, 0 | 1, 0x54..=0x57) => {
+                let base = ["vand", "vandn", "vor", "vxor"][(op - 0x54) as usize];
+                three(&format!("{}{}", base, if pp == 0 { "ps" } else { "pd" }), self)
+            }
+            (1, _, 0x58 | 0x59 | 0x5c..=0x5f) => {
+                let base = match op {
+                    0x58 => "vadd",
+                    0x59 => "vmul",
+                    0x5c => "vsub",
+                    0x5d => "vmin",
+                    0x5e => "vdiv",
+                    _ => "vmax",
+                };
+                let packed = ["ps", "pd", "ss", "sd"][pp as usize];
+                three(&format!("{}{}", base, packed), self)
+            }
+            (1, 1, 0x6e) => {
+                let name = if w { "vmovq" } else { "vmovd" };
+                ins(name, vec![self.rm(&m, gpr), self.reg(&m, x)])
+            }
+            (1, 1, 0x7e) => {
+                let name = if w { "vmovq" } else { "vmovd" };
+                ins(name, vec![self.reg(&m, x), self.rm(&m, gpr)])
+            }
+            (1, 2, 0x7e) => ins("vmovq", vec![self.rm(&m, x), self.reg(&m, x)]),
+            (1, 1, 0xd6) => ins("vmovq", vec![self.reg(&m, x), self.rm(&m, x)]),
+            (1, 0 | 1, 0x14 | 0x15) => {
+                let base = if op == 0x14 { "vunpckl" } else { "vunpckh" };
+                three(&format!("{}{}", base, if pp == 0 { "ps" } else { "pd" }), self)
+            }
+            (1, 0 | 1, 0x2e | 0x2f) => {
+                let base = if op == 0x2e { "vucomi" } else { "vcomi" };
+                ins(&format!("{}{}", base, if pp == 0 { "ss" } else { "sd" }), vec![self.rm(&m, x), self.reg(&m, x)])
+            }
+            (1, 2 | 3, 0x2a) => {
+                let name = if pp == 2 { "vcvtsi2ss" } else { "vcvtsi2sd" };
+                let size = if w { Size::Qword } else { Size::Dword };
+                let name = if m.mem.is_some() { format!("{}{}", name, size.suffix()) } else { name.to_string() };
+                ins(&name, vec![self.rm(&m, gpr), self.vvvv(x), self.reg(&m, x)])
+            }
+            (1, 2 | 3, 0x2c | 0x2d) => {
+                let name = format!(
+                    "vcvt{}{}2si",
+                    if op == 0x2c { "t" } else { "" },
+                    if pp == 2 { "ss" } else { "sd" }
+                );
+                ins(&name, vec![self.rm(&m, x), self.reg(&m, gpr)])
+            }
+            (1, _, 0x51) => three(&format!("vsqrt{}", ["ps", "pd", "ss", "sd"][pp as usize]), self),
+            (1, 2, 0x5a) => three("vcvtss2sd", self),
+            (1, 3, 0x5a) => three("vcvtsd2ss", self),
+            (1, _, 0xc2) => {
+                let packed = ["ps", "pd", "ss", "sd"][pp as usize];
+                let predicate = match &imm {
+                    Some(Operand::Imm(value, _)) => *value as u8,
+                    _ => return None,
+                };
+                let operands = vec![self.rm(&m, vec_kind), self.vvvv(vec_kind), self.reg(&m, vec_kind)];
+                match CMP_PREDICATES.get(predicate as usize) {
+                    Some(name) => ins(&format!("vcmp{}{}", name, packed), operands),
+                    None => {
+                        let mut operands = operands;
+                        operands.insert(0, imm?);
+                        ins(&format!("vcmp{}", packed), operands)
+                    }
+                }
+            }
+            (1, 0, 0xae) if m.mem.is_some() && matches!(m.reg & 7, 2 | 3) => {
+                let name = if m.reg & 7 == 2 { "vldmxcsr" } else { "vstmxcsr" };
+                ins(name, vec![self.rm(&m, x)])
+            }
+            (2, 1, 0x96..=0x9f | 0xa6..=0xaf | 0xb6..=0xbf) => {
+                let order = ["132", "213", "231"][((op >> 4) - 9) as usize];
+                let (base, scalar) = match op & 0xf {
+                    0x6 => ("vfmaddsub", false),
+                    0x7 => ("vfmsubadd", false),
+                    0x8 => ("vfmadd", false),
+                    0x9 => ("vfmadd", true),
+                    0xa => ("vfmsub", false),
+                    0xb => ("vfmsub", true),
+                    0xc => ("vfnmadd", false),
+                    0xd => ("vfnmadd", true),
+                    0xe => ("vfnmsub", false),
+                    _ => ("vfnmsub", true),
+                };
+                let kind = if scalar { x } else { vec_kind };
+                let packed = match (scalar, w) {
+                    (true, true) => "sd",
+                    (true, false) => "ss",
+                    (false, true) => "pd",
+                    (false, false) => "ps",
+                };
+                ins(
+                    &format!("{}{}{}", base, order, packed),
+                    vec![self.rm(&m, kind), self.vvvv(kind), self.reg(&m, kind)],
+                )
+            }
+            (1, 1, 0x6f) => load("vmovdqa", self),
+            (1, 2, 0x6f) => load("vmovdqu", self),
+            (1, 1, 0x7f) => store("vmovdqa", self),
+            (1, 2, 0x7f) => store("vmovdqu", self),
+            (1, 1, 0xe7) => store("vmovntdq", self),
+            (1, 1, 0x70) => ins("vpshufd", vec![imm?, self.rm(&m, vec_kind), self.reg(&m, vec_kind)]),
+            (1, 1, 0xd7) => ins("vpmovmskb", vec![self.rm(&m, vec_kind), self.reg(&m, Kind::Gpr(Size::Dword))]),
+            (1, 1, 0x73) => {
+                let name = match m.reg & 7 {
+                    2 => "vpsrlq",
+                    3 => "vpsrldq",
+                    6 => "vpsllq",
+                    7 => "vpslldq",
+                    _ => return None,
+                };
+                ins(name, vec![imm?, self.rm(&m, vec_kind), self.vvvv(vec_kind)])
+            }
+            (1, 1, _) => {
+                let name = match op {
+                    0x60 => "vpunpcklbw",
+                    0x61 => "vpunpcklwd",
+                    0x62 => "vpunpckldq",
+                    0x64 => "vpcmpgtb",
+                    0x65 => "vpcmpgtw",
+                    0x66 => "vpcmpgtd",
+                    0x67 => "vpackuswb",
+                    0x68 => "vpunpckhbw",
+                    0x6c => "vpunpcklqdq",
+                    0x6d => "vpunpckhqdq",
+                    0x74 => "vpcmpeqb",
+                    0x75 => "vpcmpeqw",
+                    0x76 => "vpcmpeqd",
+                    0xd4 => "vpaddq",
+                    0xda => "vpminub",
+                    0xdb => "vpand",
+                    0xde => "vpmaxub",
+                    0xdf => "vpandn",
+                    0xeb => "vpor",
+                    0xef => "vpxor",
+                    0xf8 => "vpsubb",
+                    0xf9 => "vpsubw",
+                    0xfa => "vpsubd",
+                    0xfb => "vpsubq",
+                    0xfc => "vpaddb",
+                    0xfd => "vpaddw",
+                    0xfe => "vpaddd",
+                    _ => return None,
+                };
+                three(name, self)
+            }
+            (2, 1, 0x18 | 0x58 | 0x59 | 0x78 | 0x79) => {
+                let name = match op {
+                    0x18 => "vbroadcastss",
+                    0x58 => "vpbroadcastd",
+                    0x59 => "vpbroadcastq",
+                    0x78 => "vpbroadcastb",
+                    _ => "vpbroadcastw",
+                };
+                ins(name, vec![self.rm(&m, x), self.reg(&m, vec_kind)])
+            }
+            (2, 1, 0x17) => load("vptest", self),
+            (2, 1, _) => {
+                let name = match op {
+                    0x00 => "vpshufb",
+                    0x29 => "vpcmpeqq",
+                    0x37 => "vpcmpgtq",
+                    0x38 => "vpminsb",
+                    0x39 => "vpminsd",
+                    0x3a => "vpminuw",
+                    0x3b => "vpminud",
+                    0x3c => "vpmaxsb",
+                    0x3d => "vpmaxsd",
+                    0x3e => "vpmaxuw",
+                    0x3f => "vpmaxud",
+                    0xf7 => return ins("shlx", vec![self.vvvv(gpr), self.rm(&m, gpr), self.reg(&m, gpr)]),
+                    _ => return None,
+                };
+                three(name, self)
+            }
+            (2, 0, 0xf2) => ins("andn", vec![self.rm(&m, gpr), self.vvvv(gpr), self.reg(&m, gpr)]),
+            (2, 0, 0xf3) => {
+                let name = match m.reg & 7 {
+                    1 => "blsr",
+                    2 => "blsmsk",
+                    3 => "blsi",
+                    _ => return None,
+                };
+                ins(name, vec![self.rm(&m, gpr), self.vvvv(gpr)])
+            }
+            (2, 0, 0xf5) => ins("bzhi", vec![self.vvvv(gpr), self.rm(&m, gpr), self.reg(&m, gpr)]),
+            (2, 2, 0xf5) => ins("pext", vec![self.rm(&m, gpr), self.vvvv(gpr), self.reg(&m, gpr)]),
+            (2, 3, 0xf5) => ins("pdep", vec![self.rm(&m, gpr), self.vvvv(gpr), self.reg(&m, gpr)]),
+            (2, 2, 0xf7) => ins("sarx", vec![self.vvvv(gpr), self.rm(&m, gpr), self.reg(&m, gpr)]),
+            (2, 3, 0xf7) => ins("shrx", vec![self.vvvv(gpr), self.rm(&m, gpr), self.reg(&m, gpr)]),
+            (3, 1, 0x0f) => ins("vpalignr", vec![imm?, self.rm(&m, vec_kind), self.vvvv(vec_kind), self.reg(&m, vec_kind)]),
+            (3, 1, 0x38) => ins("vinserti128", vec![imm?, self.rm(&m, x), self.vvvv(vec_kind), self.reg(&m, vec_kind)]),
+            (3, 1, 0x39) => ins("vextracti128", vec![imm?, self.reg(&m, vec_kind), self.rm(&m, x)]),
+            (3, 1, 0x46) => ins("vperm2i128", vec![imm?, self.rm(&m, vec_kind), self.vvvv(vec_kind), self.reg(&m, vec_kind)]),
+            (3, 1, 0x63) => ins("vpcmpistri", vec![imm?, self.rm(&m, x), self.reg(&m, x)]),
+            _ => None,
+        }
+    }
+
+    /// Renders a decoded instruction that ends at `end`.
+    fn format<F>(&self, decoded: Decoded, end: usize, symbolize: &F) -> String
+    where
+        F: Fn(usize) -> Option<String>,
+    {
+        let mut mnemonic = decoded.mnemonic;
+        if let Some(size) = decoded.suffix
+            && !decoded.operands.iter().any(|operand| matches!(operand, Operand::Reg(_)))
+        {
+            mnemonic.push(size.suffix());
+        }
+        let mut prefixes = String::new();
+        // A 0x66 is redundant if it's repeated or REX.W overrides it
+        let redundant = self.opsize && self.rex_w() && !self.opsize_used && self.vex.is_none();
+        for _ in (if redundant { 0 } else { 1 })..self.opsize_count {
+            prefixes.push_str("data16 ");
+        }
+        let indirect = decoded.operands.iter().any(|operand| matches!(operand, Operand::Indirect(_)));
+        if self.segment == Some("ds") && indirect {
+            prefixes.push_str("notrack ");
+        } else if let Some(segment) = self.segment.filter(|segment| !matches!(*segment, "fs" | "gs"))
+            && !mnemonic.starts_with("movs") && !mnemonic.starts_with("cmps") && mnemonic != "lods"
+        {
+            prefixes.push_str(segment);
+            prefixes.push(' ');
+        }
+        if self.lock {
+            prefixes.push_str("lock ");
+        }
+        if let (Some(rep), false) = (self.rep, self.rep_used) {
+            let branch = matches!(mnemonic.as_str(), "call" | "jmp" | "ret") || mnemonic.starts_with('j');
+            let compares = mnemonic.starts_with("cmps") || mnemonic.starts_with("scas");
+            prefixes.push_str(match (rep, branch, compares) {
+                (0xf2, true, _) => "bnd ",
+                (0xf2, _, _) => "repnz ",
+                (_, _, true) => "repz ",
+                (_, false, false) if mnemonic.starts_with("movs") || mnemonic == "stos" || mnemonic == "lods" => "rep ",
+                _ => "repz ",
+            });
+        }
+        let mut comment = None;
+        let operands: Vec<String> = decoded
+            .operands
+            .iter()
+            .map(|operand| self.format_operand(operand, end, symbolize, &mut comment))
+            .collect();
+        let mut text = if operands.is_empty() {
+            format!("{}{}", prefixes, mnemonic)
+        } else {
+            format!("{}{:<6} {}", prefixes, mnemonic, operands.join(","))
+        };
+        if let Some(comment) = comment {
+            text.push_str("        # ");
+            text.push_str(&comment);
+        }
+        text
+    }
+
+    fn format_operand<F>(&self, operand: &Operand, end: usize, symbolize: &F, comment: &mut Option<String>) -> String
+    where
+        F: Fn(usize) -> Option<String>,
+    {
+        match operand {
+            Operand::Reg(name) => format!("%{}", name),
+            Operand::Imm(value, size) => {
+                let mask = if *size == Size::Qword { u64::MAX } else { (1u64 << (8 * size.bytes())) - 1 };
+                format!("$0x{:x}", (*value as u64) & mask)
+            }
+            Operand::Target(offset) => {
+                let target = (end as i64 - self.pos as i64 + offset) as usize;
+                format_address(target, symbolize)
+            }
+            Operand::Indirect(inner) => format!("*{}", self.format_operand(inner, end, symbolize, comment)),
+            Operand::Mem(mem) => {
+                let mut text = String::new();
+                if let Some(segment) = mem.segment {
+                    text.push_str(&format!("%{}:", segment));
+                }
+                let disp = if mem.disp < 0 {
+                    format!("-0x{:x}", -(mem.disp as i128))
+                } else {
+                    format!("0x{:x}", mem.disp)
+                };
+                if mem.rip {
+                    let target = (end as i64 + mem.disp) as usize;
+                    *comment = Some(format_address(target, symbolize));
+                    text.push_str(&format!("{}(%rip)", disp));
+                    return text;
+                }
+                if mem.has_disp {
+                    text.push_str(&disp);
+                }
+                if mem.base.is_some() || mem.index.is_some() {
+                    text.push('(');
+                    if let Some(base) = mem.base {
+                        text.push_str(&format!("%{}", base));
+                    }
+                    if let Some((index, scale)) = mem.index {
+                        text.push_str(&format!(",%{},{}", index, scale));
+                    }
+                    text.push(')');
+                }
+                text
+            }
+        }
+    }
+}
+
+/// Formats an address as gdb shows branch targets: `0x401126 <add+4>`.
+pub fn format_address<F>(addr: usize, symbolize: &F) -> String
+where
+    F: Fn(usize) -> Option<String>,
+{
+    match symbolize(addr) {
+        Some(symbol) => format!("{:#x} <{}>", addr, symbol),
+        None => format!("{:#x}", addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `bytes` at 0x401000, where 0x402000 is `counter`, and checks the text and length.
+    fn check(bytes: &[u8], text: &str) {
+        let symbolize = |addr: usize| (addr == 0x402000).then(|| "counter".to_string());
+        let instruction = decode(bytes, 0x401000, symbolize);
+        assert_eq!(instruction.text, text);
+        assert_eq!(instruction.len, bytes.len());
+    }
+
+    #[test]
+    fn rex_prefixes() {
+        check(&[0x48, 0x89, 0xe5], "mov    %rsp,%rbp");
+        check(&[0x44, 0x89, 0xc0], "mov    %r8d,%eax");
+        check(&[0x42, 0x8b, 0x04, 0xa8], "mov    (%rax,%r13,4),%eax");
+    }
+
+    #[test]
+    fn operand_size_and_mandatory_prefixes() {
+        check(&[0x66, 0x89, 0x07], "mov    %ax,(%rdi)");
+        check(&[0x66, 0x0f, 0xef, 0xc1], "pxor   %xmm1,%xmm0");
+        check(&[0xf3, 0x0f, 0x1e, 0xfa], "endbr64");
+    }
+
+    #[test]
+    fn vex_prefixes() {
+        check(&[0xc5, 0xf8, 0x77], "vzeroupper");
+        check(&[0xc5, 0xfd, 0x6f, 0x06], "vmovdqa (%rsi),%ymm0");
+        check(&[0xc4, 0xc1, 0x7a, 0x10, 0x01], "vmovss (%r9),%xmm0");
+    }
+
+    #[test]
+    fn modrm_and_sib() {
+        check(&[0x8b, 0x44, 0x24, 0x08], "mov    0x8(%rsp),%eax");
+        check(&[0x8b, 0x04, 0x8d, 0x00, 0x00, 0x00, 0x00], "mov    0x0(,%rcx,4),%eax");
+    }
+
+    #[test]
+    fn rip_relative_operands() {
+        check(&[0x48, 0x8b, 0x05, 0xf9, 0x0f, 0x00, 0x00], "mov    0xff9(%rip),%rax        # 0x402000 <counter>");
+        check(&[0x4c, 0x8d, 0x1d, 0xfc, 0x0f, 0x00, 0x00], "lea    0xffc(%rip),%r11        # 0x402003");
+        check(
+            &[0xf2, 0x0f, 0x10, 0x05, 0xf8, 0x0f, 0x00, 0x00],
+            "movsd  0xff8(%rip),%xmm0        # 0x402000 <counter>",
+        );
+        check(
+            &[0xc4, 0xe2, 0x79, 0x18, 0x05, 0x10, 0x00, 0x00, 0x00],
+            "vbroadcastss 0x10(%rip),%xmm0        # 0x401019",
+        );
+        check(&[0xe8, 0xfb, 0x0f, 0x00, 0x00], "call   0x402000 <counter>");
+    }
+
+    #[test]
+    fn invalid_opcodes_are_bad() {
+        check(&[0x06], "(bad)");
+    }
+
+    #[test]
+    fn truncated_instructions_are_bad() {
+        let instruction = decode(&[0x48, 0x8b, 0x05, 0xf9], 0x401000, |_| None);
+        assert_eq!(instruction.text, "(bad)");
+        assert_eq!(instruction.len, 1);
+        let instruction = decode(&[], 0x401000, |_| None);
+        assert_eq!(instruction.text, "(bad)");
+        assert_eq!(instruction.len, 1);
+    }
+}
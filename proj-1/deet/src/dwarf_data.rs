@@ -1,21 +1,64 @@
+use crate::debug_file;
 use crate::gimli_wrapper;
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection, ObjectSymbol, ObjectSymbolTable, SymbolIndex, SymbolKind};
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::convert::TryInto;
 use std::{fmt, fs};
 
+/// Size of an x86-64 PLT entry and of an `Elf64_Rela`.
+const PLT_ENTRY_SIZE: usize = 16;
+const RELA_SIZE: usize = 24;
+/// Like gdb, long arrays and strings are cut off after this many elements.
+const PRINT_ELEMENTS_LIMIT: usize = 200;
+/// Runs of more than this many identical array elements are shown as `<repeats N times>`.
+const REPEAT_COUNT_THRESHOLD: usize = 10;
+
+/// addr2line's lookup tables, over DWARF sections it keeps alive itself.
+type Addr2line = Context<gimli::EndianRcSlice<gimli::RunTimeEndian>>;
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
     DwarfFormatError(gimli_wrapper::Error),
 }
 
-pub struct DwarfData<'a> {
+/// Why a breakpoint location couldn't be found.
+#[derive(Debug)]
+pub enum LocationError {
+    /// Nothing by that name is loaded, though a library loaded later might define it.
+    NotFound(String),
+    /// The location can never be found, e.g. a line past the end of the file.
+    Invalid(String),
+}
+
+impl fmt::Display for LocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocationError::NotFound(message) | LocationError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub struct DwarfData {
     files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<'a, addr2line::gimli::RunTimeEndian>>,
+    /// Every type in the debug info, keyed by the offset of its DIE in `.debug_info`.
+    types: HashMap<usize, Type>,
+    /// Function symbols from the ELF symbol tables, for code without debug info.
+    symbols: Vec<Symbol>,
+    /// None if the DWARF couldn't be read, leaving just the symbols.
+    addr2line: Option<Addr2line>,
+    /// The entry point the program was linked with.
+    entry: usize,
+    /// How far the program was loaded from the addresses it was linked at, which is only zero
+    /// for position-dependent executables. Every address we hand out includes it.
+    bias: usize,
+    /// The separate file the debug info came from, if the program was stripped.
+    debug_file: Option<String>,
 }
 
-impl fmt::Debug for DwarfData<'_> {
+impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "DwarfData {{files: {:?}}}", self.files)
     }
@@ -27,59 +70,213 @@ impl From<gimli_wrapper::Error> for Error {
     }
 }
 
-impl DwarfData<'_> {
-    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+impl DwarfData {
+    /// Loads the debug info of the program at `path`. If it was stripped, the debug info is
+    /// looked for in a separate file under `debug_dirs` (see `debug_file`).
+    pub fn from_file(path: &str, debug_dirs: &[String]) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::read::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+            .map_err(|e| gimli_wrapper::Error::Object(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let debug_file = match object.section_by_name(".debug_info") {
+            Some(_) => None,
+            None => debug_file::find(&object, path, debug_dirs),
+        };
+        let debug_mmap = match &debug_file {
+            Some(debug_file) => {
+                let file = fs::File::open(debug_file).or(Err(Error::ErrorOpeningFile))?;
+                Some(unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? })
+            }
+            None => None,
+        };
+        let debug_object = match &debug_mmap {
+            Some(debug_mmap) => Some(
+                object::read::File::parse(&**debug_mmap)
+                    .map_err(|e| gimli_wrapper::Error::Object(e.to_string()))?,
+            ),
+            None => None,
+        };
+        // Split DWARF files are found relative to the program, even when its skeletons are in
+        // the separate debug file
+        let (files, types, addr2line) = read_debug_info(debug_object.as_ref().unwrap_or(&object), endian, path);
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            // addr2line: Context::from_dwarf().or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files,
+            types,
+            symbols: load_symbols(std::iter::once(&object).chain(debug_object.as_ref())),
+            addr2line,
+            entry: object.entry() as usize,
+            bias: 0,
+            debug_file,
         })
     }
 
+    /// Debug data with no files, types or symbols, for tests that don't need a program.
+    #[cfg(test)]
+    pub fn empty() -> DwarfData {
+        DwarfData {
+            files: Vec::new(),
+            types: HashMap::new(),
+            symbols: Vec::new(),
+            addr2line: None,
+            entry: 0,
+            bias: 0,
+            debug_file: None,
+        }
+    }
+
+    /// Adds a type as if it had been read from the debug info, and returns its id.
+    #[cfg(test)]
+    pub fn add_type(&mut self, ty: Type) -> usize {
+        let id = self.types.len() + 1;
+        self.types.insert(id, ty);
+        id
+    }
+
+    /// Loads the debug info of the program at `path` from `debug_file` instead, as the
+    /// `symbol-file` command does, keeping the function symbols of both.
+    pub fn from_debug_file(path: &str, debug_file: &str) -> Result<DwarfData, Error> {
+        let mut debug_data = DwarfData::from_file(debug_file, &[])?;
+        if let Ok(program) = DwarfData::from_file(path, &[]) {
+            debug_data.symbols.extend(program.symbols);
+            sort_symbols(&mut debug_data.symbols);
+            debug_data.entry = program.entry;
+        }
+        debug_data.debug_file = Some(debug_file.to_string());
+        Ok(debug_data)
+    }
+
+    /// The separate file the debug info was read from, if it wasn't in the program itself.
+    pub fn debug_file(&self) -> Option<&str> {
+        self.debug_file.as_deref()
+    }
+
+    /// The entry point the program was linked with, for working out where it was loaded.
+    pub fn entry_point(&self) -> usize {
+        self.entry
+    }
+
+    /// Returns true if there are any compilation units, i.e. the file wasn't stripped.
+    pub fn has_debug_info(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.bias
+    }
+
+    /// Moves every address to where the program was loaded, `bias` bytes from where it was
+    /// linked.
+    pub fn relocate(&mut self, bias: usize) {
+        let delta = bias.wrapping_sub(self.bias);
+        if delta == 0 {
+            return;
+        }
+        let relocate_variable = |var: &mut Variable| match &mut var.location {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::List(entries) => {
+                for entry in entries.iter_mut() {
+                    entry.begin = entry.begin.wrapping_add(delta);
+                    entry.end = entry.end.wrapping_add(delta);
+                }
+            }
+            _ => {}
+        };
+        for file in self.files.iter_mut() {
+            file.global_variables.iter_mut().for_each(relocate_variable);
+            for func in file.functions.iter_mut() {
+                // Declarations of functions from elsewhere have no address
+                if func.address != 0 {
+                    func.address = func.address.wrapping_add(delta);
+                }
+                func.variables.iter_mut().for_each(relocate_variable);
+                for call_site in func.call_sites.iter_mut() {
+                    call_site.return_address = call_site.return_address.wrapping_add(delta);
+                }
+            }
+            for line in file.lines.iter_mut() {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+        for symbol in self.symbols.iter_mut() {
+            symbol.address = symbol.address.wrapping_add(delta);
+        }
+        self.bias = bias;
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+        self.get_target_files(file).next()
+    }
+
+    /// Every compilation unit `file` could refer to: a full path matches only itself, while a
+    /// relative one like `foo.c` or `lib/foo.c` matches it in any directory.
+    fn get_target_files(&self, file: &str) -> impl Iterator<Item = &File> {
+        let (file, suffix) = (file.to_string(), format!("/{}", file));
+        self.files
+            .iter()
+            .filter(move |f| f.name == file || (!file.starts_with('/') && f.name.ends_with(&suffix)))
+    }
+
+    /// Resolves a (possibly abbreviated) source file name to the full path of its compilation
+    /// unit.
+    pub fn get_file_path(&self, file: &str) -> Option<String> {
+        Some(self.get_target_file(file)?.name.clone())
     }
 
+    /// Finds the code for `line_number`, or for the next line that has any if it has none (e.g.
+    /// a blank line). Without a file, looks in the compilation unit that defines `main`.
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+            None => self.get_main_file()?,
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        Some(Self::get_line_at_or_after(target_file, line_number)?.address)
     }
 
-    #[allow(dead_code)]
+    /// The first row for `line_number` in `file`'s line table, or for the next line after it that
+    /// has one.
+    fn get_line_at_or_after(file: &File, line_number: usize) -> Option<&Line> {
+        let number = file
+            .lines
+            .iter()
+            .map(|line| line.number)
+            .filter(|&number| number >= line_number)
+            .min()?;
+        file.lines
+            .iter()
+            .filter(|line| line.number == number)
+            .min_by_key(|line| line.address)
+    }
+
+    /// The compilation unit that defines `main`, or the first one if none does.
+    fn get_main_file(&self) -> Option<&File> {
+        self.files
+            .iter()
+            .find(|file| file.functions.iter().any(|func| func.name == "main" && func.address != 0))
+            .or_else(|| self.files.first())
+    }
+
+    /// Finds where function `func_name` is defined. Declarations of functions defined elsewhere,
+    /// e.g. in a shared library, have no address and don't count.
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        let is_match = |func: &&Function| func.name == func_name && func.address != 0;
         match file {
             Some(filename) => Some(
                 self.get_target_file(filename)?
                     .functions
                     .iter()
-                    .find(|func| func.name == func_name)?
+                    .find(is_match)?
                     .address,
             ),
             None => {
                 for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
+                    if let Some(func) = file.functions.iter().find(is_match) {
                         return Some(func.address);
                     }
                 }
@@ -88,11 +285,72 @@ impl DwarfData<'_> {
         }
     }
 
+    /// Finds every place a `[file:]line` or `[file:]function` breakpoint location could refer
+    /// to, as the line where execution would stop: a static function may be defined in several
+    /// files, and a file name in several directories. Bare line numbers refer to `default_file`,
+    /// or else to the file that defines `main`.
+    pub fn get_locations(&self, spec: &str, default_file: Option<&str>) -> Result<Vec<Line>, LocationError> {
+        let (file, location) = match spec.rsplit_once(':') {
+            Some((file, location)) => (Some(file), location),
+            None => (None, spec),
+        };
+        let line_number = location.parse::<usize>().ok();
+        let files: Vec<&File> = match file.or(default_file.filter(|_| line_number.is_some())) {
+            Some(file) => {
+                let files: Vec<&File> = self.get_target_files(file).collect();
+                if files.is_empty() {
+                    return Err(LocationError::NotFound(format!("No source file named {}.", file)));
+                }
+                files
+            }
+            None if line_number.is_some() => self.get_main_file().into_iter().collect(),
+            None => self.files.iter().collect(),
+        };
+        if let Some(number) = line_number {
+            let locations: Vec<Line> = files
+                .iter()
+                .filter_map(|file| Self::get_line_at_or_after(file, number).cloned())
+                .collect();
+            if locations.is_empty() {
+                return Err(LocationError::Invalid(format!("Line {} out of range.", number)));
+            }
+            return Ok(locations);
+        }
+        let locations: Vec<Line> = files
+            .iter()
+            .flat_map(|file| {
+                file.functions
+                    .iter()
+                    .filter(|func| func.name == location && func.address != 0)
+                    .map(move |func| Line {
+                        file: file.name.clone(),
+                        number: func.line_number,
+                        // Stop after the prologue so that arguments can be read (and conditions
+                        // tested)
+                        address: self.skip_prologue(func.address),
+                    })
+            })
+            .collect();
+        // Functions without debug info can still be found by their symbols
+        let symbol = self.symbols.iter().find(|symbol| symbol.name == location).filter(|_| file.is_none());
+        if let (true, Some(symbol)) = (locations.is_empty(), symbol) {
+            return Ok(vec![Line { file: String::new(), number: 0, address: symbol.address }]);
+        }
+        if locations.is_empty() {
+            return Err(LocationError::NotFound(match file {
+                Some(file) => format!("Function \"{}\" not defined in \"{}\".", location, file),
+                None => format!("Function \"{}\" not defined.", location),
+            }));
+        }
+        Ok(locations)
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .as_ref()?
+            .find_location(curr_addr.wrapping_sub(self.bias).try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -101,17 +359,271 @@ impl DwarfData<'_> {
         })
     }
 
+    /// Names the function containing `curr_addr`, from the symbol table if there's no debug
+    /// info for it.
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
+        self.get_debug_function_name(curr_addr)
+            .or_else(|| Some(self.get_symbol_containing(curr_addr)?.name.clone()))
+    }
+
+    fn get_debug_function_name(&self, curr_addr: usize) -> Option<String> {
         let frame = self
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
+            .as_ref()?
+            .find_frames(curr_addr.wrapping_sub(self.bias).try_into().unwrap())
+            .skip_all_loads()
             .ok()?
             .next()
             .ok()??;
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    /// Returns the function symbol whose code contains `addr`.
+    pub fn get_symbol_containing(&self, addr: usize) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.address <= addr && addr < symbol.address + symbol.size.max(1))
+    }
+
+    /// Returns the function whose text contains `curr_addr`.
+    pub fn get_function_containing(&self, curr_addr: usize) -> Option<&Function> {
+        self.files.iter().flat_map(|file| file.functions.iter()).find(|func| {
+            func.address <= curr_addr && curr_addr < func.address + func.text_length
+        })
+    }
+
+    /// Names the function or global variable containing `addr`, as `name` or `name+offset`.
+    pub fn describe_address(&self, addr: usize) -> Option<String> {
+        let symbol = || self.get_symbol_containing(addr).map(|symbol| (&symbol.name, symbol.address));
+        let (name, start) = match self.get_function_containing(addr) {
+            Some(func) => (&func.name, func.address),
+            None => symbol().or_else(|| self
+                .files
+                .iter()
+                .flat_map(|file| file.global_variables.iter())
+                .find_map(|var| match var.location {
+                    Location::Address(start)
+                        if start <= addr && addr < start + var.entity_type.size.max(1) =>
+                    {
+                        Some((&var.name, start))
+                    }
+                    _ => None,
+                }))?,
+        };
+        Some(if addr == start {
+            name.clone()
+        } else {
+            format!("{}+{}", name, addr - start)
+        })
+    }
+
+    /// If `addr` is the entry point of a function, returns the address of its second line table
+    /// row, i.e. the first instruction after the prologue has set up the frame. Otherwise returns
+    /// `addr` unchanged.
+    pub fn skip_prologue(&self, addr: usize) -> usize {
+        let func = match self.get_function_containing(addr) {
+            Some(func) if func.address == addr => func,
+            _ => return addr,
+        };
+        self.files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|&line_addr| line_addr > addr && line_addr < func.address + func.text_length)
+            .min()
+            .unwrap_or(addr)
+    }
+
+    /// Returns the line table row that begins exactly at `addr`, if any.
+    pub fn get_line_starting_at(&self, addr: usize) -> Option<&Line> {
+        self.files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .find(|line| line.address == addr)
+    }
+
+    /// Looks up `name` the way C scoping would from `curr_addr`: locals and parameters of the
+    /// enclosing function first, then globals.
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<&Variable> {
+        self.get_function_containing(curr_addr)
+            .and_then(|func| func.variables.iter().find(|var| var.name == name))
+            .or_else(|| self.get_global_variable(name))
+    }
+
+    /// Looks up a global variable by name across all compilation units.
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Looks up a type by the offset of its DIE.
+    pub fn get_type(&self, id: usize) -> Option<&Type> {
+        self.types.get(&id)
+    }
+
+    /// Looks up a type by its C name, e.g. `int`, `struct point *` or the name of a typedef,
+    /// and returns its DIE offset. Complete definitions are preferred over forward declarations.
+    pub fn find_type_id(&self, name: &str) -> Option<usize> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.types
+            .iter()
+            .filter(|(_, ty)| ty.name == name)
+            .max_by_key(|(id, ty)| (ty.size, usize::MAX - **id))
+            .map(|(&id, _)| id)
+    }
+
+    /// Looks up a type by its C name.
+    pub fn find_type(&self, name: &str) -> Option<&Type> {
+        self.types.get(&self.find_type_id(name)?)
+    }
+
+    /// Makes the type of a pointer to the type with DIE offset `target`, or `void *`.
+    pub fn pointer_type(&self, target: Option<usize>) -> Type {
+        let mut pointer = Type::with_encoding(String::new(), 8, Encoding::Pointer);
+        pointer.kind = TypeKind::Pointer(target);
+        pointer.name = type_name(&self.types, &pointer);
+        pointer
+    }
+
+    /// Finds the enumeration type that has an enumerator called `name`, and its value.
+    pub fn find_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.types.values().find_map(|ty| match &ty.kind {
+            TypeKind::Enum(enumerators) => enumerators
+                .iter()
+                .find(|(enumerator, _)| enumerator == name)
+                .map(|(_, value)| (ty, *value)),
+            _ => None,
+        })
+    }
+
+    /// Looks through typedefs and `const`/`volatile` to the type that determines how a value is
+    /// laid out.
+    pub fn strip_typedefs<'s>(&'s self, mut ty: &'s Type) -> &'s Type {
+        while let TypeKind::Typedef(Some(target)) | TypeKind::Qualified(_, Some(target)) = ty.kind {
+            match self.types.get(&target) {
+                Some(target) => ty = target,
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Finds the member called `name` of a struct or union, also looking inside anonymous
+    /// members. Returns its offset from the start of the aggregate, and its type.
+    pub fn find_member(&self, ty: &Type, name: &str) -> Option<(usize, &Type)> {
+        let members = match &self.strip_typedefs(ty).kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => members,
+            _ => return None,
+        };
+        for member in members {
+            let member_type = match self.types.get(&member.type_id) {
+                Some(member_type) => member_type,
+                None => continue,
+            };
+            if member.name == name {
+                return Some((member.offset, member_type));
+            }
+            if member.name.is_empty()
+                && let Some((offset, found)) = self.find_member(member_type, name)
+            {
+                return Some((member.offset + offset, found));
+            }
+        }
+        None
+    }
+
+    /// Renders a value of type `ty` the way gdb's `print` does, with structs and arrays shown
+    /// member by member. `read` reads inferior memory, which is needed to show the strings that
+    /// `char *` values point to.
+    pub fn format_value<F>(&self, ty: &Type, bytes: &[u8], read: &F) -> String
+    where
+        F: Fn(usize, usize) -> Option<Vec<u8>>,
+    {
+        let ty = self.strip_typedefs(ty);
+        match &ty.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = match self.types.get(&member.type_id) {
+                            Some(member_type) => {
+                                let start = member.offset.min(bytes.len());
+                                let end = (member.offset + member_type.size).min(bytes.len());
+                                self.format_value(member_type, &bytes[start..end], read)
+                            }
+                            None => "<unknown type>".to_string(),
+                        };
+                        if member.name.is_empty() {
+                            value
+                        } else {
+                            format!("{} = {}", member.name, value)
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array(element, _) => {
+                let element = match self.types.get(element) {
+                    Some(element) => element,
+                    None => return "<unknown type>".to_string(),
+                };
+                if is_char(self.strip_typedefs(element)) {
+                    let bytes = match bytes.split_last() {
+                        // The terminating NUL isn't shown
+                        Some((0, rest)) => rest,
+                        _ => bytes,
+                    };
+                    return format_chars(bytes);
+                }
+                if element.size == 0 {
+                    return "{}".to_string();
+                }
+                let values: Vec<String> = bytes
+                    .chunks_exact(element.size)
+                    .map(|chunk| self.format_value(element, chunk, read))
+                    .collect();
+                let mut items = Vec::new();
+                let mut index = 0;
+                while index < values.len() && items.len() < PRINT_ELEMENTS_LIMIT {
+                    let repeats = values[index..].iter().take_while(|value| **value == values[index]).count();
+                    if repeats > REPEAT_COUNT_THRESHOLD {
+                        items.push(format!("{} <repeats {} times>", values[index], repeats));
+                        index += repeats;
+                    } else {
+                        items.push(values[index].clone());
+                        index += 1;
+                    }
+                }
+                let ellipsis = if index < values.len() { "..." } else { "" };
+                format!("{{{}{}}}", items.join(", "), ellipsis)
+            }
+            TypeKind::Pointer(target) => {
+                let value = ty.format_value(bytes);
+                let addr = usize::from_str_radix(value.trim_start_matches("0x"), 16).unwrap_or(0);
+                let target = target.and_then(|target| self.types.get(&target));
+                match target.map(|target| self.strip_typedefs(target)) {
+                    _ if addr == 0 => value,
+                    Some(target) if is_char(target) => format!("{} {}", value, read_string(addr, read)),
+                    _ => match self.describe_address(addr) {
+                        Some(symbol) => format!("{} <{}>", value, symbol),
+                        None => value,
+                    },
+                }
+            }
+            _ => ty.format_value(bytes),
+        }
+    }
+
+    /// Writes out a type the way `ptype` shows it: typedefs are looked through, and the members
+    /// of structs, unions and enums are listed.
+    pub fn type_declaration(&self, ty: &Type) -> String {
+        let (prefix, suffix) = declarator(&self.types, ty, true, 0);
+        format!("{}{}", prefix, suffix).trim_end().to_string()
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
@@ -149,32 +661,336 @@ impl DwarfData<'_> {
     }
 }
 
+/// How the bytes of a value should be interpreted, taken from DW_AT_encoding for base types.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Unknown,
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+    Pointer,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub encoding: Encoding,
+    pub kind: TypeKind,
+}
+
+/// How a type is built. Other types are referred to by the offset of their DIE, which is the
+/// key to look them up with `DwarfData::get_type`.
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    #[default]
+    Base,
+    /// A pointer to the given type, or `void *`.
+    Pointer(Option<usize>),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// The element type and the number of elements, if known. Arrays with several dimensions
+    /// are arrays of arrays.
+    Array(usize, Option<usize>),
+    /// The names and values of the enumerators.
+    Enum(Vec<(String, i64)>),
+    /// A typedef of the given type, or of `void`.
+    Typedef(Option<usize>),
+    /// `const`, `volatile` or `restrict` applied to the given type, or to `void`.
+    Qualified(&'static str, Option<usize>),
+    /// The return type (`None` for void) and the parameter types.
+    Function(Option<usize>, Vec<usize>),
+}
+
+/// A member of a struct or union. Anonymous structs and unions nested in another one are
+/// members without a name.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub type_id: usize,
+    pub offset: usize,
 }
 
 impl Type {
+    #[allow(dead_code)]
     pub fn new(name: String, size: usize) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            encoding: Encoding::Unknown,
+            kind: TypeKind::Base,
         }
     }
+
+    pub fn with_encoding(name: String, size: usize, encoding: Encoding) -> Self {
+        Type {
+            name,
+            size,
+            encoding,
+            kind: TypeKind::Base,
+        }
+    }
+
+    /// Renders the raw (little-endian) bytes of a value of this type the way gdb would.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        let mut buf = [0u8; 8];
+        let len = bytes.len().min(8);
+        buf[..len].copy_from_slice(&bytes[..len]);
+        let raw = u64::from_le_bytes(buf);
+        let signed = if len > 0 && len < 8 {
+            let shift = 64 - 8 * len;
+            ((raw << shift) as i64) >> shift
+        } else {
+            raw as i64
+        };
+        if let TypeKind::Enum(enumerators) = &self.kind {
+            let value = if self.encoding == Encoding::Signed { signed } else { raw as i64 };
+            return match enumerators.iter().find(|(_, val)| *val == value) {
+                Some((name, _)) => name.clone(),
+                None => format!("{}", value),
+            };
+        }
+        match self.encoding {
+            Encoding::Signed => format!("{}", signed),
+            Encoding::Unsigned => format!("{}", raw),
+            Encoding::SignedChar | Encoding::UnsignedChar => {
+                let val = if self.encoding == Encoding::SignedChar {
+                    signed
+                } else {
+                    raw as i64
+                };
+                format!("{} '{}'", val, escape_char(raw as u8, '\''))
+            }
+            Encoding::Float => match len {
+                4 => format!("{}", f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])),
+                8 => format!("{}", f64::from_le_bytes(buf)),
+                _ => format!("<unsupported float size {}>", len),
+            },
+            Encoding::Boolean => format!("{}", raw != 0),
+            Encoding::Pointer => format!("{:#x}", raw),
+            Encoding::Unknown => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("<{}>", hex.join(" "))
+            }
+        }
+    }
+}
+
+/// Returns the C name of a type, e.g. `struct node *` or `int [3]`. `types` are all known types,
+/// by DIE offset.
+pub fn type_name(types: &HashMap<usize, Type>, ty: &Type) -> String {
+    let (prefix, suffix) = declarator(types, ty, false, 0);
+    format!("{}{}", prefix, suffix).trim_end().to_string()
+}
+
+/// Splits the C declaration of something of type `ty` into the part that goes before the
+/// declared name and the part that goes after it, e.g. `int (*` and `)[3]` for a pointer to an
+/// array. If `expand` is set, the body of the struct, union or enum the type is built from is
+/// written out, with its members indented by `indent + 4` columns. Anonymous ones are always
+/// written out, since they have no name to refer to them by.
+fn declarator(types: &HashMap<usize, Type>, ty: &Type, expand: bool, indent: usize) -> (String, String) {
+    let target = |id: &Option<usize>| id.and_then(|id| types.get(&id));
+    match &ty.kind {
+        TypeKind::Pointer(pointee) => match target(pointee) {
+            Some(pointee) => {
+                let (prefix, suffix) = declarator(types, pointee, expand, indent);
+                // Pointers to arrays and functions need parentheses to bind first
+                match pointee.kind {
+                    TypeKind::Array(..) | TypeKind::Function(..) => (prefix + "(*", format!("){}", suffix)),
+                    _ => (prefix + "*", suffix),
+                }
+            }
+            None => ("void *".to_string(), String::new()),
+        },
+        TypeKind::Array(element, count) => {
+            let (prefix, suffix) = match types.get(element) {
+                Some(element) => declarator(types, element, expand, indent),
+                None => ("<unknown type> ".to_string(), String::new()),
+            };
+            let count = count.map(|count| count.to_string()).unwrap_or_default();
+            (prefix, format!("[{}]{}", count, suffix))
+        }
+        TypeKind::Function(returns, params) => {
+            let (prefix, suffix) = match target(returns) {
+                Some(returns) => declarator(types, returns, expand, indent),
+                None => ("void ".to_string(), String::new()),
+            };
+            let params: Vec<String> = params
+                .iter()
+                .map(|param| match types.get(param) {
+                    Some(param) => type_name(types, param),
+                    None => "<unknown type>".to_string(),
+                })
+                .collect();
+            let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+            (prefix, format!("({}){}", params, suffix))
+        }
+        TypeKind::Qualified(qualifier, qualified) => match target(qualified) {
+            // A qualified pointer is written `int * const`
+            Some(qualified) if matches!(qualified.kind, TypeKind::Pointer(_)) => {
+                let (prefix, suffix) = declarator(types, qualified, expand, indent);
+                (format!("{} {} ", prefix, qualifier), suffix)
+            }
+            Some(qualified) => {
+                let (prefix, suffix) = declarator(types, qualified, expand, indent);
+                (format!("{} {}", qualifier, prefix), suffix)
+            }
+            None => (format!("{} void ", qualifier), String::new()),
+        },
+        TypeKind::Typedef(typedef) if expand => match target(typedef) {
+            Some(typedef) => declarator(types, typedef, expand, indent),
+            None => ("void ".to_string(), String::new()),
+        },
+        TypeKind::Struct(members) | TypeKind::Union(members) if expand || ty.name.ends_with("{...}") => {
+            let mut body = format!("{} {{\n", ty.name.trim_end_matches(" {...}"));
+            if members.is_empty() && ty.size == 0 {
+                body += &format!("{:width$}<incomplete type>\n", "", width = indent + 4);
+            }
+            for member in members {
+                let (prefix, suffix) = match types.get(&member.type_id) {
+                    Some(member_type) => declarator(types, member_type, false, indent + 4),
+                    None => ("<unknown type> ".to_string(), String::new()),
+                };
+                body += &format!("{:width$}{}{}{};\n", "", prefix, member.name, suffix, width = indent + 4);
+            }
+            body += &format!("{:width$}}} ", "", width = indent);
+            (body, String::new())
+        }
+        TypeKind::Enum(enumerators) if expand || ty.name.ends_with("{...}") => {
+            // Values are only shown where they don't follow on from the previous one
+            let mut next = 0;
+            let enumerators: Vec<String> = enumerators
+                .iter()
+                .map(|(name, value)| {
+                    let shown = if *value == next { name.clone() } else { format!("{} = {}", name, value) };
+                    next = value.wrapping_add(1);
+                    shown
+                })
+                .collect();
+            (format!("{} {{{}}} ", ty.name.trim_end_matches(" {...}"), enumerators.join(", ")), String::new())
+        }
+        _ => (format!("{} ", ty.name), String::new()),
+    }
+}
+
+/// Whether values of `ty` are characters, which arrays and pointers show as strings.
+fn is_char(ty: &Type) -> bool {
+    ty.size == 1 && matches!(ty.encoding, Encoding::SignedChar | Encoding::UnsignedChar)
+}
+
+/// Escapes a character the way gdb shows it inside `quote`s.
+fn escape_char(byte: u8, quote: char) -> String {
+    match byte {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\\' => "\\\\".to_string(),
+        byte if byte as char == quote => format!("\\{}", quote),
+        0x20..=0x7e => (byte as char).to_string(),
+        byte => format!("\\{:03o}", byte),
+    }
+}
+
+/// Shows the contents of a character array, e.g. `"hi", '\000' <repeats 13 times>`.
+fn format_chars(bytes: &[u8]) -> String {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut index = 0;
+    while index < bytes.len() && index < PRINT_ELEMENTS_LIMIT {
+        let repeats = bytes[index..].iter().take_while(|&&byte| byte == bytes[index]).count();
+        if repeats > REPEAT_COUNT_THRESHOLD {
+            if !text.is_empty() {
+                segments.push(format!("\"{}\"", text));
+                text.clear();
+            }
+            segments.push(format!("'{}' <repeats {} times>", escape_char(bytes[index], '\''), repeats));
+            index += repeats;
+        } else {
+            text += &escape_char(bytes[index], '"');
+            index += 1;
+        }
+    }
+    if !text.is_empty() || segments.is_empty() {
+        segments.push(format!("\"{}\"", text));
+    }
+    let ellipsis = if index < bytes.len() { "..." } else { "" };
+    segments.join(", ") + ellipsis
+}
+
+/// Reads the NUL-terminated string at `addr` and shows it quoted, as gdb does after a `char *`.
+fn read_string<F>(addr: usize, read: &F) -> String
+where
+    F: Fn(usize, usize) -> Option<Vec<u8>>,
+{
+    let mut bytes = Vec::new();
+    while bytes.len() < PRINT_ELEMENTS_LIMIT {
+        // Read a byte at a time so that strings ending right before unmapped memory work
+        match read(addr + bytes.len(), 1) {
+            Some(byte) if byte[0] == 0 => break,
+            Some(byte) => bytes.push(byte[0]),
+            None if bytes.is_empty() => {
+                return format!("<error: Cannot access memory at address {:#x}>", addr)
+            }
+            None => break,
+        }
+    }
+    let text: String = bytes.iter().map(|&byte| escape_char(byte, '"')).collect();
+    let ellipsis = if bytes.len() == PRINT_ELEMENTS_LIMIT { "..." } else { "" };
+    format!("\"{}\"{}", text, ellipsis)
 }
 
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other DWARF location expression, kept as bytecode to evaluate against a frame.
+    Expression(Vec<u8>),
+    /// A location list: where the variable is depends on the pc.
+    List(Vec<LocationListEntry>),
+    /// The variable has no location at all.
+    OptimizedOut,
+}
+
+/// Where a variable is while the pc is in `begin..end`.
+#[derive(Clone)]
+pub struct LocationListEntry {
+    pub begin: usize,
+    pub end: usize,
+    pub expression: Vec<u8>,
+}
+
+impl Location {
+    /// The location expression that applies at `pc`, if it isn't one of the simple cases.
+    pub fn expression_at(&self, pc: usize) -> Option<&[u8]> {
+        match self {
+            Location::Expression(expression) => Some(expression),
+            Location::List(entries) => entries
+                .iter()
+                .find(|entry| entry.begin <= pc && pc < entry.end)
+                .map(|entry| &entry.expression[..]),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => write!(f, "Expression({:02x?})", expression),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (i, entry) in entries.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{:#x}..{:#x}: {:02x?}", separator, entry.begin, entry.end, entry.expression)?;
+                }
+                write!(f, ")")
+            }
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -192,6 +1008,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -201,6 +1018,18 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    /// The DW_AT_frame_base expression, usually DW_OP_call_frame_cfa.
+    pub frame_base: Vec<u8>,
+    /// Calls this function makes, for recovering the callees' entry values.
+    pub call_sites: Vec<CallSite>,
+}
+
+/// A DW_TAG_call_site: a call, identified by its return address, and the values the caller
+/// passed in registers, as DWARF expressions to evaluate in the caller's frame.
+#[derive(Debug, Default, Clone)]
+pub struct CallSite {
+    pub return_address: usize,
+    pub parameters: Vec<(u16, Vec<u8>)>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -211,6 +1040,14 @@ pub struct File {
     pub lines: Vec<Line>,
 }
 
+/// A function from the ELF symbol table, which is all there is for code compiled without -g.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub file: String,
@@ -225,3 +1062,105 @@ impl fmt::Display for Line {
 }
 
 
+
+/// Reads the function symbols from `.symtab`, and from `.dynsym` for stripped files.
+fn load_symbols<'a, 'data: 'a>(objects: impl Iterator<Item = &'a object::read::File<'data>>) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = objects
+        .flat_map(|object| {
+            object
+                .symbols()
+                .chain(object.dynamic_symbols())
+                .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition() && symbol.address() != 0)
+                .filter_map(|symbol| {
+                    Some(Symbol {
+                        name: symbol.name().ok()?.to_string(),
+                        address: symbol.address() as usize,
+                        size: symbol.size() as usize,
+                    })
+                })
+                .chain(plt_symbols(object))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    sort_symbols(&mut symbols);
+    symbols
+}
+
+/// Sorts symbols by address, dropping duplicates. Both tables usually list exported functions,
+/// and aliases share an address; the public name (`printf` rather than `_IO_printf`) goes first.
+fn sort_symbols(symbols: &mut Vec<Symbol>) {
+    symbols.sort_by_key(|symbol| (symbol.address, symbol.name.starts_with('_'), symbol.name.clone()));
+    symbols.dedup_by(|a, b| a.address == b.address && a.name == b.name);
+}
+
+/// Reads the DWARF of `object`, which was loaded from `path`. Without usable DWARF, the symbol
+/// tables still give function names and addresses: code built without -g quietly has none,
+/// while DWARF that can't be read is reported.
+fn read_debug_info(
+    object: &object::read::File,
+    endian: gimli::RunTimeEndian,
+    path: &str,
+) -> (Vec<File>, HashMap<usize, Type>, Option<Addr2line>) {
+    if object.section_by_name(".debug_info").is_none() {
+        return (Vec::new(), HashMap::new(), None);
+    }
+    let loaded = gimli_wrapper::load_file(object, endian, path)
+        .and_then(|(files, types)| Ok((files, types, addr2line_context(object, endian)?)));
+    match loaded {
+        Ok((files, types, addr2line)) => (files, types, Some(addr2line)),
+        Err(err) => {
+            println!("Could not debugging symbols from {}: {:?}", path, err);
+            (Vec::new(), HashMap::new(), None)
+        }
+    }
+}
+
+/// Sets up addr2line, which keeps its own reference-counted copy of the DWARF sections.
+fn addr2line_context(
+    object: &object::read::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<Addr2line, gimli::Error> {
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let data = object
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or_default();
+        Ok(gimli::EndianRcSlice::new(Rc::from(&*data), endian))
+    })?;
+    Context::from_dwarf(dwarf)
+}
+
+/// Names the PLT stubs that calls to other modules go through, like `printf@plt`, as the ELF
+/// symbol tables don't. Stub i jumps through the slot of the i-th `.rela.plt` entry; without
+/// `.plt.sec`, `.plt` starts with an extra entry that calls the dynamic linker.
+fn plt_symbols(object: &object::read::File) -> Vec<Symbol> {
+    let (stubs, first) = match object.section_by_name(".plt.sec") {
+        Some(section) => (section, 0),
+        None => match object.section_by_name(".plt") {
+            Some(section) => (section, 1),
+            None => return Vec::new(),
+        },
+    };
+    let (relocations, dynsym) = match (
+        object.section_by_name(".rela.plt").and_then(|section| section.data().ok()),
+        object.dynamic_symbol_table(),
+    ) {
+        (Some(relocations), Some(dynsym)) => (relocations, dynsym),
+        _ => return Vec::new(),
+    };
+    relocations
+        .chunks_exact(RELA_SIZE)
+        .enumerate()
+        .filter_map(|(index, rela)| {
+            let info = u64::from_le_bytes(rela[8..16].try_into().ok()?);
+            let symbol = dynsym.symbol_by_index(SymbolIndex((info >> 32) as usize)).ok()?;
+            let offset = (first + index) * PLT_ENTRY_SIZE;
+            Some(Symbol {
+                name: format!("{}@plt", symbol.name().ok().filter(|name| !name.is_empty())?),
+                address: stubs.address() as usize + offset,
+                size: PLT_ENTRY_SIZE,
+            })
+            .filter(|_| offset + PLT_ENTRY_SIZE <= stubs.size() as usize)
+        })
+        .collect()
+}
@@ -0,0 +1,237 @@
+//! The `x/Nfu addr` command: dumping inferior memory in various formats, like gdb does. `N` is
+//! a repeat count, `f` a format letter and `u` a unit size (b, h, w or g).
+
+use crate::disassembler::{self, MAX_INSTRUCTION_LENGTH};
+use crate::dwarf_data::DwarfData;
+
+/// Longest string `x/s` will print before giving up on finding the terminating NUL.
+const MAX_STRING_LENGTH: usize = 200;
+/// Like gdb's default `max-value-size`, the most memory one `x` will show. Strings and
+/// instructions are at least a byte each, so this also limits how many of them it prints.
+const MAX_EXAMINE_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Hex,
+    Decimal,
+    Unsigned,
+    Octal,
+    Binary,
+    Char,
+    Address,
+    String,
+    Instruction,
+}
+
+/// What an `x` command should show. The format and unit size stick around for the next `x`
+/// that doesn't specify them.
+#[derive(Clone, Copy)]
+pub struct ExamineFormat {
+    pub count: usize,
+    pub format: Format,
+    pub unit: usize,
+}
+
+impl ExamineFormat {
+    pub fn new() -> ExamineFormat {
+        ExamineFormat {
+            count: 1,
+            format: Format::Hex,
+            unit: 4,
+        }
+    }
+
+    /// Parses the `Nfu` part of `x/Nfu`, filling in whatever is missing from `last`.
+    pub fn parse(spec: &str, last: ExamineFormat) -> Result<ExamineFormat, String> {
+        let digits: String = spec.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let count = match digits.as_str() {
+            "" => 1,
+            digits => digits.parse().map_err(|_| format!("Invalid count \"{}\"", digits))?,
+        };
+        let mut format = last.format;
+        let mut unit = None;
+        for letter in spec[digits.len()..].chars() {
+            match letter {
+                'x' => format = Format::Hex,
+                'd' => format = Format::Decimal,
+                'u' => format = Format::Unsigned,
+                'o' => format = Format::Octal,
+                't' => format = Format::Binary,
+                'c' => format = Format::Char,
+                'a' => format = Format::Address,
+                's' => format = Format::String,
+                'i' => format = Format::Instruction,
+                'b' => unit = Some(1),
+                'h' => unit = Some(2),
+                'w' => unit = Some(4),
+                'g' => unit = Some(8),
+                _ => return Err(format!("Undefined output format \"{}\".", letter)),
+            }
+        }
+        let unit = match (unit, format) {
+            (Some(unit), _) => unit,
+            (None, Format::Char) | (None, Format::String) => 1,
+            (None, Format::Address) => 8,
+            (None, _) if last.format == Format::Char || last.format == Format::String => 4,
+            (None, _) => last.unit,
+        };
+        Ok(ExamineFormat {
+            count,
+            format,
+            unit,
+        })
+    }
+
+    fn units_per_line(&self) -> usize {
+        match (self.format, self.unit) {
+            (Format::Address, _) | (_, 8) => 2,
+            (Format::Char, _) | (_, 1) | (_, 2) => 8,
+            _ => 4,
+        }
+    }
+}
+
+/// Prints memory starting at `addr` as described by `fmt`, reading it through `read`. Returns
+/// the address just past what was shown, which is where a bare `x` carries on. `x/i` marks the
+/// instruction at `pc` with `=>`.
+pub fn examine<R>(
+    fmt: &ExamineFormat,
+    addr: usize,
+    read: R,
+    debug_data: &DwarfData,
+    pc: Option<usize>,
+) -> Result<usize, String>
+where
+    R: Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+{
+    let read = |addr: usize, len: usize| {
+        read(addr, len).map_err(|_| format!("Cannot access memory at address {:#x}", addr))
+    };
+    let unit = match fmt.format {
+        Format::String | Format::Instruction => 1,
+        _ => fmt.unit,
+    };
+    let size = match fmt.count.checked_mul(unit) {
+        Some(size) if size <= MAX_EXAMINE_SIZE => size,
+        _ => {
+            return Err(format!(
+                "value requires {} bytes, which is more than max-value-size",
+                fmt.count as u128 * unit as u128
+            ))
+        }
+    };
+    // Running off the end of the address space is no different from reaching unmapped memory
+    let advance = |addr: usize, len: usize| {
+        addr.checked_add(len).ok_or_else(|| format!("Cannot access memory at address {:#x}", addr))
+    };
+    match fmt.format {
+        Format::String => {
+            let mut addr = addr;
+            for _ in 0..fmt.count {
+                let bytes = read_string(addr, &read)?;
+                println!("{}\t\"{}\"", label(addr, debug_data), escape(&bytes, '"'));
+                addr = advance(addr, bytes.len() + 1)?;
+            }
+            Ok(addr)
+        }
+        Format::Instruction => {
+            let mut addr = addr;
+            for _ in 0..fmt.count {
+                // The longest instruction might run into an unmapped page; retry up to its end
+                let bytes = read(addr, MAX_INSTRUCTION_LENGTH)
+                    .or_else(|_| read(addr, (4096 - addr % 4096).min(MAX_INSTRUCTION_LENGTH)))?;
+                let instruction = disassembler::decode(&bytes, addr, |addr| debug_data.describe_address(addr));
+                let marker = if pc == Some(addr) { "=> " } else { "   " };
+                println!("{}{}\t{}", marker, label(addr, debug_data), instruction.text);
+                addr = advance(addr, instruction.len)?;
+            }
+            Ok(addr)
+        }
+        _ => {
+            advance(addr, size)?;
+            let bytes = read(addr, size)?;
+            for (line, chunk) in bytes.chunks(fmt.unit * fmt.units_per_line()).enumerate() {
+                let line_addr = addr + line * fmt.unit * fmt.units_per_line();
+                let values: Vec<String> = chunk
+                    .chunks(fmt.unit)
+                    .map(|unit| format_unit(fmt.format, unit, debug_data))
+                    .collect();
+                println!("{}\t{}", label(line_addr, debug_data), values.join("\t"));
+            }
+            Ok(addr + bytes.len())
+        }
+    }
+}
+
+/// Reads a NUL-terminated string at `addr`, without the NUL. Reads are chunked so that they
+/// don't cross into a page that might not be mapped.
+fn read_string<R>(addr: usize, read: &R) -> Result<Vec<u8>, String>
+where
+    R: Fn(usize, usize) -> Result<Vec<u8>, String>,
+{
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_STRING_LENGTH {
+        let next = addr + bytes.len();
+        let len = (4096 - next % 4096).min(64);
+        let chunk = read(next, len)?;
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                return Ok(bytes);
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    bytes.truncate(MAX_STRING_LENGTH);
+    Ok(bytes)
+}
+
+/// Formats an address as `0x401136 <main+4>:`, leaving out the symbol if there is none.
+fn label(addr: usize, debug_data: &DwarfData) -> String {
+    match debug_data.describe_address(addr) {
+        Some(symbol) => format!("{:#x} <{}>:", addr, symbol),
+        None => format!("{:#x}:", addr),
+    }
+}
+
+fn format_unit(format: Format, bytes: &[u8], debug_data: &DwarfData) -> String {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let raw = u64::from_le_bytes(buf);
+    let shift = 64 - 8 * bytes.len();
+    let signed = ((raw << shift) as i64) >> shift;
+    match format {
+        Format::Decimal => format!("{}", signed),
+        Format::Unsigned => format!("{}", raw),
+        Format::Octal if raw == 0 => "0".to_string(),
+        Format::Octal => format!("0{:o}", raw),
+        Format::Binary => format!("{:0width$b}", raw, width = 8 * bytes.len()),
+        Format::Char => format!("{} '{}'", signed, escape(&[raw as u8], '\'')),
+        Format::Address => match debug_data.describe_address(raw as usize) {
+            Some(symbol) => format!("{:#x} <{}>", raw, symbol),
+            None => format!("{:#x}", raw),
+        },
+        _ => format!("0x{:0width$x}", raw, width = 2 * bytes.len()),
+    }
+}
+
+/// Escapes bytes for display inside a C character or string literal delimited by `quote`.
+fn escape(bytes: &[u8], quote: char) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            0 => escaped.push_str("\\000"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\\' => escaped.push_str("\\\\"),
+            _ if byte as char == quote => {
+                escaped.push('\\');
+                escaped.push(quote);
+            }
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,1248 @@
+//! A small C expression language for `print`, `ptype`, `x`, `watch` and breakpoint conditions.
+//! Expressions are parsed once and can then be evaluated any number of times against the
+//! inferior's memory and registers, with variables and types taken from the debug info.
+
+use crate::dwarf_data::{DwarfData, Encoding, Type, TypeKind, Variable};
+use crate::inferior::{Frame, Inferior};
+use crate::location::{FrameContext, Place, Target};
+use crate::registers;
+use std::fmt;
+
+/// A parsed expression.
+#[derive(Clone)]
+pub struct Expression {
+    text: String,
+    root: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Int(i128, Builtin),
+    Float(f64),
+    Name(String),
+    Register(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `a.b`; `a->b` is parsed as `(*a).b`.
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Cast(TypeName, Box<Expr>),
+    SizeOfType(TypeName),
+    SizeOf(Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UnaryOp {
+    Neg,
+    Not,
+    Complement,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// A type named in a cast or in `sizeof`: the name of the base type (as the debug info spells
+/// it, e.g. `long unsigned int`) and the number of `*`s after it.
+#[derive(Clone, Debug)]
+struct TypeName {
+    base: String,
+    pointers: usize,
+}
+
+/// The types that literals and the results of arithmetic can have.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Builtin {
+    Char,
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    Double,
+}
+
+/// C base types, for casts to types the program itself doesn't use.
+const BASE_TYPES: [(&str, usize, Encoding); 15] = [
+    ("char", 1, Encoding::SignedChar),
+    ("signed char", 1, Encoding::SignedChar),
+    ("unsigned char", 1, Encoding::UnsignedChar),
+    ("short int", 2, Encoding::Signed),
+    ("short unsigned int", 2, Encoding::Unsigned),
+    ("int", 4, Encoding::Signed),
+    ("unsigned int", 4, Encoding::Unsigned),
+    ("long int", 8, Encoding::Signed),
+    ("long unsigned int", 8, Encoding::Unsigned),
+    ("long long int", 8, Encoding::Signed),
+    ("long long unsigned int", 8, Encoding::Unsigned),
+    ("float", 4, Encoding::Float),
+    ("double", 8, Encoding::Float),
+    ("long double", 16, Encoding::Float),
+    ("_Bool", 1, Encoding::Boolean),
+];
+
+/// Words that can start a type name in a cast.
+const TYPE_KEYWORDS: [&str; 15] = [
+    "struct", "union", "enum", "unsigned", "signed", "char", "short", "int", "long", "float",
+    "double", "void", "_Bool", "const", "volatile",
+];
+
+const PUNCTUATION: [&str; 26] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "(", ")", "[", "]", ".",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(i128, Builtin),
+    Float(f64),
+    Ident(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+impl Builtin {
+    fn to_type(self) -> Type {
+        let (name, size, encoding) = match self {
+            Builtin::Char => ("char", 1, Encoding::SignedChar),
+            Builtin::Int => ("int", 4, Encoding::Signed),
+            Builtin::UnsignedInt => ("unsigned int", 4, Encoding::Unsigned),
+            Builtin::Long => ("long", 8, Encoding::Signed),
+            Builtin::UnsignedLong => ("unsigned long", 8, Encoding::Unsigned),
+            Builtin::Double => ("double", 8, Encoding::Float),
+        };
+        Type::with_encoding(name.to_string(), size, encoding)
+    }
+
+    /// The type a value of type `ty` has in arithmetic, after the integer promotions.
+    fn promoted(ty: &Type) -> Builtin {
+        let unsigned = matches!(ty.encoding, Encoding::Unsigned | Encoding::Pointer);
+        match ty.encoding {
+            Encoding::Float => Builtin::Double,
+            _ if ty.size > 4 && unsigned => Builtin::UnsignedLong,
+            _ if ty.size > 4 => Builtin::Long,
+            _ if ty.size == 4 && unsigned => Builtin::UnsignedInt,
+            _ => Builtin::Int,
+        }
+    }
+
+    /// The type of the result of arithmetic on values of types `a` and `b`, after the usual
+    /// arithmetic conversions.
+    fn common(a: Builtin, b: Builtin) -> Builtin {
+        let either = |ty: Builtin| a == ty || b == ty;
+        if either(Builtin::Double) {
+            Builtin::Double
+        } else if either(Builtin::UnsignedLong) {
+            Builtin::UnsignedLong
+        } else if either(Builtin::Long) {
+            // long can hold every unsigned int
+            Builtin::Long
+        } else if either(Builtin::UnsignedInt) {
+            Builtin::UnsignedInt
+        } else {
+            Builtin::Int
+        }
+    }
+
+    /// Truncates `value` to the range of this type, as C arithmetic wraps around.
+    fn wrap(self, value: i128) -> i128 {
+        match self {
+            Builtin::Char => value as i8 as i128,
+            Builtin::Int => value as i32 as i128,
+            Builtin::UnsignedInt => value as u32 as i128,
+            Builtin::Long => value as i64 as i128,
+            Builtin::UnsignedLong => value as u64 as i128,
+            Builtin::Double => value,
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let root = parser.parse_binary(1)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.syntax_error());
+        }
+        Ok(Expression {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate(&self, scope: &Scope) -> Result<Value, String> {
+        scope.evaluate(&self.root)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Splits `text` into tokens, each paired with the offset it starts at.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let (token, len) = if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            lex_number(rest)?
+        } else if c == '\'' {
+            lex_char(rest)?
+        } else if c == '$' {
+            let len = 1 + rest[1..].find(|c: char| !is_word(c)).unwrap_or(rest.len() - 1);
+            (Token::Register(rest[1..len].to_string()), len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !is_word(c)).unwrap_or(rest.len());
+            (Token::Ident(rest[..len].to_string()), len)
+        } else if let Some(punct) = PUNCTUATION.iter().find(|punct| rest.starts_with(*punct)) {
+            (Token::Punct(punct), punct.len())
+        } else {
+            return Err(format!("Invalid character '{}' in expression.", c));
+        };
+        tokens.push((token, pos));
+        pos += len;
+    }
+    Ok(tokens)
+}
+
+/// Reads an integer or floating point literal, with C's prefixes and suffixes.
+fn lex_number(text: &str) -> Result<(Token, usize), String> {
+    let invalid = |len: usize| format!("Invalid number \"{}\".", &text[..len]);
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+        .unwrap_or(text.len());
+    // An exponent can have a sign, as in 1e-5
+    let mut end = end;
+    if !text.starts_with("0x") && !text.starts_with("0X") && text[..end].ends_with(['e', 'E']) && text[end..].starts_with(['+', '-']) {
+        end += 1 + text[end + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len() - end - 1);
+    }
+    let literal = &text[..end];
+    let hex = literal.starts_with("0x") || literal.starts_with("0X");
+    if !hex && (literal.contains('.') || literal.contains(['e', 'E'])) {
+        let float = literal.trim_end_matches(['f', 'F', 'l', 'L']);
+        return float
+            .parse()
+            .map(|value| (Token::Float(value), end))
+            .map_err(|_| invalid(end));
+    }
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let suffix = &literal[digits.len()..].to_ascii_lowercase();
+    let (radix, digits) = if hex {
+        (16, &digits[2..])
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    let value = u64::from_str_radix(digits, radix).map_err(|_| invalid(end))?;
+    let unsigned = suffix.contains('u');
+    let long = suffix.contains('l');
+    // The first type that can hold the value, like C does
+    let ty = if !long && value <= i32::MAX as u64 {
+        if unsigned { Builtin::UnsignedInt } else { Builtin::Int }
+    } else if !long && value <= u32::MAX as u64 && (unsigned || radix != 10) {
+        Builtin::UnsignedInt
+    } else if !unsigned && value <= i64::MAX as u64 {
+        Builtin::Long
+    } else {
+        Builtin::UnsignedLong
+    };
+    Ok((Token::Int(value as i128, ty), end))
+}
+
+/// Reads a character literal such as `'a'`, `'\n'` or `'\0'`.
+fn lex_char(text: &str) -> Result<(Token, usize), String> {
+    let invalid = || "Unmatched single quote.".to_string();
+    let body = &text[1..];
+    let (value, len) = match body.strip_prefix('\\') {
+        Some(escape) => {
+            let c = escape.chars().next().ok_or_else(invalid)?;
+            match c {
+                'n' => (b'\n' as u32, 2),
+                't' => (b'\t' as u32, 2),
+                'r' => (b'\r' as u32, 2),
+                'a' => (7, 2),
+                'b' => (8, 2),
+                'f' => (12, 2),
+                'v' => (11, 2),
+                'e' => (27, 2),
+                'x' => {
+                    let digits = escape[1..].find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(escape.len() - 1);
+                    let value = u32::from_str_radix(&escape[1..1 + digits], 16).map_err(|_| invalid())?;
+                    (value, 2 + digits)
+                }
+                '0'..='7' => {
+                    let digits = escape.find(|c: char| !('0'..='7').contains(&c)).unwrap_or(escape.len()).min(3);
+                    let value = u32::from_str_radix(&escape[..digits], 8).map_err(|_| invalid())?;
+                    (value, 1 + digits)
+                }
+                c => (c as u32, 1 + c.len_utf8()),
+            }
+        }
+        None => {
+            let c = body.chars().next().ok_or_else(invalid)?;
+            (c as u32, c.len_utf8())
+        }
+    };
+    if !body[len..].starts_with('\'') {
+        return Err(invalid());
+    }
+    Ok((Token::Int(value as u8 as i8 as i128, Builtin::Char), len + 2))
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    /// Complains about the input from the current token on, like gdb does.
+    fn syntax_error(&self) -> String {
+        let rest = match self.tokens.get(self.pos) {
+            Some((_, start)) => &self.text[*start..],
+            None => "",
+        };
+        format!("A syntax error in expression, near `{}'.", rest)
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// Parses binary operators of precedence `min_precedence` and up, and their operands.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Punct(punct)) = self.peek() {
+            let (op, precedence) = match *punct {
+                "||" => (BinaryOp::Or, 1),
+                "&&" => (BinaryOp::And, 2),
+                "|" => (BinaryOp::BitOr, 3),
+                "^" => (BinaryOp::BitXor, 4),
+                "&" => (BinaryOp::BitAnd, 5),
+                "==" => (BinaryOp::Eq, 6),
+                "!=" => (BinaryOp::Ne, 6),
+                "<" => (BinaryOp::Lt, 7),
+                "<=" => (BinaryOp::Le, 7),
+                ">" => (BinaryOp::Gt, 7),
+                ">=" => (BinaryOp::Ge, 7),
+                "<<" => (BinaryOp::Shl, 8),
+                ">>" => (BinaryOp::Shr, 8),
+                "+" => (BinaryOp::Add, 9),
+                "-" => (BinaryOp::Sub, 9),
+                "*" => (BinaryOp::Mul, 10),
+                "/" => (BinaryOp::Div, 10),
+                "%" => (BinaryOp::Rem, 10),
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.parse_binary(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => UnaryOp::Neg,
+            Some(Token::Punct("!")) => UnaryOp::Not,
+            Some(Token::Punct("~")) => UnaryOp::Complement,
+            Some(Token::Punct("*")) => UnaryOp::Deref,
+            Some(Token::Punct("&")) => UnaryOp::AddressOf,
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.parse_unary();
+            }
+            Some(Token::Punct("(")) if self.at_cast() => {
+                self.pos += 1;
+                let type_name = self.parse_type_name()?;
+                self.expect(")")?;
+                return Ok(Expr::Cast(type_name, Box::new(self.parse_unary()?)));
+            }
+            Some(Token::Ident(word)) if word == "sizeof" => {
+                self.pos += 1;
+                if matches!(self.peek(), Some(Token::Punct("("))) && self.at_cast() {
+                    self.pos += 1;
+                    let type_name = self.parse_type_name()?;
+                    self.expect(")")?;
+                    return Ok(Expr::SizeOfType(type_name));
+                }
+                return Ok(Expr::SizeOf(Box::new(self.parse_unary()?)));
+            }
+            _ => return self.parse_postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+    }
+
+    /// Decides whether the `(` at the current position starts a cast. Without knowing which
+    /// names are typedefs, `(name)` is taken to be a cast if an operand follows it, and
+    /// `(name *)` always is.
+    fn at_cast(&self) -> bool {
+        match self.peek_at(1) {
+            Some(Token::Ident(word)) if TYPE_KEYWORDS.contains(&word.as_str()) => true,
+            Some(Token::Ident(_)) => {
+                let mut offset = 2;
+                while let Some(Token::Punct("*")) = self.peek_at(offset) {
+                    offset += 1;
+                }
+                if !matches!(self.peek_at(offset), Some(Token::Punct(")"))) {
+                    return false;
+                }
+                offset > 2
+                    || matches!(
+                        self.peek_at(offset + 1),
+                        Some(Token::Int(..)) | Some(Token::Float(_)) | Some(Token::Ident(_))
+                            | Some(Token::Register(_)) | Some(Token::Punct("("))
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<TypeName, String> {
+        let mut words: Vec<String> = Vec::new();
+        let mut base = None;
+        while let Some(Token::Ident(word)) = self.peek() {
+            let word = word.clone();
+            match word.as_str() {
+                "const" | "volatile" => self.pos += 1,
+                "struct" | "union" | "enum" if words.is_empty() && base.is_none() => {
+                    self.pos += 1;
+                    base = Some(format!("{} {}", word, self.identifier()?));
+                }
+                word if TYPE_KEYWORDS.contains(&word) && base.is_none() => {
+                    self.pos += 1;
+                    words.push(word.to_string());
+                }
+                // A typedef name
+                _ if words.is_empty() && base.is_none() => {
+                    self.pos += 1;
+                    base = Some(word);
+                }
+                _ => break,
+            }
+        }
+        let base = match base {
+            Some(base) => base,
+            None if !words.is_empty() => base_type_name(&words),
+            None => return Err(self.syntax_error()),
+        };
+        let mut pointers = 0;
+        while self.eat("*") {
+            pointers += 1;
+            while self.eat_qualifier() {}
+        }
+        Ok(TypeName { base, pointers })
+    }
+
+    fn eat_qualifier(&mut self) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(word)) if word == "const" || word == "volatile") {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.eat("[") {
+                let index = self.parse_binary(1)?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat(".") {
+                expr = Expr::Member(Box::new(expr), self.identifier()?);
+            } else if self.eat("->") {
+                let target = Expr::Unary(UnaryOp::Deref, Box::new(expr));
+                expr = Expr::Member(Box::new(target), self.identifier()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let expr = match self.peek() {
+            Some(Token::Int(value, ty)) => Expr::Int(*value, *ty),
+            Some(Token::Float(value)) => Expr::Float(*value),
+            Some(Token::Ident(name)) => Expr::Name(name.clone()),
+            Some(Token::Register(name)) => Expr::Register(name.clone()),
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let expr = self.parse_binary(1)?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(self.syntax_error()),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+/// Spells a base type given as C keywords (`unsigned long`, `long unsigned int`, ...) the way
+/// the debug info names it.
+fn base_type_name(words: &[String]) -> String {
+    let has = |word: &str| words.iter().any(|w| w == word);
+    let longs = words.iter().filter(|w| *w == "long").count();
+    let unsigned = has("unsigned");
+    let name = if has("void") {
+        "void"
+    } else if has("_Bool") {
+        "_Bool"
+    } else if has("float") {
+        "float"
+    } else if has("double") {
+        if longs > 0 { "long double" } else { "double" }
+    } else if has("char") {
+        if unsigned {
+            "unsigned char"
+        } else if has("signed") {
+            "signed char"
+        } else {
+            "char"
+        }
+    } else if has("short") {
+        if unsigned { "short unsigned int" } else { "short int" }
+    } else if longs >= 2 {
+        if unsigned { "long long unsigned int" } else { "long long int" }
+    } else if longs == 1 {
+        if unsigned { "long unsigned int" } else { "long int" }
+    } else if unsigned {
+        "unsigned int"
+    } else {
+        "int"
+    };
+    name.to_string()
+}
+
+/// The context an expression is evaluated in: the debug info, the inferior (if there is one)
+/// and the frame whose variables are visible.
+pub struct Scope<'a> {
+    pub debug_data: &'a DwarfData,
+    pub inferior: Option<&'a Inferior>,
+    /// The selected frame; without one, only globals are visible.
+    pub frame: Option<Frame>,
+    /// The selected frame's caller, which knows the values its parameters were called with.
+    pub caller: Option<Frame>,
+}
+
+/// The result of evaluating an expression.
+#[derive(Clone)]
+pub struct Value {
+    pub ty: Type,
+    contents: Contents,
+}
+
+#[derive(Clone)]
+enum Contents {
+    /// An object in the inferior's memory, read when it's needed.
+    Memory(usize),
+    Computed(Vec<u8>),
+    OptimizedOut,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Scalar {
+    Int(i128),
+    Float(f64),
+}
+
+impl Value {
+    fn computed(ty: Type, scalar: Scalar) -> Value {
+        let bytes = encode(scalar, &ty);
+        Value {
+            ty,
+            contents: Contents::Computed(bytes),
+        }
+    }
+
+    /// The address of the value if it's an object in memory, rather than a computed value.
+    pub fn address(&self) -> Option<usize> {
+        match self.contents {
+            Contents::Memory(addr) => Some(addr),
+            Contents::Computed(_) | Contents::OptimizedOut => None,
+        }
+    }
+
+    /// The part of this value of type `ty` at `offset`, e.g. a member or an element.
+    fn part(&self, ty: Type, offset: usize) -> Value {
+        let contents = match &self.contents {
+            Contents::Memory(addr) => Contents::Memory(addr + offset),
+            Contents::Computed(bytes) => {
+                let start = offset.min(bytes.len());
+                let end = (offset + ty.size).min(bytes.len());
+                Contents::Computed(bytes[start..end].to_vec())
+            }
+            Contents::OptimizedOut => Contents::OptimizedOut,
+        };
+        Value { ty, contents }
+    }
+}
+
+impl Scalar {
+    fn as_f64(self) -> f64 {
+        match self {
+            Scalar::Int(val) => val as f64,
+            Scalar::Float(val) => val,
+        }
+    }
+}
+
+/// Converts a number to the bytes of a value of type `ty`.
+fn encode(scalar: Scalar, ty: &Type) -> Vec<u8> {
+    let mut bytes = match (ty.encoding, scalar) {
+        (Encoding::Float, _) if ty.size == 4 => (scalar.as_f64() as f32).to_le_bytes().to_vec(),
+        (Encoding::Float, _) => scalar.as_f64().to_le_bytes().to_vec(),
+        (Encoding::Boolean, Scalar::Int(value)) => vec![(value != 0) as u8],
+        (Encoding::Boolean, Scalar::Float(value)) => vec![(value != 0.0) as u8],
+        (_, Scalar::Int(value)) => (value as u64).to_le_bytes().to_vec(),
+        (_, Scalar::Float(value)) => (value as i64 as u64).to_le_bytes().to_vec(),
+    };
+    bytes.resize(ty.size, 0);
+    bytes
+}
+
+/// Interprets the bytes of a value of scalar type `ty` as a number.
+fn decode(ty: &Type, bytes: &[u8]) -> Result<Scalar, String> {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    let raw = u64::from_le_bytes(buf);
+    Ok(match ty.encoding {
+        Encoding::Float if len == 4 => Scalar::Float(f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64),
+        Encoding::Float if len == 8 => Scalar::Float(f64::from_le_bytes(buf)),
+        Encoding::Float => return Err(format!("unsupported float size {}", len)),
+        Encoding::Signed | Encoding::SignedChar if len > 0 && len < 8 => {
+            let shift = 64 - 8 * len;
+            Scalar::Int((((raw << shift) as i64) >> shift) as i128)
+        }
+        Encoding::Signed | Encoding::SignedChar => Scalar::Int(raw as i64 as i128),
+        _ => Scalar::Int(raw as i128),
+    })
+}
+
+impl Scope<'_> {
+    /// Reads the bytes of a value.
+    pub fn read(&self, value: &Value) -> Result<Vec<u8>, String> {
+        match &value.contents {
+            Contents::Memory(addr) => self.read_memory(*addr, value.ty.size),
+            Contents::Computed(bytes) => Ok(bytes.clone()),
+            Contents::OptimizedOut => Err("value has been optimized out".to_string()),
+        }
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+        let cannot_access = || format!("Cannot access memory at address {:#x}", addr);
+        self.inferior
+            .ok_or_else(cannot_access)?
+            .read_bytes(addr, len)
+            .map_err(|_| cannot_access())
+    }
+
+    /// Renders a value the way `print` shows it.
+    pub fn format(&self, value: &Value) -> Result<String, String> {
+        if let Contents::OptimizedOut = value.contents {
+            return Ok("<optimized out>".to_string());
+        }
+        let bytes = self.read(value)?;
+        let read = |addr, len| self.read_memory(addr, len).ok();
+        Ok(self.debug_data.format_value(&value.ty, &bytes, &read))
+    }
+
+    /// Whether a value is true in the C sense, i.e. nonzero.
+    pub fn is_true(&self, value: &Value) -> Result<bool, String> {
+        Ok(match self.scalar(value)? {
+            Scalar::Int(value) => value != 0,
+            Scalar::Float(value) => value != 0.0,
+        })
+    }
+
+    /// Interprets a value as an address: pointers and integers are taken as they are, and
+    /// arrays stand for the address of their first element.
+    pub fn to_address(&self, value: &Value) -> Result<usize, String> {
+        match self.scalar(value)? {
+            Scalar::Int(value) => Ok(value as usize),
+            Scalar::Float(_) => Err("Invalid cast.".to_string()),
+        }
+    }
+
+    /// The numeric value of a scalar. Arrays decay to the address of their first element.
+    fn scalar(&self, value: &Value) -> Result<Scalar, String> {
+        let ty = self.debug_data.strip_typedefs(&value.ty);
+        match ty.kind {
+            TypeKind::Array(..) | TypeKind::Function(..) => {
+                return value
+                    .address()
+                    .map(|addr| Scalar::Int(addr as i128))
+                    .ok_or_else(|| "Attempt to take address of value not located in memory.".to_string())
+            }
+            TypeKind::Struct(_) | TypeKind::Union(_) => {
+                return Err("Argument to arithmetic operation not a number or boolean.".to_string())
+            }
+            _ => {}
+        }
+        decode(ty, &self.read(value)?)
+    }
+
+    /// The type of a pointer to values of type `ty`, or of `void *`. Pointers can only be
+    /// followed if the debug info has a type of that name to point to.
+    fn pointer_to(&self, ty: Option<&Type>) -> Type {
+        match ty {
+            Some(ty) => match self.debug_data.find_type_id(&ty.name) {
+                Some(id) => self.debug_data.pointer_type(Some(id)),
+                None => {
+                    let mut pointer = self.debug_data.pointer_type(None);
+                    pointer.name = format!("{} *", ty.name);
+                    pointer
+                }
+            },
+            None => self.debug_data.pointer_type(None),
+        }
+    }
+
+    /// The type pointed to by pointers of type `ty`, with its size (1 for `void`), if `ty` is
+    /// a pointer or an array.
+    fn pointee(&self, ty: &Type) -> Option<(Option<&Type>, usize)> {
+        match self.debug_data.strip_typedefs(ty).kind {
+            TypeKind::Pointer(Some(target)) | TypeKind::Array(target, _) => {
+                let target = self.debug_data.get_type(target)?;
+                Some((Some(target), target.size.max(1)))
+            }
+            TypeKind::Pointer(None) => Some((None, 1)),
+            _ => None,
+        }
+    }
+
+    /// Looks up a type written the way C spells it, e.g. `unsigned long` or `struct node *`.
+    pub fn parse_type(&self, text: &str) -> Result<Type, String> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let type_name = parser.parse_type_name()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.syntax_error());
+        }
+        self.resolve_type_name(&type_name)?
+            .ok_or_else(|| "Attempt to use a type name as an expression".to_string())
+    }
+
+    fn resolve_type_name(&self, type_name: &TypeName) -> Result<Option<Type>, String> {
+        let mut ty = match type_name.base.as_str() {
+            "void" => None,
+            name => Some(match self.debug_data.find_type(name) {
+                Some(ty) => ty.clone(),
+                None => BASE_TYPES
+                    .iter()
+                    .find(|(base, _, _)| *base == name)
+                    .map(|(base, size, encoding)| Type::with_encoding(base.to_string(), *size, *encoding))
+                    .ok_or_else(|| match name.split_once(' ') {
+                        Some((tag @ ("struct" | "union" | "enum"), tag_name)) => {
+                            format!("No {} type named {}.", tag, tag_name)
+                        }
+                        _ => format!("No symbol \"{}\" in current context.", name),
+                    })?,
+            }),
+        };
+        for _ in 0..type_name.pointers {
+            ty = Some(self.pointer_to(ty.as_ref()));
+        }
+        Ok(ty)
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(value, ty) => Ok(Value::computed(ty.to_type(), Scalar::Int(*value))),
+            Expr::Float(value) => Ok(Value::computed(Builtin::Double.to_type(), Scalar::Float(*value))),
+            Expr::Name(name) => self.lookup(name),
+            Expr::Register(name) => self.register(name),
+            Expr::Unary(op, operand) => self.unary(*op, self.evaluate(operand)?),
+            Expr::Binary(op, lhs, rhs) => self.binary(*op, lhs, rhs),
+            Expr::Member(value, member) => self.member(self.evaluate(value)?, member),
+            Expr::Index(array, index) => {
+                let array = self.evaluate(array)?;
+                let index = match self.scalar(&self.evaluate(index)?)? {
+                    Scalar::Int(index) => index as isize,
+                    Scalar::Float(_) => return Err("Array subscript is not an integer.".to_string()),
+                };
+                self.index(array, index)
+            }
+            Expr::Cast(type_name, value) => {
+                let ty = self
+                    .resolve_type_name(type_name)?
+                    .ok_or_else(|| "Invalid cast.".to_string())?;
+                let value = self.evaluate(value)?;
+                match self.debug_data.strip_typedefs(&ty).kind {
+                    TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => Err("Invalid cast.".to_string()),
+                    _ => Ok(Value::computed(ty, self.scalar(&value)?)),
+                }
+            }
+            Expr::SizeOfType(type_name) => {
+                let size = self.resolve_type_name(type_name)?.map_or(1, |ty| ty.size);
+                Ok(Value::computed(Builtin::UnsignedLong.to_type(), Scalar::Int(size as i128)))
+            }
+            Expr::SizeOf(value) => {
+                // sizeof(name) can't be told apart from sizeof(typedef name) when parsing
+                let size = match (self.evaluate(value), value.as_ref()) {
+                    (Ok(value), _) => value.ty.size,
+                    (Err(err), Expr::Name(name)) => self.debug_data.find_type(name).ok_or(err)?.size,
+                    (Err(err), _) => return Err(err),
+                };
+                Ok(Value::computed(Builtin::UnsignedLong.to_type(), Scalar::Int(size as i128)))
+            }
+        }
+    }
+
+    /// Looks up a variable, then an enumerator, then a function.
+    fn lookup(&self, name: &str) -> Result<Value, String> {
+        let var = match &self.frame {
+            Some(frame) => self.debug_data.get_variable(frame.pc, name),
+            None => self.debug_data.get_global_variable(name),
+        };
+        if let Some(var) = var {
+            return self.variable(var);
+        }
+        if let Some((ty, value)) = self.debug_data.find_enumerator(name) {
+            return Ok(Value::computed(ty.clone(), Scalar::Int(value as i128)));
+        }
+        if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            let mut ty = self.debug_data.pointer_type(None);
+            ty.name = "void (*)()".to_string();
+            return Ok(Value::computed(ty, Scalar::Int(addr as i128)));
+        }
+        Err(format!("No symbol \"{}\" in current context.", name))
+    }
+
+    /// The value of a variable in the selected frame, wherever its location says it is.
+    pub fn variable(&self, var: &Variable) -> Result<Value, String> {
+        let context = FrameContext {
+            debug_data: self.debug_data,
+            inferior: self.inferior.map(|inferior| inferior as &dyn Target),
+            frame: self.frame.as_ref(),
+            caller: self.caller.as_ref(),
+        };
+        let contents = match context.locate(var)? {
+            Place::Memory(addr) => Contents::Memory(addr),
+            Place::Value(bytes) => Contents::Computed(bytes),
+            Place::OptimizedOut => Contents::OptimizedOut,
+        };
+        Ok(Value {
+            ty: var.entity_type.clone(),
+            contents,
+        })
+    }
+
+    fn register(&self, name: &str) -> Result<Value, String> {
+        let regs = self
+            .inferior
+            .ok_or_else(|| "No registers.".to_string())?
+            .get_registers()
+            .map_err(|err| format!("Could not read registers of the inferior: {}", err))?;
+        let name = match name {
+            "sp" => "rsp",
+            "fp" => "rbp",
+            name => name,
+        };
+        let value = registers::general_register_value(&regs, name)
+            .ok_or_else(|| format!("Invalid register `{}'", name))?;
+        // Like gdb, the program counter is a code pointer and the stack and frame pointers are
+        // data pointers
+        let ty = match name {
+            "rip" | "pc" => {
+                let mut ty = self.debug_data.pointer_type(None);
+                ty.name = "void (*)()".to_string();
+                ty
+            }
+            "rsp" | "rbp" => self.debug_data.pointer_type(None),
+            _ => Builtin::Long.to_type(),
+        };
+        Ok(Value::computed(ty, Scalar::Int(value as i128)))
+    }
+
+    fn unary(&self, op: UnaryOp, value: Value) -> Result<Value, String> {
+        match op {
+            UnaryOp::Deref => self.dereference(value),
+            UnaryOp::AddressOf => {
+                let addr = value
+                    .address()
+                    .ok_or_else(|| "Attempt to take address of value not located in memory.".to_string())?;
+                Ok(Value::computed(self.pointer_to(Some(&value.ty)), Scalar::Int(addr as i128)))
+            }
+            UnaryOp::Not => {
+                let result = !self.is_true(&value)?;
+                Ok(Value::computed(Builtin::Int.to_type(), Scalar::Int(result as i128)))
+            }
+            UnaryOp::Neg | UnaryOp::Complement => {
+                let ty = Builtin::promoted(self.debug_data.strip_typedefs(&value.ty));
+                let result = match (op, self.scalar(&value)?) {
+                    (UnaryOp::Neg, Scalar::Int(value)) => Scalar::Int(ty.wrap(-value)),
+                    (UnaryOp::Neg, Scalar::Float(value)) => Scalar::Float(-value),
+                    (_, Scalar::Int(value)) => Scalar::Int(ty.wrap(!value)),
+                    (_, Scalar::Float(_)) => return Err("Argument to complement operation not an integer, boolean.".to_string()),
+                };
+                Ok(Value::computed(ty.to_type(), result))
+            }
+        }
+    }
+
+    /// Follows a pointer, or gets the first element of an array.
+    fn dereference(&self, value: Value) -> Result<Value, String> {
+        let not_pointer = || "Attempt to take contents of a non-pointer value.".to_string();
+        match self.debug_data.strip_typedefs(&value.ty).kind {
+            TypeKind::Pointer(Some(target)) => {
+                let target = self.debug_data.get_type(target).ok_or_else(not_pointer)?.clone();
+                let addr = self.to_address(&value)?;
+                Ok(Value {
+                    ty: target,
+                    contents: Contents::Memory(addr),
+                })
+            }
+            TypeKind::Array(..) => self.index(value, 0),
+            _ => Err(not_pointer()),
+        }
+    }
+
+    /// Gets a member of a struct or union, or of one a pointer points to.
+    fn member(&self, value: Value, name: &str) -> Result<Value, String> {
+        let value = match self.debug_data.strip_typedefs(&value.ty).kind {
+            TypeKind::Pointer(_) => self.dereference(value)?,
+            _ => value,
+        };
+        match self.debug_data.strip_typedefs(&value.ty).kind {
+            TypeKind::Struct(_) | TypeKind::Union(_) => {}
+            _ => return Err("Attempt to extract a component of a value that is not a structure.".to_string()),
+        }
+        let (offset, member_type) = self
+            .debug_data
+            .find_member(&value.ty, name)
+            .ok_or_else(|| format!("There is no member named {}.", name))?;
+        Ok(value.part(member_type.clone(), offset))
+    }
+
+    fn index(&self, value: Value, index: isize) -> Result<Value, String> {
+        let cannot_subscript = || format!("cannot subscript something of type `{}'", value.ty.name);
+        let (element, size) = match self.pointee(&value.ty) {
+            Some((Some(element), size)) => (element.clone(), size),
+            _ => return Err(cannot_subscript()),
+        };
+        // Like gdb, address arithmetic wraps rather than failing
+        let offset = index.wrapping_mul(size as isize);
+        match self.debug_data.strip_typedefs(&value.ty).kind {
+            TypeKind::Array(_, count) => {
+                if value.address().is_none() && (index < 0 || count.is_none_or(|count| index as usize >= count)) {
+                    return Err("no such vector element".to_string());
+                }
+                match value.address() {
+                    Some(addr) => Ok(Value {
+                        ty: element,
+                        contents: Contents::Memory((addr as isize).wrapping_add(offset) as usize),
+                    }),
+                    None => Ok(value.part(element, offset as usize)),
+                }
+            }
+            _ => {
+                let addr = (self.to_address(&value)? as isize).wrapping_add(offset);
+                Ok(Value {
+                    ty: element,
+                    contents: Contents::Memory(addr as usize),
+                })
+            }
+        }
+    }
+
+    fn binary(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<Value, String> {
+        let boolean = |value: bool| Value::computed(Builtin::Int.to_type(), Scalar::Int(value as i128));
+        // && and || don't evaluate their right operand if the left one decides the result
+        if op == BinaryOp::And || op == BinaryOp::Or {
+            let lhs = self.is_true(&self.evaluate(lhs)?)?;
+            if lhs == (op == BinaryOp::Or) {
+                return Ok(boolean(lhs));
+            }
+            return Ok(boolean(self.is_true(&self.evaluate(rhs)?)?));
+        }
+        let lhs = self.evaluate(lhs)?;
+        let rhs = self.evaluate(rhs)?;
+
+        // Pointer arithmetic is in units of the type pointed to
+        let lhs_pointee = self.pointee(&lhs.ty);
+        let rhs_pointee = self.pointee(&rhs.ty);
+        match (op, lhs_pointee, rhs_pointee) {
+            (BinaryOp::Add, Some((target, size)), None)
+            | (BinaryOp::Sub, Some((target, size)), None)
+            | (BinaryOp::Add, None, Some((target, size))) => {
+                let (pointer, offset) = if lhs_pointee.is_some() { (&lhs, &rhs) } else { (&rhs, &lhs) };
+                let addr = self.to_address(pointer)? as i128;
+                let offset = match self.scalar(offset)? {
+                    Scalar::Int(offset) => offset.wrapping_mul(size as i128),
+                    Scalar::Float(_) => return Err("Argument to arithmetic operation not a number or boolean.".to_string()),
+                };
+                let addr = if op == BinaryOp::Sub { addr.wrapping_sub(offset) } else { addr.wrapping_add(offset) };
+                let ty = match self.debug_data.strip_typedefs(&pointer.ty).kind {
+                    TypeKind::Array(..) => self.pointer_to(target),
+                    _ => pointer.ty.clone(),
+                };
+                return Ok(Value::computed(ty, Scalar::Int(addr as u64 as i128)));
+            }
+            (BinaryOp::Sub, Some((_, size)), Some(_)) => {
+                let difference = (self.to_address(&lhs)? as i64).wrapping_sub(self.to_address(&rhs)? as i64);
+                return Ok(Value::computed(Builtin::Long.to_type(), Scalar::Int((difference / size as i64) as i128)));
+            }
+            _ => {}
+        }
+
+        let a = self.scalar(&lhs)?;
+        let b = self.scalar(&rhs)?;
+        let comparison = match (a, b) {
+            (Scalar::Int(a), Scalar::Int(b)) => a.partial_cmp(&b),
+            (a, b) => a.as_f64().partial_cmp(&b.as_f64()),
+        };
+        let compare = |test: fn(std::cmp::Ordering) -> bool| match comparison {
+            Some(ordering) => boolean(test(ordering)),
+            // NaN compares unequal to everything
+            None => boolean(op == BinaryOp::Ne),
+        };
+        match op {
+            BinaryOp::Eq => return Ok(compare(|ordering| ordering.is_eq())),
+            BinaryOp::Ne => return Ok(compare(|ordering| ordering.is_ne())),
+            BinaryOp::Lt => return Ok(compare(|ordering| ordering.is_lt())),
+            BinaryOp::Le => return Ok(compare(|ordering| ordering.is_le())),
+            BinaryOp::Gt => return Ok(compare(|ordering| ordering.is_gt())),
+            BinaryOp::Ge => return Ok(compare(|ordering| ordering.is_ge())),
+            _ => {}
+        }
+
+        let lhs_type = Builtin::promoted(self.debug_data.strip_typedefs(&lhs.ty));
+        let rhs_type = Builtin::promoted(self.debug_data.strip_typedefs(&rhs.ty));
+        let ty = match op {
+            // The type of a shift is that of its left operand
+            BinaryOp::Shl | BinaryOp::Shr => lhs_type,
+            _ => Builtin::common(lhs_type, rhs_type),
+        };
+        if ty == Builtin::Double {
+            let (a, b) = (a.as_f64(), b.as_f64());
+            let result = match op {
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                _ => return Err("Integer only operation.".to_string()),
+            };
+            return Ok(Value::computed(ty.to_type(), Scalar::Float(result)));
+        }
+        let (a, b) = match (a, b) {
+            (Scalar::Int(a), Scalar::Int(b)) => (ty.wrap(a), ty.wrap(b)),
+            _ => return Err("Integer only operation.".to_string()),
+        };
+        let result = match op {
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div | BinaryOp::Rem if b == 0 => return Err("Division by zero".to_string()),
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => a % b,
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Shl => a.checked_shl(b as u32).unwrap_or(0),
+            BinaryOp::Shr => a.checked_shr(b as u32).unwrap_or(if a < 0 { -1 } else { 0 }),
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitOr => a | b,
+            _ => unreachable!(),
+        };
+        Ok(Value::computed(ty.to_type(), Scalar::Int(ty.wrap(result))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a parsed expression with every operation parenthesized, e.g. `(1 + (2 * 3))`.
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::Int(value, _) => value.to_string(),
+            Expr::Float(value) => value.to_string(),
+            Expr::Name(name) => name.clone(),
+            Expr::Register(name) => format!("${}", name),
+            Expr::Unary(op, operand) => {
+                let op = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                    UnaryOp::Complement => "~",
+                    UnaryOp::Deref => "*",
+                    UnaryOp::AddressOf => "&",
+                };
+                format!("({}{})", op, show(operand))
+            }
+            Expr::Binary(op, lhs, rhs) => format!("({} {:?} {})", show(lhs), op, show(rhs)),
+            Expr::Member(object, member) => format!("({}.{})", show(object), member),
+            Expr::Index(array, index) => format!("({}[{}])", show(array), show(index)),
+            Expr::Cast(ty, operand) => format!("(({}{}) {})", ty.base, "*".repeat(ty.pointers), show(operand)),
+            Expr::SizeOfType(ty) => format!("(sizeof {}{})", ty.base, "*".repeat(ty.pointers)),
+            Expr::SizeOf(operand) => format!("(sizeof {})", show(operand)),
+        }
+    }
+
+    fn parse(text: &str) -> String {
+        show(&Expression::parse(text).unwrap().root)
+    }
+
+    /// Evaluates an expression that doesn't need a process, and prints it. The only type in
+    /// the debug info is `int`, so that pointers to it have a size to scale by.
+    fn print(text: &str) -> Result<String, String> {
+        let mut debug_data = DwarfData::empty();
+        debug_data.add_type(Builtin::Int.to_type());
+        let scope = Scope {
+            debug_data: &debug_data,
+            inferior: None,
+            frame: None,
+            caller: None,
+        };
+        scope.format(&Expression::parse(text)?.evaluate(&scope)?)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(1 Add (2 Mul 3))");
+        assert_eq!(parse("1 - 2 - 3"), "((1 Sub 2) Sub 3)");
+        assert_eq!(parse("a < b == c"), "((a Lt b) Eq c)");
+        assert_eq!(parse("a || b && c | d ^ e & f"), "(a Or (b And (c BitOr (d BitXor (e BitAnd f)))))");
+        assert_eq!(parse("1 << 2 + 3"), "(1 Shl (2 Add 3))");
+        assert_eq!(parse("(1 + 2) * 3"), "((1 Add 2) Mul 3)");
+        assert_eq!(print("1 + 2 * 3 - 8 / 4 % 3").unwrap(), "5");
+        assert_eq!(print("1 << 4 | 1 == 1").unwrap(), "17");
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(parse("*p"), "(*p)");
+        assert_eq!(parse("&x"), "(&x)");
+        assert_eq!(parse("**pp + 1"), "((*(*pp)) Add 1)");
+        assert_eq!(parse("-x * y"), "((-x) Mul y)");
+        assert_eq!(parse("&a[1]"), "(&(a[1]))");
+        assert_eq!(parse("*p.q"), "(*(p.q))");
+        assert_eq!(print("-5 + ~0 + !0").unwrap(), "-5");
+    }
+
+    #[test]
+    fn members_and_indexing() {
+        assert_eq!(parse("s.a.b"), "((s.a).b)");
+        assert_eq!(parse("p->next->value"), "((*((*p).next)).value)");
+        assert_eq!(parse("a[i + 1][j]"), "((a[(i Add 1)])[j])");
+        assert_eq!(parse("p->items[2].name"), "((((*p).items)[2]).name)");
+        assert_eq!(print("1.x"), Err("Invalid number \"1.x\".".to_string()));
+        assert_eq!(
+            print("(1).x"),
+            Err("Attempt to extract a component of a value that is not a structure.".to_string())
+        );
+    }
+
+    #[test]
+    fn casts() {
+        assert_eq!(parse("(char)x"), "((char) x)");
+        assert_eq!(parse("(unsigned long)p"), "((long unsigned int) p)");
+        assert_eq!(parse("(struct node *)p->next"), "((struct node*) ((*p).next))");
+        assert_eq!(parse("(size_t)x + 1"), "(((size_t) x) Add 1)");
+        // Without an operand after it, a parenthesized name is just a variable
+        assert_eq!(parse("(x) + 1"), "(x Add 1)");
+        assert_eq!(parse("sizeof(int) * 2"), "((sizeof int) Mul 2)");
+        assert_eq!(print("(unsigned char)-1").unwrap(), "255 '\\377'");
+        assert_eq!(print("(char)65").unwrap(), "65 'A'");
+        assert_eq!(print("(int)3.9").unwrap(), "3");
+        assert_eq!(print("(short)65537").unwrap(), "1");
+    }
+
+    #[test]
+    fn pointer_arithmetic_wraps() {
+        assert_eq!(print("(long)((char*)0x7fffffffffffffff - (char*)0x8000000000000000)").unwrap(), "-1");
+        assert_eq!(print("(long)((int*)16 - (int*)8)").unwrap(), "2");
+        assert_eq!(print("(unsigned long)((char*)0xffffffffffffffff + 2)").unwrap(), "1");
+        assert_eq!(
+            print("((int*)8)[0x7fffffffffffffff]"),
+            Err("Cannot access memory at address 0x4".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Expression::parse(text).err().unwrap();
+        assert_eq!(error("1 +"), "A syntax error in expression, near `'.");
+        assert_eq!(error("(1 + 2"), "A syntax error in expression, near `'.");
+        assert_eq!(error("a[1"), "A syntax error in expression, near `'.");
+        assert_eq!(error("1 2"), "A syntax error in expression, near `2'.");
+        assert_eq!(error("a. + 1"), "A syntax error in expression, near `+ 1'.");
+        assert_eq!(error("p->"), "A syntax error in expression, near `'.");
+        assert_eq!(error("1 @ 2"), "Invalid character '@' in expression.");
+        assert_eq!(error("'a"), "Unmatched single quote.");
+        assert_eq!(error("0x"), "Invalid number \"0x\".");
+    }
+}
@@ -5,18 +5,25 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    self, CallSite, Encoding, File, Function, Line, Location, LocationListEntry, Member, Type, TypeKind, Variable,
+};
+use crate::split_dwarf::SplitFile;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+/// Reads the debug info of `object`, which was loaded from `path`.
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+    path: &str,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         // Ok(object
@@ -29,21 +36,86 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             None => Ok(borrow::Cow::Borrowed(&[][..])),
         }
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
-
     // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+    let dwarf_sections = gimli::DwarfSections::load(&load_section)?;
 
     // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+    let dwarf = dwarf_sections.borrow(borrow_section);
+
+    // Units compiled with -gsplit-dwarf are only skeletons here; the rest of their debug info
+    // is in the program's .dwp package, or else in a .dwo file per unit
+    let package_file = SplitFile::open(&format!("{}.dwp", path));
+    let package = match package_file {
+        Some(ref file) => Some(gimli::DwarfPackage::load(
+            |id| -> Result<_, gimli::Error> { Ok(gimli::EndianSlice::new(file.section(id), endian)) },
+            gimli::EndianSlice::new(&[][..], endian),
+        )?),
+        None => None,
+    };
+    let mut skeletons = Vec::new();
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        skeletons.push(dwarf.unit(header)?);
+    }
+    // A skeleton's DWO id is in its unit header in DWARF 5, and in DW_AT_GNU_dwo_id before that;
+    // gimli's `Unit::dwo_id` reads the header first and falls back to the attribute
+    let in_package = |unit: &gimli::Unit<_>| match (&package, unit.dwo_id) {
+        (Some(package), Some(dwo_id)) => package.cu_index.find(dwo_id.0).is_some(),
+        _ => false,
+    };
+    let dwo_files: Vec<Option<SplitFile>> = skeletons
+        .iter()
+        .map(|unit| match in_package(unit) {
+            true => None,
+            false => dwo_path(unit, &dwarf).and_then(|path| SplitFile::open(&path)),
+        })
+        .collect();
+    let mut split_dwarfs = Vec::new();
+    for (skeleton, dwo_file) in skeletons.iter().zip(dwo_files.iter()) {
+        let split = match (dwo_file, &package, skeleton.dwo_id) {
+            (Some(file), _, _) => {
+                let mut split = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+                    Ok(gimli::EndianSlice::new(file.section(id), endian))
+                })?;
+                // Addresses stay in the program's .debug_addr
+                split.make_dwo(&dwarf);
+                Some(split)
+            }
+            (None, Some(package), Some(dwo_id)) => package.find_cu(dwo_id, &dwarf)?,
+            _ => None,
+        };
+        split_dwarfs.push(split);
+    }
+
+    // Types are keyed by the offset of their DIE, and each split unit starts over at offset 0 of
+    // its own .debug_info, so count those as following the program's to keep the keys unique
+    let mut next_type_base = object.section_by_name(".debug_info").map_or(0, |section| section.size() as usize);
+    let mut units = Vec::new();
+    for (skeleton, split) in skeletons.into_iter().zip(split_dwarfs.iter()) {
+        let split_unit = match split {
+            Some(split) => match split.units().next()? {
+                Some(header) => Some((split.unit(header)?, split)),
+                None => None,
+            },
+            None => None,
+        };
+        match split_unit {
+            Some((mut unit, split)) => {
+                unit.copy_relocated_attributes(&skeleton);
+                let type_base = next_type_base;
+                next_type_base += unit.header.length_including_self();
+                units.push((unit, split, Some(skeleton), type_base));
+            }
+            None => units.push((skeleton, &dwarf, None, 0)),
+        }
+    }
+    let program_dwarf = &dwarf;
 
     // Define a mapping from type offsets to type structs
     let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
@@ -51,21 +123,32 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let mut compilation_units: Vec<File> = Vec::new();
 
     // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+    for (unit, dwarf, skeleton, type_base) in units {
+
+        // Types can refer to types defined after them, so collect all of them before resolving
+        // names and sizes and before looking at the variables that use them
+        let mut unit_types = Vec::new();
+        let mut tree = unit.entries_tree(None)?;
+        collect_types(tree.root()?, &unit, dwarf, type_base, &mut offset_to_type, &mut unit_types)?;
+        resolve_types(&mut offset_to_type, &unit_types);
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // The depth of the DW_TAG_inlined_subroutine being walked, if any: its variables belong
+        // to the inlined function, not the one it was inlined into
+        let mut inlined_depth: Option<isize> = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            if inlined_depth.is_some_and(|inlined| depth <= inlined) {
+                inlined_depth = None;
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
                     let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
+                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, dwarf) {
                             name
                         } else {
                             "<unknown>".to_string()
@@ -73,6 +156,16 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     } else {
                         "<unknown>".to_string()
                     };
+                    // The line program reports full paths, so resolve the name against the
+                    // compilation directory to be able to match line rows to this unit.
+                    let name = match unit.comp_dir {
+                        Some(ref comp_dir) if !name.starts_with('/') => {
+                            let mut path = path::PathBuf::from(comp_dir.to_string_lossy().as_ref());
+                            path.push(&name);
+                            path.to_string_lossy().into_owned()
+                        }
+                        _ => name,
+                    };
                     compilation_units.push(File {
                         name,
                         global_variables: Vec::new(),
@@ -80,38 +173,11 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
+                        let val = get_attr_value(&attr, &unit, dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
@@ -135,19 +201,85 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_frame_base => {
+                                if let gimli::AttributeValue::Exprloc(ref expression) = attr.value() {
+                                    func.frame_base = expression_bytes(expression, &unit).unwrap_or_default();
+                                }
+                            }
+                            gimli::DW_AT_abstract_origin | gimli::DW_AT_specification => {
+                                if let Some((name, _, line_number)) = abstract_origin(&attr, &unit, dwarf)
+                                    && func.name.is_empty()
+                                {
+                                    func.name = name;
+                                    func.line_number = line_number.try_into().unwrap();
+                                }
+                            }
                             _ => {}
                         }
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
+                gimli::DW_TAG_inlined_subroutine => {
+                    inlined_depth.get_or_insert(depth);
+                }
+                gimli::DW_TAG_call_site | gimli::DW_TAG_GNU_call_site => {
+                    let mut call_site: CallSite = Default::default();
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        // DWARF 5 has DW_AT_call_return_pc; the GNU extension for DWARF 4 puts the
+                        // return address in DW_AT_low_pc
+                        if let gimli::DW_AT_call_return_pc | gimli::DW_AT_low_pc = attr.name()
+                            && let Ok(DebugValue::Uint(addr)) = get_attr_value(&attr, &unit, dwarf)
+                        {
+                            call_site.return_address = addr.try_into().unwrap();
+                        }
+                    }
+                    if let Some(func) = compilation_units.last_mut().and_then(|file| file.functions.last_mut()) {
+                        func.call_sites.push(call_site);
+                    }
+                }
+                gimli::DW_TAG_call_site_parameter | gimli::DW_TAG_GNU_call_site_parameter => {
+                    let mut register = None;
+                    let mut value = None;
+                    let mut attrs = entry.attrs();
+                    while let Some(attr) = attrs.next()? {
+                        let expression = match attr.value() {
+                            gimli::AttributeValue::Exprloc(expression) => expression,
+                            _ => continue,
+                        };
+                        match attr.name() {
+                            gimli::DW_AT_location => {
+                                let mut pc = expression.0;
+                                if let Ok(gimli::Operation::Register { register: reg }) =
+                                    gimli::Operation::parse(&mut pc, unit.encoding())
+                                {
+                                    register = Some(reg.0);
+                                }
+                            }
+                            gimli::DW_AT_call_value | gimli::DW_AT_GNU_call_site_value => {
+                                value = expression_bytes(&expression, &unit);
+                            }
+                            _ => {}
+                        }
+                    }
+                    let call_site = compilation_units
+                        .last_mut()
+                        .and_then(|file| file.functions.last_mut())
+                        .and_then(|func| func.call_sites.last_mut());
+                    if let (Some(call_site), Some(register), Some(value)) = (call_site, register, value) {
+                        call_site.parameters.push((register, value));
+                    }
+                }
+                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable if inlined_depth.is_some() => {}
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
+                    let mut is_declaration = false;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
+                        let val = get_attr_value(&attr, &unit, dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
@@ -157,30 +289,54 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                                    entity_type = offset_to_type.get(&(type_base + offset)).cloned();
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, dwarf) {
                                     location = Some(loc);
                                 }
                             }
+                            gimli::DW_AT_const_value => {
+                                location = constant_location(attr.value());
+                            }
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
                                 }
                             }
+                            gimli::DW_AT_abstract_origin => {
+                                if let Some((origin_name, type_offset, origin_line)) =
+                                    abstract_origin(&attr, &unit, dwarf)
+                                {
+                                    if name.is_empty() {
+                                        name = origin_name;
+                                        line_number = origin_line;
+                                    }
+                                    if entity_type.is_none() {
+                                        entity_type =
+                                            type_offset.and_then(|offset| offset_to_type.get(&(type_base + offset)).cloned());
+                                    }
+                                }
+                            }
+                            gimli::DW_AT_declaration => is_declaration = true,
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // Locals the optimizer got rid of have no location; declarations of globals
+                    // defined elsewhere don't either, but aren't variables in their own right
+                    if location.is_none() && depth > 1 && !is_declaration {
+                        location = Some(Location::OptimizedOut);
+                    }
+                    if let (Some(entity_type), Some(location)) = (entity_type, location)
+                        && !name.is_empty()
+                    {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            entity_type,
+                            location,
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
                         if depth == 1 {
                             compilation_units
@@ -206,21 +362,29 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
+        // Get line numbers. A split unit's line table stays in its skeleton
+        let (line_unit, line_dwarf) = match skeleton {
+            Some(ref skeleton) => (skeleton, program_dwarf),
+            None => (&unit, dwarf),
+        };
+        if let Some(program) = line_unit.line_program.clone() {
             // Iterate over the line program rows.
             let mut rows = program.rows();
             while let Some((header, row)) = rows.next_row()? {
                 if !row.end_sequence() {
                     // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
+                    // Directories may be relative to the compilation directory
+                    let mut path = match line_unit.comp_dir {
+                        Some(ref comp_dir) => path::PathBuf::from(comp_dir.to_string_lossy().as_ref()),
+                        None => path::PathBuf::new(),
+                    };
                     if let Some(file) = row.file(header) {
                         if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+                            path.push(line_dwarf.attr_string(line_unit, dir)?.to_string_lossy().as_ref());
                         }
                         path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
+                            line_dwarf
+                                .attr_string(line_unit, file.path_name())?
                                 .to_string_lossy()
                                 .as_ref(),
                         );
@@ -233,7 +397,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
+                    let line = row.line().map_or(0, |line| line.get());
 
                     if let Some(file) = file {
                         file.lines.push(Line {
@@ -246,13 +410,252 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// Returns where the `.dwo` file named by a skeleton unit should be, relative to its
+/// compilation directory.
+fn dwo_path<R: Reader>(unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Option<String> {
+    let dwo_name = dwarf.attr_string(unit, unit.dwo_name().ok()??).ok()?;
+    let mut path = path::PathBuf::new();
+    if let Some(ref comp_dir) = unit.comp_dir {
+        path.push(comp_dir.to_string_lossy().ok()?.as_ref());
+    }
+    path.push(dwo_name.to_string_lossy().ok()?.as_ref());
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Records the type defined by `node` and by its descendants in `types`, keyed by DIE offset
+/// plus `type_base`, and appends their keys to `collected`. Names of derived types (pointers, arrays, ...) and
+/// sizes that depend on other types are left for `resolve_types`.
+fn collect_types<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+    types: &mut HashMap<usize, Type>,
+    collected: &mut Vec<usize>,
+) -> Result<(), Error> {
+    let entry = node.entry().clone();
+    let offset = type_base + section_offset(entry.offset(), unit);
+    let name = match entry.attr(gimli::DW_AT_name)? {
+        Some(attr) => match get_attr_value(&attr, unit, dwarf)? {
+            DebugValue::Str(name) => Some(name),
+            _ => None,
+        },
+        None => None,
+    };
+    let target = match entry.attr(gimli::DW_AT_type)? {
+        Some(attr) => match get_attr_value(&attr, unit, dwarf)? {
+            DebugValue::Size(offset) => Some(type_base + offset),
+            _ => None,
+        },
+        None => None,
+    };
+    let byte_size = entry
+        .attr_value(gimli::DW_AT_byte_size)?
+        .and_then(|value| value.udata_value())
+        .unwrap_or(0) as usize;
+    // A tagged type is called e.g. `struct point`, or `struct {...}` if it's anonymous
+    let tagged_name = |tag: &str| format!("{} {}", tag, name.as_deref().unwrap_or("{...}"));
+
+    // Members, enumerators, array bounds and parameters are children of the type's DIE
+    let mut members = Vec::new();
+    let mut enumerators = Vec::new();
+    let mut dimensions = Vec::new();
+    let mut params = Vec::new();
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let child_entry = child.entry().clone();
+        let child_name = match child_entry.attr(gimli::DW_AT_name)? {
+            Some(attr) => match get_attr_value(&attr, unit, dwarf)? {
+                DebugValue::Str(name) => name,
+                _ => String::new(),
+            },
+            None => String::new(),
+        };
+        let child_type = match child_entry.attr(gimli::DW_AT_type)? {
+            Some(attr) => match get_attr_value(&attr, unit, dwarf)? {
+                DebugValue::Size(offset) => Some(type_base + offset),
+                _ => None,
+            },
+            None => None,
+        };
+        match child_entry.tag() {
+            gimli::DW_TAG_member => {
+                let member_offset = child_entry
+                    .attr_value(gimli::DW_AT_data_member_location)?
+                    .and_then(|value| value.udata_value())
+                    .unwrap_or(0);
+                if let Some(type_id) = child_type {
+                    members.push(Member {
+                        name: child_name,
+                        type_id,
+                        offset: member_offset as usize,
+                    });
+                }
+            }
+            gimli::DW_TAG_enumerator => {
+                let value = match child_entry.attr_value(gimli::DW_AT_const_value)? {
+                    Some(gimli::AttributeValue::Sdata(value)) => value,
+                    Some(value) => value.udata_value().unwrap_or(0) as i64,
+                    None => 0,
+                };
+                enumerators.push((child_name, value));
+            }
+            gimli::DW_TAG_subrange_type => {
+                let count = match child_entry.attr_value(gimli::DW_AT_count)? {
+                    Some(count) => count.udata_value(),
+                    None => child_entry
+                        .attr_value(gimli::DW_AT_upper_bound)?
+                        .and_then(|bound| bound.udata_value())
+                        .map(|bound| bound + 1),
+                };
+                let child_offset = type_base + section_offset(child_entry.offset(), unit);
+                dimensions.push((child_offset, count.map(|count| count as usize)));
+            }
+            gimli::DW_TAG_formal_parameter if entry.tag() == gimli::DW_TAG_subroutine_type => {
+                if let Some(param) = child_type {
+                    params.push(param);
+                }
+            }
+            _ => {}
+        }
+        collect_types(child, unit, dwarf, type_base, types, collected)?;
+    }
+
+    let (name, size, encoding, kind) = match entry.tag() {
+        gimli::DW_TAG_base_type => {
+            let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                Some(gimli::AttributeValue::Encoding(ate)) => match ate {
+                    gimli::DW_ATE_signed => Encoding::Signed,
+                    gimli::DW_ATE_unsigned => Encoding::Unsigned,
+                    gimli::DW_ATE_signed_char => Encoding::SignedChar,
+                    gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+                    gimli::DW_ATE_float => Encoding::Float,
+                    gimli::DW_ATE_boolean => Encoding::Boolean,
+                    _ => Encoding::Unknown,
+                },
+                _ => Encoding::Unknown,
+            };
+            let name = name.unwrap_or_else(|| "<unknown>".to_string());
+            (name, byte_size, encoding, TypeKind::Base)
+        }
+        gimli::DW_TAG_pointer_type => {
+            let size = unit.encoding().address_size as usize;
+            (String::new(), size, Encoding::Pointer, TypeKind::Pointer(target))
+        }
+        gimli::DW_TAG_structure_type => {
+            (tagged_name("struct"), byte_size, Encoding::Unknown, TypeKind::Struct(members))
+        }
+        gimli::DW_TAG_union_type => {
+            (tagged_name("union"), byte_size, Encoding::Unknown, TypeKind::Union(members))
+        }
+        gimli::DW_TAG_enumeration_type => {
+            let encoding = if enumerators.iter().any(|(_, value)| *value < 0) {
+                Encoding::Signed
+            } else {
+                Encoding::Unsigned
+            };
+            (tagged_name("enum"), byte_size, encoding, TypeKind::Enum(enumerators))
+        }
+        gimli::DW_TAG_array_type => {
+            let element = match target {
+                Some(element) => element,
+                None => return Ok(()),
+            };
+            // int a[2][3] is an array of 2 arrays of 3 ints. The inner arrays have no DIE of
+            // their own, so they're keyed by the offsets of their subranges.
+            let mut element = element;
+            for &(subrange, count) in dimensions.iter().skip(1).rev() {
+                types.insert(
+                    subrange,
+                    Type {
+                        kind: TypeKind::Array(element, count),
+                        ..Default::default()
+                    },
+                );
+                collected.push(subrange);
+                element = subrange;
+            }
+            let count = dimensions.first().and_then(|(_, count)| *count);
+            (String::new(), 0, Encoding::Unknown, TypeKind::Array(element, count))
+        }
+        gimli::DW_TAG_typedef => {
+            let name = name.unwrap_or_else(|| "<unknown>".to_string());
+            (name, 0, Encoding::Unknown, TypeKind::Typedef(target))
+        }
+        gimli::DW_TAG_const_type => (String::new(), 0, Encoding::Unknown, TypeKind::Qualified("const", target)),
+        gimli::DW_TAG_volatile_type => {
+            (String::new(), 0, Encoding::Unknown, TypeKind::Qualified("volatile", target))
+        }
+        gimli::DW_TAG_restrict_type => {
+            (String::new(), 0, Encoding::Unknown, TypeKind::Qualified("restrict", target))
+        }
+        // sizeof on a function is 1 in GNU C
+        gimli::DW_TAG_subroutine_type => (String::new(), 1, Encoding::Unknown, TypeKind::Function(target, params)),
+        _ => return Ok(()),
+    };
+    types.insert(
+        offset,
+        Type {
+            name,
+            size,
+            encoding,
+            kind,
+        },
+    );
+    collected.push(offset);
+    Ok(())
+}
+
+/// Fills in the names of derived types, and the sizes and encodings of types that take them
+/// from the types they're built from, now that all of those are known.
+fn resolve_types(types: &mut HashMap<usize, Type>, ids: &[usize]) {
+    for id in ids {
+        let ty = &types[id];
+        let name = match ty.kind {
+            TypeKind::Pointer(_) | TypeKind::Array(..) | TypeKind::Qualified(..) | TypeKind::Function(..) => {
+                dwarf_data::type_name(types, ty)
+            }
+            _ => ty.name.clone(),
+        };
+        let (size, encoding) = layout(types, ty, 0);
+        let ty = types.get_mut(id).unwrap();
+        ty.name = name;
+        ty.size = size;
+        ty.encoding = encoding;
+    }
+}
+
+/// Works out the size and encoding of a type, which typedefs, qualifiers and arrays take from
+/// the types they're built from. `depth` guards against cycles in malformed debug info.
+fn layout(types: &HashMap<usize, Type>, ty: &Type, depth: usize) -> (usize, Encoding) {
+    let target = |id: &usize| match types.get(id) {
+        Some(target) if depth < 32 => layout(types, target, depth + 1),
+        _ => (0, Encoding::Unknown),
+    };
+    match &ty.kind {
+        TypeKind::Typedef(Some(id)) | TypeKind::Qualified(_, Some(id)) => target(id),
+        TypeKind::Array(element, count) => (target(element).0 * count.unwrap_or(0), Encoding::Unknown),
+        _ => (ty.size, ty.encoding),
+    }
+}
+
+/// Converts the offset of a DIE within its unit to its offset in the section, which is what
+/// references to it resolve to.
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
+    #[allow(dead_code)]
     Int(i64),
     Size(usize),
     NoVal,
@@ -260,33 +663,26 @@ pub enum DebugValue {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
-    ObjectError(String),
-    IoError,
+    Gimli(gimli::Error),
+    Object(String),
+    Io,
 }
 
 impl From<gimli::Error> for Error {
     fn from(err: gimli::Error) -> Self {
-        Error::GimliError(err)
-    }
-}
-
-impl From<addr2line::gimli::Error> for Error {
-    fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
+        Error::Gimli(err)
     }
 }
 
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
 impl From<std::fmt::Error> for Error {
     fn from(_: std::fmt::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
@@ -297,25 +693,127 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+/// Reads a DW_AT_location. A lone DW_OP_addr or DW_OP_fbreg becomes an address or a frame base
+/// offset; anything else is kept as bytecode, or as a location list, to evaluate when needed.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+        // gcc's -gsplit-dwarf for DWARF 4 emits the GNU spelling of DW_OP_addrx
+        if pc.read_u8() == Ok(DW_OP_GNU_ADDR_INDEX)
+            && let Ok(index) = pc.read_uleb128()
+            && pc.is_empty()
+        {
+            let address = dwarf.address(unit, gimli::DebugAddrIndex(index as usize)).ok()?;
+            return Some(Location::Address(address.try_into().unwrap()));
+        }
+        let mut pc = data.0.clone();
+        if let Ok(op) = gimli::Operation::parse(&mut pc, unit.encoding())
+            && pc.is_empty()
+        {
+            match op {
+                gimli::Operation::FrameOffset { offset } => {
+                    return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
                 }
+                gimli::Operation::Address { address } => {
+                    return Some(Location::Address(address.try_into().unwrap()));
+                }
+                // Split units keep addresses in the program's .debug_addr
+                gimli::Operation::AddressIndex { index } => {
+                    return Some(Location::Address(dwarf.address(unit, index).ok()?.try_into().unwrap()));
+                }
+                _ => {}
             }
         }
+        return match expression_bytes(data, unit)? {
+            bytes if bytes.is_empty() => Some(Location::OptimizedOut),
+            bytes => Some(Location::Expression(bytes)),
+        };
+    }
+    let mut locations = dwarf.attr_locations(unit, attr.value()).ok()??;
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = locations.next() {
+        entries.push(LocationListEntry {
+            begin: entry.range.begin.try_into().unwrap(),
+            end: entry.range.end.try_into().unwrap(),
+            expression: expression_bytes(&entry.data, unit)?,
+        });
+    }
+    Some(Location::List(entries))
+}
+
+/// gcc marks values that aren't initialized yet with this GNU extension, which gimli can't parse.
+const DW_OP_GNU_UNINIT: u8 = 0xf0;
+/// Pre-DWARF 5 split units refer to addresses in `.debug_addr` with this GNU extension.
+const DW_OP_GNU_ADDR_INDEX: u8 = 0xfb;
+
+/// Copies out the bytecode of a DWARF expression, dropping a trailing DW_OP_GNU_uninit.
+fn expression_bytes<R: Reader>(expression: &gimli::Expression<R>, unit: &gimli::Unit<R>) -> Option<Vec<u8>> {
+    let mut bytes = expression.0.to_slice().ok()?.into_owned();
+    let mut pc = expression.0.clone();
+    while pc.len() > 1 {
+        if gimli::Operation::parse(&mut pc, unit.encoding()).is_err() {
+            return Some(bytes);
+        }
     }
-    None
+    if pc.len() == 1 && bytes.last() == Some(&DW_OP_GNU_UNINIT) {
+        bytes.pop();
+    }
+    Some(bytes)
+}
+
+/// Turns a DW_AT_const_value into a DW_OP_implicit_value expression, so that constants the
+/// compiler folded away are read like any other value without a location.
+fn constant_location<R: Reader>(value: gimli::AttributeValue<R>) -> Option<Location> {
+    let bytes = match value {
+        gimli::AttributeValue::Block(data) => data.to_slice().ok()?.into_owned(),
+        gimli::AttributeValue::Sdata(value) => value.to_le_bytes().to_vec(),
+        value => value.udata_value()?.to_le_bytes().to_vec(),
+    };
+    let mut expression = vec![gimli::DW_OP_implicit_value.0];
+    let mut len = bytes.len();
+    // ULEB128 length
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            expression.push(byte);
+            break;
+        }
+        expression.push(byte | 0x80);
+    }
+    expression.extend(bytes);
+    Some(Location::Expression(expression))
+}
+
+/// Follows DW_AT_abstract_origin (or DW_AT_specification) to the DIE describing an inline
+/// function or one of its variables, and returns the name, type offset and line it declares.
+/// Out-of-line copies of inline functions only refer to these.
+fn abstract_origin<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<(String, Option<usize>, u64)> {
+    let origin = match attr.value() {
+        gimli::AttributeValue::UnitRef(offset) => unit.entry(offset).ok()?,
+        _ => return None,
+    };
+    let mut name = None;
+    let mut type_offset = None;
+    let mut line_number = 0;
+    let mut attrs = origin.attrs();
+    while let Ok(Some(attr)) = attrs.next() {
+        match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+            (gimli::DW_AT_name, Ok(DebugValue::Str(origin_name))) => name = Some(origin_name),
+            (gimli::DW_AT_type, Ok(DebugValue::Size(offset))) => type_offset = Some(offset),
+            (gimli::DW_AT_decl_line, Ok(DebugValue::Uint(num))) => line_number = num,
+            _ => {}
+        }
+    }
+    Some((name?, type_offset, line_number))
 }
 
 // based on dwarf_dump.rs
@@ -350,6 +848,13 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        // Split units refer to strings and addresses through tables, and DWARF 5 keeps file
+        // and directory names in .debug_line_str
+        gimli::AttributeValue::DebugStrOffsetsIndex(_) | gimli::AttributeValue::DebugLineStrRef(_) => {
+            let s = dwarf.attr_string(unit, attr.value())?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => Ok(DebugValue::Uint(dwarf.address(unit, index)?)),
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
@@ -392,10 +897,10 @@ fn dump_file_index<R: Reader, W: Write>(
     if let Some(directory) = file.directory(header) {
         let directory = dwarf.attr_string(unit, directory)?;
         let directory = directory.to_string_lossy()?;
-        if !directory.starts_with('/') {
-            if let Some(ref comp_dir) = unit.comp_dir {
-                write!(w, "{}/", comp_dir.to_string_lossy()?,)?;
-            }
+        if !directory.starts_with('/')
+            && let Some(ref comp_dir) = unit.comp_dir
+        {
+            write!(w, "{}/", comp_dir.to_string_lossy()?,)?;
         }
         write!(w, "{}/", directory)?;
     }
@@ -587,6 +1092,11 @@ fn dump_op<R: Reader, W: Write>(
         gimli::Operation::Reinterpret { base_type } => {
             write!(w, " type 0x{:08x}", base_type.0)?;
         }
+        gimli::Operation::WasmLocal { index }
+        | gimli::Operation::WasmGlobal { index }
+        | gimli::Operation::WasmStack { index } => {
+            write!(w, " {}", index)?;
+        }
         gimli::Operation::Drop
         | gimli::Operation::Swap
         | gimli::Operation::Rot
@@ -5,13 +5,70 @@ use nix::unistd::Pid;
 use std::process::Child;
 use std::process::Command;
 use std::os::unix::process::CommandExt;
+use crate::breakpoint::Breakpoints;
+use crate::coredump::{Core, CoreThread};
+use crate::debug_file;
 use crate::dwarf_data::{DwarfData};
-use std::mem::size_of;
+use crate::signals::{self, SignalTable};
+use crate::solib::{self, SharedLibraries};
+use crate::unwind::{FrameRegisters, Modules};
+use crate::watchpoint::{DR_CONTROL, DR_STATUS, NUM_DEBUG_REGISTERS};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::mem::{self, offset_of, size_of, MaybeUninit};
+use std::ptr;
+
+/// A stack frame found by unwinding.
+#[derive(Clone)]
+pub struct Frame {
+    /// Where execution is, or for callers, where it will resume.
+    pub rip: usize,
+    /// The address to look up source information for: `rip`, or for callers, the call
+    /// instruction.
+    pub pc: usize,
+    /// The registers unwinding recovered for this frame.
+    pub registers: FrameRegisters,
+    /// The canonical frame address: the caller's rsp just before the call, if the frame could
+    /// be unwound.
+    pub cfa: Option<usize>,
+}
+
+/// A file mapped into the inferior's address space.
+#[derive(Clone)]
+pub struct Mapping {
+    pub path: String,
+    pub start: u64,
+    pub end: u64,
+    /// Offset in the file of the start of the mapping.
+    pub offset: u64,
+}
+
+/// Backtraces stop after this many frames, in case the stack is corrupted.
+const MAX_BACKTRACE_DEPTH: usize = 256;
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum FollowForkMode {
+    /// Keep debugging the parent and let the child run freely.
+    Parent,
+    /// Switch to the child and let the parent run freely.
+    Child,
+}
+
+impl fmt::Display for FollowForkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FollowForkMode::Parent => write!(f, "parent"),
+            FollowForkMode::Child => write!(f, "child"),
+        }
+    }
+}
+
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -23,154 +80,1096 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior called exec and is stopped at the start of the new program. None
+    /// of our breakpoints survive in the new image.
+    Exec,
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
 }
 
+/// A thread of the inferior. Threads are numbered from 1 in the order we learn about them.
+pub struct Thread {
+    pub number: usize,
+    pub tid: Pid,
+}
+
+/// A traced process. All of its threads are traced, and the inferior runs in all-stop mode:
+/// whenever one thread stops, the others are stopped too before control returns to the user.
+///
+/// An inferior can also be a core dump of a process that crashed, in which case its registers
+/// and memory are read from the core and it can't be resumed or modified.
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The process handle if we spawned the inferior ourselves.
+    child: Option<Child>,
+    /// True if we attached to the process rather than starting it.
+    attached: bool,
+    /// Which process to keep debugging when the inferior forks.
+    pub follow_fork_mode: FollowForkMode,
+    /// Which signals stop the inferior, and which are passed on to it.
+    pub signals: SignalTable,
+    /// Where to look for the debug info of stripped libraries.
+    pub debug_file_directories: Vec<String>,
+    threads: Vec<Thread>,
+    next_thread_number: usize,
+    /// The selected thread, whose registers are used for stepping, backtraces and printing.
+    current: Pid,
+    /// Threads we sent a SIGSTOP (to stop them along with another thread) that haven't reported
+    /// it yet. The SIGSTOP is swallowed when it shows up.
+    pending_stops: HashSet<Pid>,
+    /// Signals that arrived while we were stopping a thread, delivered when it is resumed.
+    pending_signals: HashMap<Pid, signal::Signal>,
+    /// Threads that hit a breakpoint while we were stopping them. Their %rip was rewound so that
+    /// they hit it again (and get reported) once resumed.
+    deferred: HashSet<Pid>,
+    /// New threads or processes whose initial SIGSTOP arrived before the event announcing them.
+    early_stops: HashSet<Pid>,
+    /// The core dump this inferior was loaded from, if it isn't a live process.
+    core: Option<Core>,
+    libraries: SharedLibraries,
 }
 
 impl Inferior {
+    fn with_main_thread(pid: Pid, child: Option<Child>, attached: bool) -> Inferior {
+        Inferior {
+            pid,
+            child,
+            attached,
+            follow_fork_mode: FollowForkMode::Parent,
+            signals: SignalTable::new(),
+            debug_file_directories: vec![debug_file::DEFAULT_DEBUG_FILE_DIRECTORY.to_string()],
+            threads: vec![Thread { number: 1, tid: pid }],
+            next_thread_number: 2,
+            current: pid,
+            pending_stops: HashSet::new(),
+            pending_signals: HashMap::new(),
+            deferred: HashSet::new(),
+            early_stops: HashSet::new(),
+            core: None,
+            libraries: SharedLibraries::default(),
+        }
+    }
+
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &Vec<usize>) -> Option<Inferior> {
-        // TODO: implement me!
+    /// an error is encountered. It's stopped before the first instruction, without breakpoints.
+    pub fn new(target: &str, args: &Vec<String>) -> Option<Inferior> {
         let mut cmd = Command::new(target);
         cmd.args(args);
         unsafe {
             cmd.pre_exec(child_traceme);
         }
         let child = cmd.spawn().ok()?;
-        let inf = Inferior { child: child };
+        let pid = Pid::from_raw(child.id() as i32);
+        let inf = Inferior::with_main_thread(pid, Some(child), false);
         match inf.wait(None).ok()? {
-            Status::Stopped(signal, ip) => Some(inf),
-            _ => None
+            Status::Stopped(_signal, _ip) => {},
+            _ => return None,
+        }
+        set_trace_options(pid).ok()?;
+        Some(inf)
+    }
+
+    /// Attaches to an already-running process (all of its threads) with PTRACE_ATTACH and waits
+    /// for it to stop. If that fails partway, the threads already attached are let go again.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        ptrace::attach(pid)?;
+        let mut inf = Inferior::with_main_thread(pid, None, true);
+        if let Err(err) = inf.attach_threads() {
+            // Leave the process running as we found it
+            let _ = inf.release();
+            return Err(err);
         }
-        for (b, orig) in breakpoints {
-            match inf.write_byte(b.clone(), 0xcc) {
-                Ok(val) => {
-                    *orig = val;
-                }, 
-                Err(err) => {
-                    println!("Cannot Set breakpoint at 0x{:#x}. Got Error {}", b, err);
+        Ok(inf)
+    }
+
+    /// Waits for the main thread to stop after PTRACE_ATTACH, then attaches to the others.
+    fn attach_threads(&mut self) -> Result<(), nix::Error> {
+        let pid = self.pid();
+        // PTRACE_ATTACH stops the process with a SIGSTOP, which we swallow when resuming.
+        self.wait(None)?;
+        set_trace_options(pid)?;
+        // PTRACE_ATTACH only takes hold of a single thread
+        if let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", pid)) {
+            for task in tasks.flatten() {
+                let tid = match task.file_name().to_str().and_then(|name| name.parse().ok()) {
+                    Some(tid) => Pid::from_raw(tid),
+                    None => continue,
+                };
+                if tid == pid {
+                    continue;
+                }
+                match ptrace::attach(tid) {
+                    Ok(()) => {}
+                    // The thread exited since we listed it
+                    Err(nix::Error::ESRCH) => continue,
+                    Err(err) => return Err(err),
                 }
+                self.add_thread(tid);
+                match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                    WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                        self.remove_thread(tid);
+                        continue;
+                    }
+                    _ => {}
+                }
+                set_trace_options(tid)?;
             }
         }
+        Ok(())
+    }
 
-        
-        // println!(
-        //     "Inferior::new not implemented! target={}, args={:?}",
-        //     target, args
-        // );
-        // None
+    /// Makes an inferior out of a core dump. The thread that got the fatal signal is thread 1
+    /// and is selected.
+    pub fn from_core(core: Core) -> Inferior {
+        let mut inf = Inferior::with_main_thread(core.pid, None, false);
+        inf.threads.clear();
+        inf.next_thread_number = 1;
+        for thread in core.threads.iter() {
+            inf.add_thread(thread.tid);
+        }
+        inf.current = core.threads[0].tid;
+        inf.core = Some(core);
+        inf
     }
 
-    pub fn cont(&self, breakpoints: HashMap<usize, u8>) -> Result<Status, nix::Error>  {
-        let regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        
-        if let Some(val) = breakpoints.get(&(rip - 1)) {
-            self.write_byte(rip - 1, *val).unwrap();
+    /// Returns true if this inferior is a core dump rather than a live process.
+    pub fn is_core(&self) -> bool {
+        self.core.is_some()
+    }
 
-            regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
+    /// Returns the core dump's record of thread `tid`, or EPERM if this is a live process.
+    fn core_thread(&self, tid: Pid) -> Result<&CoreThread, nix::Error> {
+        match &self.core {
+            Some(core) => core.thread(tid).ok_or(nix::Error::ESRCH),
+            None => Err(nix::Error::EPERM),
+        }
+    }
 
-            ptrace::step(self.pid(), None).unwrap();
+    /// Fails with EPERM if this is a core dump, which can't be modified.
+    fn check_live(&self) -> Result<(), nix::Error> {
+        match self.core {
+            Some(_) => Err(nix::Error::EPERM),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if this inferior was attached to rather than spawned by us.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Restores every byte we replaced with 0xcc, clears the debug registers and lets the
+    /// process continue running on its own.
+    pub fn detach(self, breakpoints: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        Self::remove_breakpoints(self.tid(), breakpoints)?;
+        self.set_debug_register(DR_CONTROL, 0)?;
+        self.release()
+    }
+
+    /// Detaches from every thread of the process.
+    fn release(&self) -> Result<(), nix::Error> {
+        for thread in self.threads.iter() {
+            ptrace::detach(thread.tid, self.pending_signals.get(&thread.tid).copied())?;
+        }
+        if !self.pending_stops.is_empty() {
+            // Cancel the SIGSTOPs we sent so the process doesn't stop once we're gone
+            signal::kill(self.pid(), signal::Signal::SIGCONT)?;
+        }
+        Ok(())
+    }
+
+    /// Puts the original bytes back at every breakpoint address of process `pid`.
+    fn remove_breakpoints(pid: Pid, breakpoints: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        for (addr, orig) in breakpoints.iter() {
+            write_byte(pid, *addr, *orig)?;
+        }
+        Ok(())
+    }
 
-            match self.wait(None).unwrap() {
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                Status::Stopped(pid, signal) => {
-                    self.write_byte(rip - 1, 0xcc).unwrap(); 
+    /// Resumes the inferior until it hits a breakpoint, receives a signal or exits. When the
+    /// inferior stops on one of our breakpoints, %rip is rewound to the breakpoint address.
+    /// Breakpoints whose condition is false (or that are being ignored) are stepped over
+    /// transparently.
+    pub fn cont(&mut self, debug_data: &DwarfData, breakpoints: &mut Breakpoints) -> Result<Status, nix::Error>  {
+        loop {
+            if let Some(status) = self.step_over_breakpoint(breakpoints)? {
+                match status {
+                    Status::Stopped(signal::Signal::SIGTRAP, _) => {
+                        // The instruction under the breakpoint may itself touch a watched value
+                        if self.check_watchpoints(breakpoints)? {
+                            return Ok(status);
+                        }
+                    }
+                    _ => return Ok(status),
                 }
             }
-        } 
+            self.step_others_over_breakpoints(breakpoints)?;
 
+            match self.resume(false, breakpoints)? {
+                Status::Stopped(signal::Signal::SIGTRAP, rip)
+                    if breakpoints.saved_bytes().contains_key(&(rip - 1)) =>
+                {
+                    let mut regs = ptrace::getregs(self.tid())?;
+                    regs.rip = (rip - 1) as u64;
+                    ptrace::setregs(self.tid(), regs)?;
+                    if self.libraries.is_event(rip - 1) {
+                        self.load_libraries(breakpoints)?;
+                        if !breakpoints.has_breakpoint_at(rip - 1) {
+                            continue;
+                        }
+                    }
+                    if breakpoints.should_stop(rip - 1, self, debug_data) {
+                        return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip - 1));
+                    }
+                }
+                status @ Status::Stopped(signal::Signal::SIGTRAP, _) => {
+                    // Write watchpoints whose value didn't actually change are passed over
+                    let triggered = self.take_watch_triggers()?;
+                    if triggered == 0 || breakpoints.watchpoints_should_stop(triggered, self) {
+                        return Ok(status);
+                    }
+                }
+                status => return Ok(status),
+            }
+        }
+    }
 
-        ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+    /// If %rip sits on one of our breakpoints, temporarily restores the original byte, executes
+    /// that single instruction and re-inserts the 0xcc. Returns None if there was no breakpoint
+    /// to step over, or the status after the single step otherwise.
+    fn step_over_breakpoint(&mut self, breakpoints: &mut Breakpoints) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.tid())?.rip as usize;
+        let orig = match breakpoints.saved_bytes().get(&rip) {
+            Some(orig) => *orig,
+            None => return Ok(None),
+        };
+        self.write_byte(rip, orig)?;
+        let status = self.resume(true, breakpoints)?;
+        if let Status::Stopped(..) = status {
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(Some(status))
+    }
+
+    /// Moves the other threads that are sitting on a breakpoint they already reported past it,
+    /// so that they don't report it again as soon as they are resumed.
+    fn step_others_over_breakpoints(&mut self, breakpoints: &Breakpoints) -> Result<(), nix::Error> {
+        for tid in self.thread_ids() {
+            if tid == self.tid() || self.deferred.contains(&tid) {
+                continue;
+            }
+            let rip = ptrace::getregs(tid)?.rip as usize;
+            let orig = match breakpoints.saved_bytes().get(&rip) {
+                Some(orig) => *orig,
+                None => continue,
+            };
+            write_byte(tid, rip, orig)?;
+            loop {
+                self.resume_thread(tid, true)?;
+                match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                    WaitStatus::Stopped(_, signal::Signal::SIGSTOP) if self.pending_stops.remove(&tid) => {}
+                    WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                        self.remove_thread(tid);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            write_byte(tid, rip, 0xcc)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single machine instruction, noting any watchpoint it triggers.
+    pub fn step_instruction(&mut self, breakpoints: &mut Breakpoints) -> Result<Status, nix::Error> {
+        let status = match self.step_over_breakpoint(breakpoints)? {
+            Some(status) => status,
+            None => self.resume(true, breakpoints)?,
+        };
+        if let Status::Stopped(signal::Signal::SIGTRAP, _) = status {
+            self.check_watchpoints(breakpoints)?;
+        }
+        Ok(status)
+    }
+
+    /// Steps until the inferior reaches the beginning of a different source line. If `step_into`
+    /// is false, or the callee has no line information, calls are stepped over as a whole.
+    pub fn step_line(
+        &mut self,
+        debug_data: &DwarfData,
+        breakpoints: &mut Breakpoints,
+        step_into: bool,
+    ) -> Result<Status, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let start_line = debug_data.get_line_from_addr(regs.rip as usize);
+        // Without line information there's no line to step through, so leave the function, or
+        // just keep going if there's nothing to return to
+        if start_line.is_none() {
+            if self.in_outermost_frame(debug_data)? {
+                return self.cont(debug_data, breakpoints);
+            }
+            return self.finish(debug_data, breakpoints);
+        }
+        let start_func = debug_data.get_function_containing(regs.rip as usize).map(|f| f.address);
+        loop {
+            let before = ptrace::getregs(self.tid())?;
+            let mut status = self.step_instruction(breakpoints)?;
+            let mut rip = match status {
+                Status::Stopped(signal::Signal::SIGTRAP, _) if breakpoints.has_watch_hits() => {
+                    return Ok(status)
+                }
+                Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+                _ => return Ok(status),
+            };
+            let regs = ptrace::getregs(self.tid())?;
+
+            // A call pushes a return address that points just past the calling instruction.
+            if regs.rsp == before.rsp - 8 {
+                let ret_addr = ptrace::read(self.tid(), regs.rsp as ptrace::AddressType)? as usize;
+                if ret_addr > before.rip as usize && ret_addr <= before.rip as usize + 15 {
+                    let has_lines = debug_data.get_line_from_addr(rip).is_some();
+                    if !step_into || !has_lines {
+                        status = self.run_to_return(ret_addr, regs.rsp as usize, debug_data, breakpoints)?;
+                        match status {
+                            Status::Stopped(signal::Signal::SIGTRAP, addr) if addr == ret_addr => rip = addr,
+                            _ => return Ok(status),
+                        }
+                    }
+                }
+            }
+
+            if breakpoints.saved_bytes().contains_key(&rip) && breakpoints.should_stop(rip, self, debug_data) {
+                return Ok(status);
+            }
+            // We returned into code without line information (e.g. out of main into libc), so
+            // there is no source line to stop at.
+            if debug_data.get_line_from_addr(rip).is_none() {
+                return self.cont(debug_data, breakpoints);
+            }
+            let line = match debug_data.get_line_starting_at(rip) {
+                Some(line) => line,
+                None => continue,
+            };
+            let func = match debug_data.get_function_containing(rip) {
+                Some(func) => func,
+                None => continue,
+            };
+            // Skip over the prologue of a function we just stepped into so that its locals are
+            // set up by the time we stop.
+            if rip == func.address && Some(func.address) != start_func {
+                continue;
+            }
+            let same_line = match &start_line {
+                Some(start) => start.file == line.file && start.number == line.number,
+                None => false,
+            };
+            if !same_line || Some(func.address) != start_func {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Runs until the current function returns to its caller, as found by unwinding. Fails with
+    /// EINVAL without resuming in the outermost frame, which has no caller to return to.
+    pub fn finish(&mut self, debug_data: &DwarfData, breakpoints: &mut Breakpoints) -> Result<Status, nix::Error> {
+        let frames = self.frames(debug_data, 2)?;
+        match (frames.first().and_then(|frame| frame.cfa), frames.get(1)) {
+            (Some(cfa), Some(caller)) => self.run_to_return(caller.rip, cfa - 8, debug_data, breakpoints),
+            _ => Err(nix::Error::EINVAL),
+        }
+    }
+
+    /// Returns true if the current function has no caller that unwinding can find, which is
+    /// always the case in main.
+    pub fn in_outermost_frame(&self, debug_data: &DwarfData) -> Result<bool, nix::Error> {
+        Ok(self.frames(debug_data, 2)?.len() < 2)
+    }
+
+    /// Continues until the frame whose return address is stored at `ret_slot` returns to
+    /// `ret_addr`. A temporary breakpoint is used, and hits from deeper (recursive) frames are
+    /// ignored, as are hits from other threads. Stops early on user breakpoints, signals or exit.
+    fn run_to_return(
+        &mut self,
+        ret_addr: usize,
+        ret_slot: usize,
+        debug_data: &DwarfData,
+        breakpoints: &mut Breakpoints,
+    ) -> Result<Status, nix::Error> {
+        let tid = self.tid();
+        breakpoints.insert_temporary(ret_addr, self)?;
+        let status = loop {
+            let status = self.cont(debug_data, breakpoints)?;
+            match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) if rip == ret_addr => {
+                    let rsp = ptrace::getregs(self.tid())?.rsp as usize;
+                    if (self.tid() == tid && rsp > ret_slot) || breakpoints.was_hit() {
+                        break status;
+                    }
+                }
+                _ => break status,
+            }
+        };
+        let inferior = match status {
+            Status::Stopped(..) => Some(self),
+            _ => None,
+        };
+        breakpoints.remove_temporary(ret_addr, inferior)?;
+        Ok(status)
+    }
+
+    /// Resumes the inferior and waits for it to stop. If `step` is set, only the current thread
+    /// executes a single instruction; otherwise every thread runs until one of them stops, and
+    /// the rest are stopped too. New threads, thread exits and forks along the way are handled
+    /// here.
+    fn resume(&mut self, step: bool, breakpoints: &mut Breakpoints) -> Result<Status, nix::Error> {
+        let mut step = step;
+        if step {
+            self.resume_thread(self.tid(), true)?;
+        } else {
+            self.deferred.clear();
+            for tid in self.thread_ids() {
+                self.resume_thread(tid, false)?;
+            }
+        }
+        loop {
+            let status = waitpid(None, Some(WaitPidFlag::__WALL))?;
+            let tid = match status.pid() {
+                Some(tid) => tid,
+                None => continue,
+            };
+            if !self.has_thread(tid) {
+                // Either a new thread or process that stopped before we heard of it, or a
+                // process we have detached from
+                if let WaitStatus::Stopped(_, signal::Signal::SIGSTOP) = status {
+                    self.early_stops.insert(tid);
+                }
+                continue;
+            }
+            match status {
+                WaitStatus::PtraceEvent(_pid, _signal, event) => {
+                    match event {
+                        libc::PTRACE_EVENT_CLONE => {
+                            let new_tid = self.trace_new_thread(tid)?;
+                            if !step {
+                                self.resume_thread(new_tid, false)?;
+                            }
+                        }
+                        libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK => {
+                            if self.follow_fork_mode == FollowForkMode::Child && !step {
+                                self.current = tid;
+                                self.stop_others(breakpoints)?;
+                            }
+                            self.follow_fork(tid, event == libc::PTRACE_EVENT_VFORK, breakpoints)?;
+                        }
+                        libc::PTRACE_EVENT_VFORK_DONE => {
+                            // The vfork child has exec'd or exited and no longer shares our memory
+                            for addr in breakpoints.saved_bytes().keys() {
+                                self.write_byte(*addr, 0xcc)?;
+                            }
+                        }
+                        libc::PTRACE_EVENT_EXEC => {
+                            // Every other thread is gone, and the one that called exec has taken
+                            // over the main thread's id.
+                            let pid = self.pid();
+                            self.threads.retain(|thread| thread.tid == pid);
+                            if self.threads.is_empty() {
+                                self.add_thread(pid);
+                            }
+                            self.current = pid;
+                            self.pending_stops.clear();
+                            self.pending_signals.clear();
+                            self.libraries = SharedLibraries::default();
+                            return Ok(Status::Exec);
+                        }
+                        _ => {}
+                    }
+                    // After following a fork child, it's the child that carries on
+                    let tid = if self.has_thread(tid) { tid } else { self.tid() };
+                    self.resume_thread(tid, step)?;
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) if self.pending_stops.remove(&tid) => {
+                    self.resume_thread(tid, step)?;
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) if tid != self.pid() => {
+                    self.remove_thread(tid);
+                    println!("[Thread {} exited]", tid);
+                    if step && tid == self.tid() {
+                        // The thread we were stepping is gone; let the others carry on
+                        self.current = self.pid();
+                        step = false;
+                        for tid in self.thread_ids() {
+                            self.resume_thread(tid, false)?;
+                        }
+                    }
+                }
+                WaitStatus::Stopped(_, signal)
+                    if signal != signal::Signal::SIGTRAP && !self.signals.get(signal).stop =>
+                {
+                    let policy = self.signals.get(signal);
+                    if policy.print {
+                        println!("Program received signal {}, {}.", signal, signals::description(signal));
+                    }
+                    if policy.pass {
+                        self.pending_signals.insert(tid, signal);
+                    }
+                    self.resume_thread(tid, step)?;
+                }
+                WaitStatus::Continued(..) | WaitStatus::StillAlive => {}
+                status => {
+                    // The signal is delivered when the thread resumes, unless it's to be ignored
+                    if let WaitStatus::Stopped(_, signal) = status
+                        && signal != signal::Signal::SIGTRAP && self.signals.get(signal).pass
+                    {
+                        self.pending_signals.insert(tid, signal);
+                    }
+                    self.current = tid;
+                    if !step && matches!(status, WaitStatus::Stopped(..)) {
+                        self.stop_others(breakpoints)?;
+                    }
+                    return self.status_from(status);
+                }
+            }
+        }
+    }
+
+    /// Resumes a single stopped thread, delivering any signal it received while being stopped.
+    fn resume_thread(&mut self, tid: Pid, step: bool) -> Result<(), nix::Error> {
+        let signal = self.pending_signals.remove(&tid);
+        if step {
+            ptrace::step(tid, signal)
+        } else {
+            ptrace::cont(tid, signal)
+        }
+    }
+
+    /// Stops every thread except the current one, which has just stopped.
+    fn stop_others(&mut self, breakpoints: &Breakpoints) -> Result<(), nix::Error> {
+        let others: Vec<Pid> = self.thread_ids().into_iter().filter(|tid| *tid != self.tid()).collect();
+        for tid in others.iter() {
+            // A thread that has just exited will tell us so below
+            let _ = tgkill(self.pid(), *tid, signal::Signal::SIGSTOP);
+        }
+        for tid in others {
+            // The thread may report something else before our SIGSTOP, which then stays pending
+            match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => continue,
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {
+                    let mut regs = ptrace::getregs(tid)?;
+                    let rip = regs.rip as usize;
+                    if breakpoints.saved_bytes().contains_key(&(rip - 1)) {
+                        regs.rip = (rip - 1) as u64;
+                        ptrace::setregs(tid, regs)?;
+                        self.deferred.insert(tid);
+                    }
+                }
+                WaitStatus::Stopped(_, signal) if self.signals.get(signal).pass => {
+                    self.pending_signals.insert(tid, signal);
+                }
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE) => {
+                    // The new thread stays stopped along with the rest
+                    self.trace_new_thread(tid)?;
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    self.remove_thread(tid);
+                    println!("[Thread {} exited]", tid);
+                    continue;
+                }
+                _ => {}
+            }
+            self.pending_stops.insert(tid);
+        }
+        Ok(())
+    }
+
+    /// Picks up the thread created by `parent` (announced by PTRACE_EVENT_CLONE). It is left
+    /// stopped.
+    fn trace_new_thread(&mut self, parent: Pid) -> Result<Pid, nix::Error> {
+        let tid = Pid::from_raw(ptrace::getevent(parent)? as i32);
+        self.wait_for_new_tracee(tid)?;
+        self.add_thread(tid);
+        println!("[New Thread {}]", tid);
+        // Debug registers aren't inherited, so copy the watchpoints over
+        for index in (0..NUM_DEBUG_REGISTERS).chain([DR_CONTROL]) {
+            write_debug_register(tid, index, read_debug_register(parent, index)?)?;
+        }
+        Ok(tid)
+    }
+
+    /// Waits for the initial SIGSTOP of a newly traced thread or process.
+    fn wait_for_new_tracee(&mut self, pid: Pid) -> Result<(), nix::Error> {
+        if !self.early_stops.remove(&pid) {
+            waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        }
+        Ok(())
+    }
+
+    fn add_thread(&mut self, tid: Pid) {
+        self.threads.push(Thread {
+            number: self.next_thread_number,
+            tid,
+        });
+        self.next_thread_number += 1;
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        self.threads.retain(|thread| thread.tid != tid);
+        self.pending_stops.remove(&tid);
+        self.pending_signals.remove(&tid);
+        self.deferred.remove(&tid);
+    }
+
+    fn has_thread(&self, tid: Pid) -> bool {
+        self.threads.iter().any(|thread| thread.tid == tid)
+    }
+
+    fn thread_ids(&self) -> Vec<Pid> {
+        self.threads.iter().map(|thread| thread.tid).collect()
+    }
+
+    /// Returns the threads of the inferior, in the order they were created.
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// Returns the selected thread.
+    pub fn current_thread(&self) -> &Thread {
+        self.threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .unwrap_or(&self.threads[0])
+    }
+
+    /// Selects thread `number`. Returns false if there is no such thread.
+    pub fn select_thread(&mut self, number: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.number == number) {
+            Some(thread) => {
+                self.current = thread.tid;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the id of the selected thread, which is what register and memory accesses use.
+    fn tid(&self) -> Pid {
+        self.current
+    }
+
+    /// Handles a fork (or vfork) event of thread `tid`: the process we don't follow has its
+    /// breakpoints and watchpoints removed and is detached.
+    fn follow_fork(&mut self, tid: Pid, vfork: bool, breakpoints: &mut Breakpoints) -> Result<(), nix::Error> {
+        let kind = if vfork { "vfork" } else { "fork" };
+        let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+        // The new process is traced from the start and begins with a SIGSTOP
+        self.wait_for_new_tracee(child)?;
+        match self.follow_fork_mode {
+            FollowForkMode::Parent => {
+                // After a vfork the child shares our memory, so this takes the breakpoints out
+                // of the parent as well until PTRACE_EVENT_VFORK_DONE.
+                Self::remove_breakpoints(child, breakpoints.saved_bytes())?;
+                ptrace::detach(child, None)?;
+                println!("[Detaching after {} from child process {}]", kind, child);
+            }
+            FollowForkMode::Child => {
+                println!("[Attaching after process {} {} to child process {}]", self.pid(), kind, child);
+                Self::remove_breakpoints(tid, breakpoints.saved_bytes())?;
+                self.set_debug_register(DR_CONTROL, 0)?;
+                self.release()?;
+                println!("[Detaching after {} from parent process {}]", kind, self.pid());
+                // A vfork child still shares the parent's memory, so it runs without breakpoints
+                // until it execs.
+                let attached = self.attached;
+                let parent = mem::replace(self, Inferior::with_main_thread(child, None, attached));
+                // The settings are the user's, not the parent process's
+                self.follow_fork_mode = parent.follow_fork_mode;
+                self.signals = parent.signals;
+                self.debug_file_directories = parent.debug_file_directories;
+                // The child has a copy of the parent's address space, libraries and all
+                self.libraries = parent.libraries;
+                // Debug registers aren't inherited across fork
+                breakpoints.install_watchpoints(self)?;
+            }
+        }
+        Ok(())
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+        self.status_from(waitpid(self.pid(), options)?)
+    }
+
+    fn status_from(&self, status: WaitStatus) -> Result<Status, nix::Error> {
+        Ok(match status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
+            WaitStatus::Stopped(tid, signal) | WaitStatus::PtraceEvent(tid, signal, _) => {
+                let regs = ptrace::getregs(tid)?;
                 Status::Stopped(signal, regs.rip as usize)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            // We don't ask for syscall stops or WCONTINUED/WNOHANG, so nothing else should come up
+            _ => return Err(nix::Error::EINVAL),
         })
     }
 
-    pub fn kill(&mut self) {
-        self.child.kill().unwrap();
-        self.wait(None).unwrap();
+    /// Kills the inferior and reaps it. Fails with ESRCH if the process is already gone.
+    pub fn kill(&mut self) -> Result<(), nix::Error> {
+        // A core dump's process is already dead
+        if self.is_core() {
+            return Ok(());
+        }
+        match &mut self.child {
+            Some(child) => child
+                .kill()
+                .map_err(|err| err.raw_os_error().map_or(nix::Error::EIO, nix::Error::from_raw))?,
+            None => signal::kill(self.pid(), signal::Signal::SIGKILL)?,
+        }
+        // Reap the other threads, then the process itself
+        loop {
+            match waitpid(None, Some(WaitPidFlag::__WALL)) {
+                Ok(WaitStatus::Exited(pid, _)) | Ok(WaitStatus::Signaled(pid, _, _)) if pid == self.pid() => break,
+                Err(_) => break,
+                _ => {}
+            }
+        }
         println!("killing running inferior (pid {})", self.pid());
+        Ok(())
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
-        let line_ = debug_data.get_line_from_addr(regs.rip);
-        let func_ = debug_data.get_function_from_addr(regs.rip);
+    /// Unwinds the current thread's stack, innermost frame first, stopping at main.
+    pub fn backtrace(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        self.frames(debug_data, MAX_BACKTRACE_DEPTH)
+    }
 
-        loop {
-            let mut result = String::new();
-            result = match &func_ {
-                None => format!("Unkown func"),
-                Some(func) -> format!("{}", func)
-            };
+    /// Unwinds at most `count` frames of the current thread's stack, innermost first.
+    pub fn frames(&self, debug_data: &DwarfData, count: usize) -> Result<Vec<Frame>, nix::Error> {
+        let modules = Modules::from_mappings(&self.mappings());
+        let mut registers = FrameRegisters::from_regs(&self.get_registers()?);
+        let mut frames = Vec::new();
 
-            result = match &line_ {
-                None => format!("{} (source file not found)", result),
-                Some(line) -> format!("{} ({})", result, line)
-            };
+        while let Some(rip) = registers.rip().filter(|&rip| rip != 0) {
+            // A caller's rip is the return address, which may already belong to the next line
+            // or function; look up the call instruction instead
+            let pc = if frames.is_empty() { rip } else { rip - 1 };
+            let read = |addr: u64| self.read_word(addr as usize);
+            // Unwind even the last frame, since its CFA is the caller's rsp
+            let caller = modules.unwind(&registers, pc, read);
+            frames.push(Frame {
+                rip: rip as usize,
+                pc: pc as usize,
+                registers: registers.clone(),
+                cfa: caller.as_ref().and_then(|caller| caller.rsp()).map(|rsp| rsp as usize),
+            });
 
-            if Some(func) = func_ {
-                if func == "main" {
-                    break;
-                }
-            } else {
+            let func = debug_data.get_function_from_addr(pc as usize);
+            if func.as_deref() == Some("main") || frames.len() == count {
                 break;
             }
 
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
+            let caller = match caller {
+                Some(caller) => caller,
+                None => break,
+            };
+            // The stack grows down, so callers' frames must be at higher addresses
+            if caller.rsp() <= registers.rsp() {
+                break;
+            }
+            registers = caller;
+        }
+
+        Ok(frames)
+    }
+
+    /// Returns the files mapped into the inferior, from /proc/<pid>/maps or the core dump.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let maps = match &self.core {
+            Some(core) => return core.mappings.clone(),
+            None => fs::read_to_string(format!("/proc/{}/maps", self.pid())).unwrap_or_default(),
+        };
+        let mut mappings = Vec::new();
+        for line in maps.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 || !fields[5].starts_with('/') {
+                continue;
+            }
+            let (start, end) = match fields[0].split_once('-') {
+                Some((start, end)) => (start, end),
+                None => continue,
+            };
+            let (start, end) = match (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16)) {
+                (Ok(start), Ok(end)) => (start, end),
+                _ => continue,
+            };
+            mappings.push(Mapping {
+                path: fields[5].to_string(),
+                start,
+                end,
+                offset: u64::from_str_radix(fields[2], 16).unwrap_or(0),
+            });
+        }
+        mappings
+    }
+
+    /// Returns the auxiliary vector the kernel passed to the program, as (type, value) pairs.
+    pub fn auxv(&self) -> Vec<(u64, u64)> {
+        if let Some(core) = &self.core {
+            return core.auxv.clone();
+        }
+        let bytes = fs::read(format!("/proc/{}/auxv", self.pid())).unwrap_or_default();
+        bytes
+            .chunks_exact(2 * size_of::<u64>())
+            .map(|entry| {
+                let (key, value) = entry.split_at(size_of::<u64>());
+                (u64::from_le_bytes(key.try_into().unwrap()), u64::from_le_bytes(value.try_into().unwrap()))
+            })
+            .collect()
+    }
+
+    /// How far the program was loaded from where it was linked, given the entry point it was
+    /// linked with: position-independent executables are loaded at a random address.
+    pub fn load_bias(&self, entry: usize) -> usize {
+        match self.auxv().iter().find(|(key, _)| *key == solib::AT_ENTRY) {
+            Some((_, runtime_entry)) => (*runtime_entry as usize).wrapping_sub(entry),
+            None => 0,
         }
+    }
+
+    pub fn libraries(&self) -> &SharedLibraries {
+        &self.libraries
+    }
 
-        println!(result);
+    /// Catches up with the shared libraries ld.so has loaded: reads its list of them, moves our
+    /// breakpoint to wherever it reports the next change, and resolves breakpoints on functions
+    /// that only the libraries define.
+    pub fn load_libraries(&mut self, breakpoints: &mut Breakpoints) -> Result<(), nix::Error> {
+        if !self.libraries.is_located() {
+            self.libraries = SharedLibraries::locate(&self.auxv(), &self.mappings());
+        }
+        let mut libraries = std::mem::take(&mut self.libraries);
+        let events = libraries.events().to_vec();
+        libraries.update(&|addr, len| self.read_bytes(addr, len).ok(), &self.debug_file_directories);
+        if self.is_core() {
+            self.libraries = libraries;
+            return Ok(());
+        }
+        for addr in events.iter().filter(|addr| !libraries.is_event(**addr)) {
+            breakpoints.remove_temporary(*addr, Some(self))?;
+        }
+        for addr in libraries.events() {
+            breakpoints.insert_temporary(*addr, self)?;
+        }
+        let result = breakpoints.resolve(|spec| libraries.location_address(spec), self);
+        self.libraries = libraries;
+        result
+    }
+
+    /// Writes debug register DR`index` of every thread. Watchpoints apply to the whole process.
+    pub fn set_debug_register(&self, index: usize, value: u64) -> Result<(), nix::Error> {
+        self.check_live()?;
+        for thread in self.threads.iter() {
+            write_debug_register(thread.tid, index, value)?;
+        }
         Ok(())
     }
 
-    fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+    /// Reads debug register DR`index` of the current thread.
+    pub fn get_debug_register(&self, index: usize) -> Result<u64, nix::Error> {
+        self.check_live()?;
+        read_debug_register(self.tid(), index)
+    }
+
+    /// Reads and clears the current thread's DR6, returning the bits (one per slot) of the
+    /// watchpoints that fired.
+    fn take_watch_triggers(&self) -> Result<u8, nix::Error> {
+        let status = self.get_debug_register(DR_STATUS)?;
+        let triggered = (status & 0xf) as u8;
+        if triggered != 0 {
+            write_debug_register(self.tid(), DR_STATUS, 0)?;
+        }
+        Ok(triggered)
+    }
+
+    /// After a SIGTRAP, checks whether one of our watchpoints fired. Returns true if the
+    /// inferior should stay stopped for it.
+    fn check_watchpoints(&self, breakpoints: &mut Breakpoints) -> Result<bool, nix::Error> {
+        let triggered = self.take_watch_triggers()?;
+        Ok(triggered != 0 && breakpoints.watchpoints_should_stop(triggered, self))
+    }
+
+    /// Returns the general-purpose registers of the current thread.
+    pub fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        self.get_thread_registers(self.tid())
+    }
+
+    /// Overwrites the general-purpose registers of the current thread.
+    pub fn set_registers(&self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        self.check_live()?;
+        ptrace::setregs(self.tid(), regs)
+    }
+
+    /// Returns the x87 and SSE registers of the current thread (PTRACE_GETFPREGS).
+    pub fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        if self.is_core() {
+            return self.core_thread(self.tid())?.fp_regs.ok_or(nix::Error::ENODATA);
+        }
+        let mut fpregs = MaybeUninit::<libc::user_fpregs_struct>::uninit();
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.tid().as_raw(),
+                ptr::null_mut::<libc::c_void>(),
+                fpregs.as_mut_ptr(),
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(unsafe { fpregs.assume_init() })
+    }
+
+    /// Overwrites the x87 and SSE registers of the current thread (PTRACE_SETFPREGS).
+    pub fn set_fp_registers(&self, fpregs: &libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        self.check_live()?;
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.tid().as_raw(),
+                ptr::null_mut::<libc::c_void>(),
+                fpregs as *const libc::user_fpregs_struct,
+            )
+        };
+        nix::errno::Errno::result(res).map(drop)
+    }
+
+    /// Returns the general-purpose registers of thread `tid`.
+    pub fn get_thread_registers(&self, tid: Pid) -> Result<libc::user_regs_struct, nix::Error> {
+        if self.is_core() {
+            return Ok(self.core_thread(tid)?.regs);
+        }
+        ptrace::getregs(tid)
+    }
+
+    /// Reads `len` bytes of inferior memory starting at `addr`, in bulk through /proc/<pid>/mem
+    /// if possible, or a word at a time with PTRACE_PEEKDATA otherwise.
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        if let Some(core) = &self.core {
+            return core.read(addr, len).ok_or(nix::Error::EIO);
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        if let Ok(bytes) = self.read_mem_file(addr, len) {
+            return Ok(bytes);
+        }
+        let end = addr.checked_add(len).ok_or(nix::Error::EFAULT)?;
+        let mut bytes = Vec::with_capacity(len);
+        for word_addr in (align_addr_to_word(addr)..end).step_by(size_of::<usize>()) {
+            let word = ptrace::read(self.tid(), word_addr as ptrace::AddressType)? as u64;
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let start = addr - align_addr_to_word(addr);
+        Ok(bytes[start..start + len].to_vec())
+    }
+
+    /// Reads the word at `addr`, for unwinding.
+    fn read_word(&self, addr: usize) -> Option<u64> {
+        match &self.core {
+            Some(core) => core.read(addr, size_of::<u64>()).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())),
+            None => ptrace::read(self.tid(), addr as ptrace::AddressType).ok().map(|word| word as u64),
+        }
+    }
+
+    fn read_mem_file(&self, addr: usize, len: usize) -> std::io::Result<Vec<u8>> {
+        let file = File::open(format!("/proc/{}/mem", self.pid()))?;
+        let mut bytes = vec![0; len];
+        file.read_exact_at(&mut bytes, addr as u64)?;
+        Ok(bytes)
+    }
+
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
+        self.check_live()?;
+        write_byte(self.tid(), addr, val)
+    }
+}
+
+/// Asks to be notified of new threads, fork, vfork and exec so that nothing escapes (or trips
+/// over breakpoints it inherited). Threads and processes created by `tid` inherit the options.
+fn set_trace_options(tid: Pid) -> Result<(), nix::Error> {
+    ptrace::setoptions(
+        tid,
+        ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORKDONE
+            | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+}
+
+/// Sends `signal` to a single thread of process `pid`.
+fn tgkill(pid: Pid, tid: Pid, signal: signal::Signal) -> Result<(), nix::Error> {
+    let res = unsafe { libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), signal as libc::c_int) };
+    nix::errno::Errno::result(res).map(drop)
+}
+
+/// Writes debug register DR`index` of thread `tid` through PTRACE_POKEUSER.
+fn write_debug_register(tid: Pid, index: usize, value: u64) -> Result<(), nix::Error> {
+    let offset = offset_of!(libc::user, u_debugreg) + index * size_of::<u64>();
+    ptrace::write_user(tid, offset as ptrace::AddressType, value as i64)
+}
+
+fn read_debug_register(tid: Pid, index: usize) -> Result<u64, nix::Error> {
+    let offset = offset_of!(libc::user, u_debugreg) + index * size_of::<u64>();
+    Ok(ptrace::read_user(tid, offset as ptrace::AddressType)? as u64)
+}
+
+/// Writes `val` at `addr` in the memory of process `pid`, returning the byte it replaced.
+fn write_byte(pid: Pid, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    let aligned_addr = align_addr_to_word(addr);
+    let byte_offset = addr - aligned_addr;
+    let word = ptrace::read(pid, aligned_addr as ptrace::AddressType)? as u64;
+    let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+    let masked_word = word & !(0xff << (8 * byte_offset));
+    let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
+    ptrace::write(
+        pid,
+        aligned_addr as ptrace::AddressType,
+        updated_word as i64,
+    )?;
+    Ok(orig_byte as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_fork_child_keeps_settings() {
+        // The shell forks to run the subshell, which exits without exec'ing anything
+        let args = vec!["-c".to_string(), "(exit 3); exit 0".to_string()];
+        let mut inferior = Inferior::new("/bin/sh", &args).expect("could not start /bin/sh");
+        let parent = inferior.pid();
+        inferior.follow_fork_mode = FollowForkMode::Child;
+        inferior.signals.apply(signal::Signal::SIGUSR1, "nostop").unwrap();
+        inferior.signals.apply(signal::Signal::SIGUSR1, "nopass").unwrap();
+        inferior.debug_file_directories = vec!["/tmp/debug".to_string()];
+        let debug_data = DwarfData::from_file("/bin/sh", &[]).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        inferior.load_libraries(&mut breakpoints).unwrap();
+        let status = inferior.cont(&debug_data, &mut breakpoints).unwrap();
+        assert!(matches!(status, Status::Exited(3)));
+        assert_ne!(inferior.pid(), parent);
+        assert!(inferior.follow_fork_mode == FollowForkMode::Child);
+        let policy = inferior.signals.get(signal::Signal::SIGUSR1);
+        assert!(!policy.stop && !policy.pass);
+        assert_eq!(inferior.debug_file_directories, vec!["/tmp/debug".to_string()]);
+        assert!(inferior.libraries().is_located());
     }
 }
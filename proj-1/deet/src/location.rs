@@ -0,0 +1,447 @@
+//! Evaluating DWARF location descriptions, which say where a variable is at a given pc: in memory,
+//! in registers, split into pieces, computed from other values, or nowhere at all once the
+//! optimizer is done with it.
+
+use crate::dwarf_data::{DwarfData, Location, Variable};
+use crate::inferior::{Frame, Inferior};
+use gimli::{EvaluationResult, Piece};
+use nix::libc;
+
+type Reader<'a> = gimli::EndianSlice<'a, gimli::LittleEndian>;
+
+/// deet only debugs x86-64, where addresses are always 8 bytes.
+const ENCODING: gimli::Encoding = gimli::Encoding {
+    address_size: 8,
+    format: gimli::Format::Dwarf32,
+    version: 5,
+};
+
+/// DWARF numbers the SSE registers xmm0-xmm15 as 17-32.
+const XMM0: u16 = 17;
+const XMM15: u16 = 32;
+
+/// Frame bases and entry values are evaluated as nested expressions; give up past this depth.
+const MAX_DEPTH: usize = 4;
+
+/// Where a variable's value is.
+pub enum Place {
+    /// In the inferior's memory, so it has an address.
+    Memory(usize),
+    /// In registers, or computed by the expression: the bytes of the value.
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+/// What location expressions read from the inferior besides a frame's registers: its memory,
+/// and the SSE registers, which only the innermost frame has.
+pub trait Target {
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+    fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error>;
+}
+
+impl Target for Inferior {
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        Inferior::read_bytes(self, addr, len)
+    }
+
+    fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        Inferior::get_fp_registers(self)
+    }
+}
+
+/// What a location expression can refer to: the inferior's memory and the registers of the frame
+/// it describes. The caller's frame is needed for the values parameters had on entry.
+pub struct FrameContext<'a> {
+    pub debug_data: &'a DwarfData,
+    pub inferior: Option<&'a dyn Target>,
+    pub frame: Option<&'a Frame>,
+    pub caller: Option<&'a Frame>,
+}
+
+impl FrameContext<'_> {
+    /// Finds where `var` is in the frame.
+    pub fn locate(&self, var: &Variable) -> Result<Place, String> {
+        match var.location {
+            Location::Address(addr) => return Ok(Place::Memory(addr)),
+            Location::FramePointerOffset(offset) => {
+                return Ok(match self.frame_base(0)? {
+                    Some(base) => Place::Memory((base as isize + offset) as usize),
+                    None => Place::OptimizedOut,
+                });
+            }
+            Location::OptimizedOut => return Ok(Place::OptimizedOut),
+            Location::Expression(_) | Location::List(_) => {}
+        }
+        let pc = self.frame.map_or(0, |frame| frame.pc);
+        // Outside of all the ranges of a location list, the variable doesn't exist
+        let bytecode = match var.location.expression_at(pc) {
+            Some(bytecode) => bytecode,
+            None => return Ok(Place::OptimizedOut),
+        };
+        match self.evaluate(bytecode, 0)? {
+            Some(pieces) => self.assemble(&pieces, var.entity_type.size),
+            None => Ok(Place::OptimizedOut),
+        }
+    }
+
+    /// Runs a DWARF expression, supplying whatever it asks for. Returns `None` if it needs a
+    /// register or value that can't be recovered in this frame.
+    fn evaluate<'b>(&self, bytecode: &'b [u8], depth: usize) -> Result<Option<Vec<Piece<Reader<'b>>>>, String> {
+        if depth > MAX_DEPTH {
+            return Err("DWARF expression nests too deeply.".to_string());
+        }
+        let mut evaluation = gimli::Expression(Reader::new(bytecode, gimli::LittleEndian)).evaluation(ENCODING);
+        let mut result = evaluation.evaluate();
+        loop {
+            let requirement = result.map_err(|err| format!("Unhandled dwarf expression: {}", err))?;
+            result = match requirement {
+                EvaluationResult::Complete => return Ok(Some(evaluation.result())),
+                EvaluationResult::RequiresMemory { address, size, .. } => {
+                    let bytes = self.read_memory(address as usize, size as usize)?;
+                    evaluation.resume_with_memory(gimli::Value::Generic(to_u64(&bytes)))
+                }
+                EvaluationResult::RequiresRegister { register, .. } => match self.register(register.0) {
+                    Some(value) => evaluation.resume_with_register(gimli::Value::Generic(value)),
+                    None => return Ok(None),
+                },
+                EvaluationResult::RequiresFrameBase => match self.frame_base(depth + 1)? {
+                    Some(base) => evaluation.resume_with_frame_base(base as u64),
+                    None => return Ok(None),
+                },
+                EvaluationResult::RequiresCallFrameCfa => match self.frame.and_then(|frame| frame.cfa) {
+                    Some(cfa) => evaluation.resume_with_call_frame_cfa(cfa as u64),
+                    None => return Ok(None),
+                },
+                EvaluationResult::RequiresEntryValue(expression) => {
+                    match self.entry_value(expression.0.slice(), depth + 1)? {
+                        Some(value) => evaluation.resume_with_entry_value(gimli::Value::Generic(value)),
+                        None => return Ok(None),
+                    }
+                }
+                EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluation.resume_with_relocated_address(address.wrapping_add(self.debug_data.load_bias() as u64))
+                }
+                // Typed operations are evaluated as if they were untyped
+                EvaluationResult::RequiresBaseType(_) => evaluation.resume_with_base_type(gimli::ValueType::Generic),
+                EvaluationResult::RequiresTls(_) => return Err("Cannot access thread-local variables.".to_string()),
+                _ => return Err("Unhandled dwarf expression.".to_string()),
+            };
+        }
+    }
+
+    /// The frame base of the frame's function: what DW_OP_fbreg offsets are relative to. gcc
+    /// uses the CFA, clang uses rbp.
+    fn frame_base(&self, depth: usize) -> Result<Option<usize>, String> {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let bytecode = match self.debug_data.get_function_containing(frame.pc) {
+            Some(func) if !func.frame_base.is_empty() => &func.frame_base,
+            _ => return Ok(frame.cfa),
+        };
+        match self.evaluate(bytecode, depth)? {
+            Some(pieces) => Ok(self.scalar(&pieces)?.map(|base| base as usize)),
+            None => Ok(None),
+        }
+    }
+
+    /// The value a register had on entry to the frame's function, for DW_OP_entry_value. At the
+    /// function's first instruction, that's still in the register. Elsewhere, it's whatever the
+    /// caller passed, if its call site records how to compute that.
+    fn entry_value(&self, bytecode: &[u8], depth: usize) -> Result<Option<u64>, String> {
+        let mut pc = Reader::new(bytecode, gimli::LittleEndian);
+        let register = match gimli::Operation::parse(&mut pc, ENCODING) {
+            Ok(gimli::Operation::Register { register }) if pc.is_empty() => register.0,
+            _ => return Ok(None),
+        };
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let func = self.debug_data.get_function_containing(frame.pc);
+        if frame.pc == frame.rip && func.map(|func| func.address) == Some(frame.pc) {
+            return Ok(self.register(register));
+        }
+        let caller = match self.caller {
+            Some(caller) => caller,
+            None => return Ok(None),
+        };
+        let parameter = self
+            .debug_data
+            .get_function_containing(caller.pc)
+            .and_then(|func| func.call_sites.iter().find(|site| site.return_address == caller.rip))
+            .and_then(|site| site.parameters.iter().find(|(reg, _)| *reg == register));
+        let value = match parameter {
+            Some((_, value)) => value,
+            None => return Ok(None),
+        };
+        let caller_context = FrameContext {
+            debug_data: self.debug_data,
+            inferior: self.inferior,
+            frame: Some(caller),
+            caller: None,
+        };
+        match caller_context.evaluate(value, depth)? {
+            Some(pieces) => caller_context.scalar(&pieces),
+            None => Ok(None),
+        }
+    }
+
+    /// Interprets the result of an expression that computes a number, such as a frame base or
+    /// a call site's parameter value, rather than a location.
+    fn scalar(&self, pieces: &[Piece<Reader<'_>>]) -> Result<Option<u64>, String> {
+        match pieces.first().map(|piece| &piece.location) {
+            // What's left on the stack is taken to be an address
+            Some(gimli::Location::Address { address }) => Ok(Some(*address)),
+            Some(location) => Ok(self.piece_bytes(location, 8)?.map(|bytes| to_u64(&bytes))),
+            None => Ok(None),
+        }
+    }
+
+    /// Puts the pieces of a value together. A value that's entirely in memory keeps its address.
+    fn assemble(&self, pieces: &[Piece<Reader<'_>>], size: usize) -> Result<Place, String> {
+        if let [piece] = pieces
+            && piece.size_in_bits.is_none()
+            && let gimli::Location::Address { address } = piece.location
+        {
+            return Ok(Place::Memory(address as usize));
+        }
+        let mut bytes = Vec::with_capacity(size);
+        for piece in pieces {
+            let len = piece.size_in_bits.map_or(size, |bits| (bits as usize).div_ceil(8));
+            // Bit offsets count from the least significant end, so whole bytes can be dropped
+            let offset = piece.bit_offset.unwrap_or(0) as usize / 8;
+            match self.piece_bytes(&piece.location, offset + len)? {
+                Some(piece_bytes) => bytes.extend_from_slice(&piece_bytes[offset..]),
+                None => return Ok(Place::OptimizedOut),
+            }
+        }
+        bytes.resize(size, 0);
+        Ok(Place::Value(bytes))
+    }
+
+    /// The first `len` bytes of one piece of a value, or `None` if they're gone.
+    fn piece_bytes(&self, location: &gimli::Location<Reader<'_>>, len: usize) -> Result<Option<Vec<u8>>, String> {
+        let mut bytes = match location {
+            gimli::Location::Empty | gimli::Location::ImplicitPointer { .. } => return Ok(None),
+            gimli::Location::Address { address } => return self.read_memory(*address as usize, len).map(Some),
+            gimli::Location::Register { register } => match self.register_bytes(register.0) {
+                Some(bytes) => bytes,
+                None => return Ok(None),
+            },
+            gimli::Location::Value { value } => value_bytes(*value),
+            gimli::Location::Bytes { value } => value.slice().to_vec(),
+        };
+        bytes.resize(len, 0);
+        Ok(Some(bytes))
+    }
+
+    /// The contents of a register, by DWARF number. Unwinding only recovers the general purpose
+    /// registers of callers, and not even all of those.
+    fn register_bytes(&self, register: u16) -> Option<Vec<u8>> {
+        let frame = self.frame?;
+        if let Some(value) = frame.registers.get(register) {
+            return Some(value.to_le_bytes().to_vec());
+        }
+        // Only the innermost frame's SSE registers are known, since they're all caller-saved
+        if (XMM0..=XMM15).contains(&register) && frame.pc == frame.rip {
+            let fp_regs = self.inferior?.get_fp_registers().ok()?;
+            let start = (register - XMM0) as usize * 4;
+            let words = &fp_regs.xmm_space[start..start + 4];
+            return Some(words.iter().flat_map(|word| word.to_le_bytes()).collect());
+        }
+        None
+    }
+
+    fn register(&self, register: u16) -> Option<u64> {
+        self.register_bytes(register).map(|bytes| to_u64(&bytes))
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+        let cannot_access = || format!("Cannot access memory at address {:#x}", addr);
+        self.inferior
+            .ok_or_else(cannot_access)?
+            .read_bytes(addr, len)
+            .map_err(|_| cannot_access())
+    }
+}
+
+/// Reads up to 8 little-endian bytes as a number.
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+/// The bytes of a value on the DWARF stack, as they'd be stored in memory.
+fn value_bytes(value: gimli::Value) -> Vec<u8> {
+    match value {
+        gimli::Value::Generic(value) | gimli::Value::U64(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I8(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U8(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I16(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U16(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I64(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::F32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::F64(value) => value.to_le_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::{LocationListEntry, Type};
+    use crate::unwind::FrameRegisters;
+
+    const RAX: u64 = 0x1122_3344_5566_7788;
+    const RDX: u64 = 0xaabb_ccdd_eeff_0011;
+    const RBX: u64 = 0x0000_0000_0000_beef;
+    const RBP: u64 = 0x7000;
+    const RSP: u64 = 0x6ff0;
+    const CFA: usize = 0x7010;
+    /// What the fake stack holds at rsp + 8.
+    const SAVED_POINTER: u64 = 0x7020;
+
+    /// A stack from rsp to the CFA, with byte `i` holding `i`, apart from a pointer at rsp + 8.
+    /// xmm0 holds 1.5.
+    struct FakeTarget {
+        stack: Vec<u8>,
+    }
+
+    impl FakeTarget {
+        fn new() -> FakeTarget {
+            let mut stack: Vec<u8> = (0..(CFA - RSP as usize) as u8).collect();
+            stack[8..16].copy_from_slice(&SAVED_POINTER.to_le_bytes());
+            FakeTarget { stack }
+        }
+    }
+
+    impl Target for FakeTarget {
+        fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+            let offset = addr.checked_sub(RSP as usize).ok_or(nix::Error::EIO)?;
+            self.stack.get(offset..offset + len).map(<[u8]>::to_vec).ok_or(nix::Error::EIO)
+        }
+
+        fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+            let mut fp_regs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+            let bits = 1.5f64.to_bits();
+            fp_regs.xmm_space[0] = bits as u32;
+            fp_regs.xmm_space[1] = (bits >> 32) as u32;
+            Ok(fp_regs)
+        }
+    }
+
+    fn frame(pc: usize) -> Frame {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = RAX;
+        regs.rdx = RDX;
+        regs.rbx = RBX;
+        regs.rbp = RBP;
+        regs.rsp = RSP;
+        regs.rip = pc as u64;
+        Frame {
+            rip: pc,
+            pc,
+            registers: FrameRegisters::from_regs(&regs),
+            cfa: Some(CFA),
+        }
+    }
+
+    /// Locates a variable of `size` bytes at `location` while the innermost frame is at `pc`,
+    /// and describes where it is.
+    fn locate_at(location: Location, size: usize, pc: usize) -> Result<String, String> {
+        // Without debug info for the frame's function, its frame base is the CFA, as with gcc
+        let debug_data = DwarfData::empty();
+        let target = FakeTarget::new();
+        let frame = frame(pc);
+        let context = FrameContext {
+            debug_data: &debug_data,
+            inferior: Some(&target),
+            frame: Some(&frame),
+            caller: None,
+        };
+        let var = Variable {
+            name: "var".to_string(),
+            entity_type: Type::new("var_type".to_string(), size),
+            location,
+            line_number: 0,
+            is_parameter: false,
+        };
+        Ok(match context.locate(&var)? {
+            Place::Memory(addr) => format!("memory {:#x}", addr),
+            Place::Value(bytes) => format!("value {:02x?}", bytes),
+            Place::OptimizedOut => "optimized out".to_string(),
+        })
+    }
+
+    fn locate(expression: &[u8], size: usize) -> Result<String, String> {
+        locate_at(Location::Expression(expression.to_vec()), size, 0x401000)
+    }
+
+    #[test]
+    fn frame_base_offsets() {
+        // DW_OP_fbreg -20
+        assert_eq!(locate(&[0x91, 0x6c], 4).unwrap(), "memory 0x6ffc");
+        assert_eq!(locate_at(Location::FramePointerOffset(-20), 4, 0x401000).unwrap(), "memory 0x6ffc");
+        // DW_OP_call_frame_cfa
+        assert_eq!(locate(&[0x9c], 8).unwrap(), "memory 0x7010");
+    }
+
+    #[test]
+    fn register_offsets() {
+        // DW_OP_breg6 (rbp) 16
+        assert_eq!(locate(&[0x76, 0x10], 4).unwrap(), "memory 0x7010");
+        // DW_OP_breg7 (rsp) 8; DW_OP_deref
+        assert_eq!(locate(&[0x77, 0x08, 0x06], 4).unwrap(), "memory 0x7020");
+        // DW_OP_breg7 (rsp) 64; DW_OP_deref, past the end of the stack
+        assert_eq!(
+            locate(&[0x77, 0xc0, 0x00, 0x06], 4),
+            Err("Cannot access memory at address 0x7030".to_string())
+        );
+    }
+
+    #[test]
+    fn registers_and_computed_values() {
+        // DW_OP_reg0 (rax)
+        assert_eq!(locate(&[0x50], 4).unwrap(), "value [88, 77, 66, 55]");
+        // DW_OP_regx 17 (xmm0)
+        assert_eq!(locate(&[0x90, 0x11], 8).unwrap(), format!("value {:02x?}", 1.5f64.to_le_bytes()));
+        // DW_OP_lit5; DW_OP_stack_value
+        assert_eq!(locate(&[0x35, 0x9f], 4).unwrap(), "value [05, 00, 00, 00]");
+    }
+
+    #[test]
+    fn pieces() {
+        // DW_OP_reg0 (rax); DW_OP_piece 4; DW_OP_reg1 (rdx); DW_OP_piece 4
+        assert_eq!(
+            locate(&[0x50, 0x93, 0x04, 0x51, 0x93, 0x04], 8).unwrap(),
+            "value [88, 77, 66, 55, 11, 00, ff, ee]"
+        );
+        // DW_OP_breg7 (rsp) 2; DW_OP_piece 2; DW_OP_reg3 (rbx); DW_OP_piece 2
+        assert_eq!(
+            locate(&[0x77, 0x02, 0x93, 0x02, 0x53, 0x93, 0x02], 4).unwrap(),
+            "value [02, 03, ef, be]"
+        );
+        // A piece without a location, e.g. half of a struct that was optimized away
+        assert_eq!(locate(&[0x93, 0x04, 0x50, 0x93, 0x04], 8).unwrap(), "optimized out");
+    }
+
+    #[test]
+    fn location_lists() {
+        let list = || {
+            Location::List(vec![
+                // DW_OP_reg0 (rax)
+                LocationListEntry { begin: 0x401000, end: 0x401010, expression: vec![0x50] },
+                // DW_OP_fbreg -16
+                LocationListEntry { begin: 0x401010, end: 0x401020, expression: vec![0x91, 0x70] },
+            ])
+        };
+        assert_eq!(locate_at(list(), 2, 0x401008).unwrap(), "value [88, 77]");
+        assert_eq!(locate_at(list(), 2, 0x401010).unwrap(), "memory 0x7000");
+        assert_eq!(locate_at(list(), 2, 0x401020).unwrap(), "optimized out");
+    }
+}
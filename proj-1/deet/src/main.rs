@@ -1,24 +1,69 @@
+mod breakpoint;
+mod condition;
+mod coredump;
+mod debug_file;
 mod debugger;
 mod debugger_command;
+mod disassembler;
 mod inferior;
 mod dwarf_data;
+mod examine;
+mod expression;
 mod gimli_wrapper;
+mod location;
+mod registers;
+mod signals;
+mod solib;
+mod source;
+mod split_dwarf;
+mod unwind;
+mod watchpoint;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
+use std::fs;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    let (target, pid, core) = match args.len() {
+        2 => (args[1].clone(), None, None),
+        4 if args[2] == "--core" => (args[1].clone(), None, Some(args[3].clone())),
+        3 if args[1] == "--pid" => {
+            let pid: i32 = match args[2].parse() {
+                Ok(pid) => pid,
+                Err(_) => {
+                    println!("Invalid process id {}", args[2]);
+                    std::process::exit(1);
+                }
+            };
+            // Load symbols for whatever the process is running
+            match fs::read_link(format!("/proc/{}/exe", pid)) {
+                Ok(path) => (path.to_string_lossy().into_owned(), Some(pid), None),
+                Err(err) => {
+                    println!("Could not find the executable of process {}: {}", pid, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => {
+            println!("Usage: {} <target program>", args[0]);
+            println!("       {} --pid <process id>", args[0]);
+            println!("       {} <target program> --core <core file>", args[0]);
+            std::process::exit(1);
+        }
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if let Some(pid) = pid {
+        debugger.attach(pid);
+    }
+    if let Some(core) = core {
+        debugger.load_core(&core);
+    }
+    debugger.run();
 }
@@ -0,0 +1,312 @@
+//! Names and formatting of the x86-64 registers, as shown by `info registers` and assigned by
+//! `set $reg = value`. General-purpose registers come from PTRACE_GETREGS, the x87 and SSE ones
+//! from PTRACE_GETFPREGS.
+
+use crate::dwarf_data::DwarfData;
+use libc::{user_fpregs_struct, user_regs_struct};
+
+/// General-purpose and segment registers, in the order `info registers` lists them.
+pub const GENERAL_REGISTERS: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+/// x87 and SSE control registers (`fctrl` is the x87 control word, `fstat` the status word).
+const FP_CONTROL_REGISTERS: [&str; 5] = ["fctrl", "fstat", "ftag", "fop", "mxcsr"];
+
+const NUM_X87_REGISTERS: usize = 8;
+const NUM_XMM_REGISTERS: usize = 16;
+
+const EFLAGS: [(u32, &str); 9] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+const MXCSR_FLAGS: [(u32, &str); 14] = [
+    (0, "IE"),
+    (1, "DE"),
+    (2, "ZE"),
+    (3, "OE"),
+    (4, "UE"),
+    (5, "PE"),
+    (6, "DAZ"),
+    (7, "IM"),
+    (8, "DM"),
+    (9, "ZM"),
+    (10, "OM"),
+    (11, "UM"),
+    (12, "PM"),
+    (15, "FZ"),
+];
+
+fn general_register<'r>(regs: &'r mut user_regs_struct, name: &str) -> Option<&'r mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        "orig_rax" => &mut regs.orig_rax,
+        _ => return None,
+    })
+}
+
+/// Returns the value of general-purpose register `name`, e.g. for `x $rsp`.
+pub fn general_register_value(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    general_register(&mut regs, name).map(|value| *value)
+}
+
+/// Returns the index of `name` if it is `prefix` followed by a number below `count`, e.g.
+/// `st3` or `xmm12`.
+fn numbered_register(name: &str, prefix: &str, count: usize) -> Option<usize> {
+    let index: usize = name.strip_prefix(prefix)?.parse().ok()?;
+    if index < count { Some(index) } else { None }
+}
+
+/// Lists the names of the set bits of `value` that appear in `flags`, gdb style.
+fn format_flags(value: u64, flags: &[(u32, &str)]) -> String {
+    let names: Vec<&str> = flags
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", names.join(" "))
+}
+
+/// Converts an x87 80-bit extended precision value (little endian) to the nearest f64.
+fn x87_to_f64(raw: &[u8; 10]) -> f64 {
+    let mantissa = u64::from_le_bytes(raw[0..8].try_into().unwrap());
+    let sign_exponent = u16::from_le_bytes([raw[8], raw[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    if exponent == 0x7fff {
+        return if mantissa << 1 == 0 { sign * f64::INFINITY } else { f64::NAN };
+    }
+    if exponent == 0 && mantissa == 0 {
+        return sign * 0.0;
+    }
+    // The mantissa has an explicit integer bit, so it reads as a fixed-point number 1.63
+    sign * (mantissa as f64) * 2f64.powi(exponent - 16383 - 63)
+}
+
+/// Converts an f64 to x87 80-bit extended precision (little endian).
+fn f64_to_x87(value: f64) -> [u8; 10] {
+    let bits = value.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        0 => {
+            // Subnormal: normalize so that the integer bit is set
+            let shift = fraction.leading_zeros() as i32;
+            (1 - 1023 + 16383 - (shift - 11), fraction << shift)
+        }
+        0x7ff => (0x7fff, (1 << 63) | (fraction << 11)),
+        _ => (exponent - 1023 + 16383, (1 << 63) | (fraction << 11)),
+    };
+    let mut raw = [0u8; 10];
+    raw[0..8].copy_from_slice(&mantissa.to_le_bytes());
+    raw[8..10].copy_from_slice(&(sign | exponent as u16).to_le_bytes());
+    raw
+}
+
+fn x87_register(fpregs: &user_fpregs_struct, index: usize) -> [u8; 10] {
+    let mut raw = [0u8; 10];
+    for (i, word) in fpregs.st_space[4 * index..4 * index + 3].iter().enumerate() {
+        let bytes = word.to_le_bytes();
+        let len = (10 - 4 * i).min(4);
+        raw[4 * i..4 * i + len].copy_from_slice(&bytes[..len]);
+    }
+    raw
+}
+
+fn set_x87_register(fpregs: &mut user_fpregs_struct, index: usize, raw: [u8; 10]) {
+    let mut bytes = [0u8; 16];
+    bytes[..10].copy_from_slice(&raw);
+    for i in 0..4 {
+        fpregs.st_space[4 * index + i] = u32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+    }
+}
+
+fn xmm_register(fpregs: &user_fpregs_struct, index: usize) -> u128 {
+    fpregs.xmm_space[4 * index..4 * index + 4]
+        .iter()
+        .rev()
+        .fold(0, |value, word| (value << 32) | *word as u128)
+}
+
+fn set_xmm_register(fpregs: &mut user_fpregs_struct, index: usize, value: u128) {
+    for i in 0..4 {
+        fpregs.xmm_space[4 * index + i] = (value >> (32 * i)) as u32;
+    }
+}
+
+fn print_row(name: &str, raw: &str, natural: &str) {
+    println!("{:<15}{:<19}{}", name, raw, natural);
+}
+
+fn print_general_register(name: &str, value: u64, debug_data: &DwarfData) {
+    let raw = format!("{:#x}", value);
+    let natural = match name {
+        "rip" | "pc" => match debug_data.describe_address(value as usize) {
+            Some(symbol) => format!("{:#x} <{}>", value, symbol),
+            None => format!("{:#x}", value),
+        },
+        "rbp" | "rsp" | "fs_base" | "gs_base" => format!("{:#x}", value),
+        "eflags" => format_flags(value, &EFLAGS),
+        _ => format!("{}", value as i64),
+    };
+    print_row(name, &raw, &natural);
+}
+
+fn print_x87_register(index: usize, fpregs: &user_fpregs_struct) {
+    let raw = x87_register(fpregs, index);
+    let hex: String = raw.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+    print_row(&format!("st{}", index), &format!("{}", x87_to_f64(&raw)), &format!("(raw 0x{})", hex));
+}
+
+fn print_xmm_register(index: usize, fpregs: &user_fpregs_struct) {
+    let value = xmm_register(fpregs, index);
+    let floats: Vec<String> = (0..4).map(|i| format!("{}", f32::from_bits((value >> (32 * i)) as u32))).collect();
+    let doubles: Vec<String> = (0..2).map(|i| format!("{}", f64::from_bits((value >> (64 * i)) as u64))).collect();
+    println!(
+        "{:<15}{{v4_float = {{{}}}, v2_double = {{{}}}, uint128 = {:#x}}}",
+        format!("xmm{}", index),
+        floats.join(", "),
+        doubles.join(", "),
+        value
+    );
+}
+
+fn print_fp_control_register(name: &str, fpregs: &user_fpregs_struct) {
+    let value = match name {
+        "fctrl" => fpregs.cwd as u64,
+        "fstat" => fpregs.swd as u64,
+        "ftag" => fpregs.ftw as u64,
+        "fop" => fpregs.fop as u64,
+        _ => fpregs.mxcsr as u64,
+    };
+    let natural = match name {
+        "mxcsr" => format_flags(value, &MXCSR_FLAGS),
+        _ => format!("{}", value),
+    };
+    print_row(name, &format!("{:#x}", value), &natural);
+}
+
+/// Prints the general-purpose and segment registers.
+pub fn print_general_registers(regs: &user_regs_struct, debug_data: &DwarfData) {
+    let mut regs = *regs;
+    for name in GENERAL_REGISTERS.iter().chain(["fs_base", "gs_base"].iter()) {
+        let value = *general_register(&mut regs, name).unwrap();
+        print_general_register(name, value, debug_data);
+    }
+}
+
+/// Prints the x87 and SSE registers.
+pub fn print_fp_registers(fpregs: &user_fpregs_struct) {
+    for index in 0..NUM_X87_REGISTERS {
+        print_x87_register(index, fpregs);
+    }
+    for name in FP_CONTROL_REGISTERS.iter() {
+        print_fp_control_register(name, fpregs);
+    }
+    for index in 0..NUM_XMM_REGISTERS {
+        print_xmm_register(index, fpregs);
+    }
+}
+
+/// Prints a single register. Returns false if there is no register called `name`.
+pub fn print_register(
+    name: &str,
+    regs: &user_regs_struct,
+    fpregs: &user_fpregs_struct,
+    debug_data: &DwarfData,
+) -> bool {
+    let mut regs = *regs;
+    if let Some(value) = general_register(&mut regs, name) {
+        print_general_register(name, *value, debug_data);
+    } else if let Some(index) = numbered_register(name, "st", NUM_X87_REGISTERS) {
+        print_x87_register(index, fpregs);
+    } else if let Some(index) = numbered_register(name, "xmm", NUM_XMM_REGISTERS) {
+        print_xmm_register(index, fpregs);
+    } else if FP_CONTROL_REGISTERS.contains(&name) {
+        print_fp_control_register(name, fpregs);
+    } else {
+        return false;
+    }
+    true
+}
+
+fn parse_integer(value: &str) -> Result<u128, String> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let parsed = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => digits.parse::<u128>(),
+    };
+    let parsed = parsed.map_err(|_| format!("Invalid number \"{}\".", value))?;
+    Ok(if negative { parsed.wrapping_neg() } else { parsed })
+}
+
+/// Assigns `value` to register `name`, in whichever of `regs` and `fpregs` holds it. x87
+/// registers take floating point values; all others take integers.
+pub fn set_register(
+    name: &str,
+    value: &str,
+    regs: &mut user_regs_struct,
+    fpregs: &mut user_fpregs_struct,
+) -> Result<(), String> {
+    if let Some(index) = numbered_register(name, "st", NUM_X87_REGISTERS) {
+        let value: f64 = value.parse().map_err(|_| format!("Invalid number \"{}\".", value))?;
+        set_x87_register(fpregs, index, f64_to_x87(value));
+        return Ok(());
+    }
+    let int = parse_integer(value)?;
+    if let Some(reg) = general_register(regs, name) {
+        *reg = int as u64;
+    } else if let Some(index) = numbered_register(name, "xmm", NUM_XMM_REGISTERS) {
+        set_xmm_register(fpregs, index, int);
+    } else {
+        match name {
+            "fctrl" => fpregs.cwd = int as u16,
+            "fstat" => fpregs.swd = int as u16,
+            "ftag" => fpregs.ftw = int as u16,
+            "fop" => fpregs.fop = int as u16,
+            "mxcsr" => fpregs.mxcsr = int as u32,
+            _ => return Err(format!("Invalid register `{}'", name)),
+        }
+    }
+    Ok(())
+}
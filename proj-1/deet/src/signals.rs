@@ -0,0 +1,146 @@
+//! What to do when the inferior receives a signal: whether to stop, whether to say so, and
+//! whether to deliver it to the program when it resumes. `handle` changes this per signal, and
+//! the defaults are gdb's.
+
+use nix::sys::signal::Signal;
+
+#[derive(Clone, Copy)]
+pub struct SignalPolicy {
+    pub stop: bool,
+    pub print: bool,
+    pub pass: bool,
+}
+
+/// The policy for every signal, in signal number order.
+#[derive(Clone)]
+pub struct SignalTable {
+    policies: Vec<(Signal, SignalPolicy)>,
+}
+
+impl SignalTable {
+    pub fn new() -> SignalTable {
+        let policies = Signal::iterator()
+            .map(|signal| {
+                let policy = match signal {
+                    // These are the debugger's own business
+                    Signal::SIGINT | Signal::SIGTRAP => SignalPolicy { stop: true, print: true, pass: false },
+                    // Programs get these all the time as part of their normal operation
+                    Signal::SIGALRM
+                    | Signal::SIGURG
+                    | Signal::SIGIO
+                    | Signal::SIGVTALRM
+                    | Signal::SIGPROF
+                    | Signal::SIGCHLD
+                    | Signal::SIGWINCH => SignalPolicy { stop: false, print: false, pass: true },
+                    _ => SignalPolicy { stop: true, print: true, pass: true },
+                };
+                (signal, policy)
+            })
+            .collect();
+        SignalTable { policies }
+    }
+
+    pub fn get(&self, signal: Signal) -> SignalPolicy {
+        self.policies
+            .iter()
+            .find(|(sig, _)| *sig == signal)
+            .map(|(_, policy)| *policy)
+            .unwrap_or(SignalPolicy { stop: true, print: true, pass: true })
+    }
+
+    /// Applies a `handle` keyword to `signal`. Like in gdb, stopping implies printing, and not
+    /// printing implies not stopping.
+    pub fn apply(&mut self, signal: Signal, action: &str) -> Result<(), String> {
+        let policy = match self.policies.iter_mut().find(|(sig, _)| *sig == signal) {
+            Some((_, policy)) => policy,
+            None => return Err(format!("Unknown signal {}.", signal)),
+        };
+        match action {
+            "stop" => {
+                policy.stop = true;
+                policy.print = true;
+            }
+            "nostop" => policy.stop = false,
+            "print" => policy.print = true,
+            "noprint" => {
+                policy.print = false;
+                policy.stop = false;
+            }
+            "pass" | "noignore" => policy.pass = true,
+            "nopass" | "ignore" => policy.pass = false,
+            _ => return Err(format!("Unrecognized or ambiguous flag word: \"{}\".", action)),
+        }
+        Ok(())
+    }
+
+    /// Prints the policies of `signals`, or of all signals if it's empty.
+    pub fn print(&self, signals: &[Signal]) {
+        println!("Signal        Stop\tPrint\tPass to program\tDescription");
+        println!();
+        let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+        for (signal, policy) in self.policies.iter() {
+            if !signals.is_empty() && !signals.contains(signal) {
+                continue;
+            }
+            println!(
+                "{:<14}{}\t{}\t{}\t\t{}",
+                signal.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                description(*signal)
+            );
+        }
+        if signals.is_empty() {
+            println!("\nUse the \"handle\" command to change these tables.");
+        }
+    }
+}
+
+/// Parses a signal name like `SIGUSR1`, or a number from 1 to 15 (the numbers of the others
+/// differ between systems).
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    match name.parse::<i32>() {
+        Ok(number) if (1..=15).contains(&number) => Signal::try_from(number).ok(),
+        Ok(_) => None,
+        Err(_) => name.parse().ok(),
+    }
+}
+
+/// How gdb describes each signal.
+pub fn description(signal: Signal) -> &'static str {
+    match signal {
+        Signal::SIGHUP => "Hangup",
+        Signal::SIGINT => "Interrupt",
+        Signal::SIGQUIT => "Quit",
+        Signal::SIGILL => "Illegal instruction",
+        Signal::SIGTRAP => "Trace/breakpoint trap",
+        Signal::SIGABRT => "Aborted",
+        Signal::SIGBUS => "Bus error",
+        Signal::SIGFPE => "Arithmetic exception",
+        Signal::SIGKILL => "Killed",
+        Signal::SIGUSR1 => "User defined signal 1",
+        Signal::SIGSEGV => "Segmentation fault",
+        Signal::SIGUSR2 => "User defined signal 2",
+        Signal::SIGPIPE => "Broken pipe",
+        Signal::SIGALRM => "Alarm clock",
+        Signal::SIGTERM => "Terminated",
+        Signal::SIGSTKFLT => "Stack fault",
+        Signal::SIGCHLD => "Child status changed",
+        Signal::SIGCONT => "Continued",
+        Signal::SIGSTOP => "Stopped (signal)",
+        Signal::SIGTSTP => "Stopped (user)",
+        Signal::SIGTTIN => "Stopped (tty input)",
+        Signal::SIGTTOU => "Stopped (tty output)",
+        Signal::SIGURG => "Urgent I/O condition",
+        Signal::SIGXCPU => "CPU time limit exceeded",
+        Signal::SIGXFSZ => "File size limit exceeded",
+        Signal::SIGVTALRM => "Virtual timer expired",
+        Signal::SIGPROF => "Profiling timer expired",
+        Signal::SIGWINCH => "Window size changed",
+        Signal::SIGIO => "I/O possible",
+        Signal::SIGPWR => "Power fail/restart",
+        Signal::SIGSYS => "Bad system call",
+        _ => "Unknown signal",
+    }
+}
@@ -0,0 +1,214 @@
+//! Shared libraries loaded by the dynamic linker. ld.so keeps a list of them, the link_map,
+//! which is reachable from its `_r_debug`, and calls `r_debug.r_brk` whenever the list changes,
+//! so a breakpoint there tells us when libraries come and go.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::Mapping;
+use object::{Object, ObjectSection, ObjectSymbol};
+use std::fs;
+use std::path::Path;
+
+/// Auxiliary vector entries: where ld.so was loaded, and the program's entry point.
+pub const AT_BASE: u64 = 7;
+pub const AT_ENTRY: u64 = 9;
+
+/// Offsets into `struct r_debug` and `struct link_map`.
+const R_DEBUG_MAP: usize = 8;
+const R_DEBUG_BRK: usize = 16;
+const LINK_MAP_ADDR: usize = 0;
+const LINK_MAP_NAME: usize = 8;
+const LINK_MAP_NEXT: usize = 24;
+/// Stop following the link_map after this many entries, in case it's corrupted.
+const MAX_LIBRARIES: usize = 1024;
+const MAX_PATH_LEN: usize = 4096;
+
+/// Reads `len` bytes of the inferior's memory.
+pub type ReadMemory<'a> = &'a dyn Fn(usize, usize) -> Option<Vec<u8>>;
+
+pub struct SharedLibrary {
+    pub path: String,
+    /// How far the library was loaded from the addresses it was linked at.
+    pub bias: usize,
+    /// Where its code is, as (start, end).
+    pub text: (usize, usize),
+    pub debug_data: DwarfData,
+}
+
+impl SharedLibrary {
+    fn load(path: &str, bias: usize, debug_dirs: &[String]) -> Option<SharedLibrary> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+        let object = object::read::File::parse(&*mmap).ok()?;
+        let text = object
+            .section_by_name(".text")
+            .map(|section| (section.address() as usize, (section.address() + section.size()) as usize))
+            .unwrap_or((0, 0));
+        let mut debug_data = DwarfData::from_file(path, debug_dirs).ok()?;
+        debug_data.relocate(bias);
+        Some(SharedLibrary {
+            path: path.to_string(),
+            bias,
+            text: (text.0 + bias, text.1 + bias),
+            debug_data,
+        })
+    }
+
+    /// Returns true if the library has DWARF debugging information.
+    pub fn has_debug_info(&self) -> bool {
+        self.debug_data.has_debug_info()
+    }
+
+    /// Finds where to break for a `[file:]line` or `[file:]function` location: after the
+    /// function's prologue if there's debug info, otherwise at its symbol. Locations that match
+    /// more than one place in the library don't count.
+    pub fn location_address(&self, spec: &str) -> Option<usize> {
+        match self.debug_data.get_locations(spec, None) {
+            Ok(locations) if locations.len() == 1 => Some(locations[0].address),
+            _ => None,
+        }
+    }
+
+    /// Names the function containing `addr`.
+    pub fn function_name(&self, addr: usize) -> Option<String> {
+        self.debug_data.get_function_from_addr(addr)
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.text.0 <= addr && addr < self.text.1
+    }
+}
+
+/// The shared libraries of the inferior, as ld.so sees them.
+#[derive(Default)]
+pub struct SharedLibraries {
+    /// The address of ld.so's `_r_debug`, if the program has a dynamic linker.
+    r_debug: Option<usize>,
+    /// The breakpoints that tell us the list may have changed: first the program's entry point,
+    /// by which time ld.so has loaded the libraries the program was linked with, and from then
+    /// on `r_brk`.
+    events: Vec<usize>,
+    libraries: Vec<SharedLibrary>,
+}
+
+impl SharedLibraries {
+    /// Finds ld.so's `_r_debug` from where the auxiliary vector says ld.so was loaded. Statically
+    /// linked programs have no ld.so and no shared libraries.
+    pub fn locate(auxv: &[(u64, u64)], mappings: &[Mapping]) -> SharedLibraries {
+        let lookup = |key: u64| auxv.iter().find(|(entry, _)| *entry == key).map(|(_, value)| *value as usize);
+        let mut libraries = SharedLibraries::default();
+        let base = match lookup(AT_BASE) {
+            Some(base) if base != 0 => base,
+            _ => return libraries,
+        };
+        let interpreter = match mappings.iter().find(|mapping| mapping.start as usize == base) {
+            Some(mapping) => &mapping.path,
+            None => return libraries,
+        };
+        libraries.r_debug = symbol_address(interpreter, "_r_debug").map(|addr| addr + base);
+        libraries.events.extend(lookup(AT_ENTRY));
+        libraries
+    }
+
+    pub fn is_located(&self) -> bool {
+        self.r_debug.is_some()
+    }
+
+    pub fn events(&self) -> &[usize] {
+        &self.events
+    }
+
+    pub fn is_event(&self, addr: usize) -> bool {
+        self.events.contains(&addr)
+    }
+
+    /// Re-reads the link_map, loading the symbols of libraries that appeared and dropping those
+    /// that went away. Stripped libraries get their debug info from under `debug_dirs`.
+    pub fn update(&mut self, read: ReadMemory, debug_dirs: &[String]) {
+        let r_debug = match self.r_debug {
+            Some(r_debug) => r_debug,
+            None => return,
+        };
+        // r_brk is filled in once ld.so has set itself up
+        if let Some(r_brk) = read_word(read, r_debug + R_DEBUG_BRK).filter(|&r_brk| r_brk != 0) {
+            self.events = vec![r_brk];
+        }
+        let mut libraries = Vec::new();
+        let mut entry = read_word(read, r_debug + R_DEBUG_MAP).unwrap_or(0);
+        while entry != 0 && libraries.len() < MAX_LIBRARIES {
+            let bias = read_word(read, entry + LINK_MAP_ADDR).unwrap_or(0);
+            let path = read_word(read, entry + LINK_MAP_NAME).and_then(|name| read_string(read, name));
+            // The program itself has no name, and neither the vdso nor anything deleted since
+            // it was loaded can be read from disk
+            if let Some(path) = path.filter(|path| Path::new(path).is_file()) {
+                let index = self.libraries.iter().position(|lib| lib.path == path && lib.bias == bias);
+                match index {
+                    Some(index) => libraries.push(self.libraries.remove(index)),
+                    None => libraries.extend(SharedLibrary::load(&path, bias, debug_dirs)),
+                }
+            }
+            entry = read_word(read, entry + LINK_MAP_NEXT).unwrap_or(0);
+        }
+        self.libraries = libraries;
+    }
+
+    /// Returns the library whose code contains `addr`.
+    pub fn containing(&self, addr: usize) -> Option<&SharedLibrary> {
+        self.libraries.iter().find(|lib| lib.contains(addr))
+    }
+
+    /// Finds a breakpoint location in the first library that has it.
+    pub fn location_address(&self, spec: &str) -> Option<usize> {
+        self.libraries.iter().find_map(|lib| lib.location_address(spec))
+    }
+
+    /// Prints the libraries like gdb's `info sharedlibrary`.
+    pub fn print(&self) {
+        if self.libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("{:<20}{:<20}{:<12}Shared Object Library", "From", "To", "Syms Read");
+        for lib in self.libraries.iter() {
+            let syms = if lib.has_debug_info() { "Yes" } else { "Yes (*)" };
+            println!("{:<#20x}{:<#20x}{:<12}{}", lib.text.0, lib.text.1, syms, lib.path);
+        }
+        if self.libraries.iter().any(|lib| !lib.has_debug_info()) {
+            println!("(*): Shared library is missing debugging information.");
+        }
+    }
+}
+
+/// Looks up a symbol's link-time address in an object file.
+fn symbol_address(path: &str, name: &str) -> Option<usize> {
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+    let object = object::read::File::parse(&*mmap).ok()?;
+    object
+        .dynamic_symbols()
+        .chain(object.symbols())
+        .find(|symbol| symbol.name() == Ok(name))
+        .map(|symbol| symbol.address() as usize)
+}
+
+fn read_word(read: ReadMemory, addr: usize) -> Option<usize> {
+    let bytes = read(addr, 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?) as usize)
+}
+
+/// Reads a NUL-terminated string a word at a time, since it may end right before unmapped
+/// memory.
+fn read_string(read: ReadMemory, addr: usize) -> Option<String> {
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_PATH_LEN {
+        let next = addr + bytes.len();
+        let chunk = read(next, 8 - next % 8)?;
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                return Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    None
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::fs;
+
+/// Number of lines shown by a single `list` command.
+pub const LIST_WINDOW: usize = 10;
+
+/// Caches the contents of source files named in the debugging information so that repeated
+/// listings don't hit the filesystem every time.
+pub struct SourceFiles {
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl SourceFiles {
+    pub fn new() -> SourceFiles {
+        SourceFiles {
+            files: HashMap::new(),
+        }
+    }
+
+    /// Returns the lines of `path`, or None if the file can't be read.
+    fn get_lines(&mut self, path: &str) -> Option<&Vec<String>> {
+        self.files
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                fs::read_to_string(path)
+                    .ok()
+                    .map(|contents| contents.lines().map(|line| line.to_string()).collect())
+            })
+            .as_ref()
+    }
+
+    /// Prints lines `first..=last` (1-based) of `path`, marking `current` with an arrow. Returns
+    /// the number of the last line printed, or None if nothing could be printed.
+    pub fn print_lines(
+        &mut self,
+        path: &str,
+        first: usize,
+        last: usize,
+        current: Option<usize>,
+    ) -> Option<usize> {
+        let lines = match self.get_lines(path) {
+            Some(lines) => lines,
+            None => {
+                println!("Could not read source file {}", path);
+                return None;
+            }
+        };
+        let first = first.max(1);
+        let last = last.min(lines.len());
+        if first > last {
+            println!("Line number {} out of range; \"{}\" has {} lines.", first, path, lines.len());
+            return None;
+        }
+        for number in first..=last {
+            let marker = if Some(number) == current { "=>" } else { "  " };
+            println!("{} {}\t{}", marker, number, lines[number - 1]);
+        }
+        Some(last)
+    }
+}
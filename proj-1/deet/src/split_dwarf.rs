@@ -0,0 +1,36 @@
+//! Split DWARF. Compiling with -gsplit-dwarf leaves just a skeleton of each compilation unit in
+//! the executable, with its addresses and line table, and moves the rest of its debug info to a
+//! `.dwo` file named by the skeleton. `dwp` can combine a program's `.dwo` files into a single
+//! `.dwp` package next to it, with an index from each unit's DWO id to its part of each section.
+
+use object::{Object, ObjectSection};
+use std::collections::HashMap;
+use std::fs;
+
+/// The sections of a `.dwo` file or a `.dwp` package, read into memory for gimli to borrow.
+pub struct SplitFile {
+    sections: HashMap<String, Vec<u8>>,
+}
+
+impl SplitFile {
+    pub fn open(path: &str) -> Option<SplitFile> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+        let object = object::read::File::parse(&*mmap).ok()?;
+        let mut sections = HashMap::new();
+        for section in object.sections() {
+            if let (Ok(name), Ok(data)) = (section.name(), section.uncompressed_data()) {
+                sections.insert(name.to_string(), data.into_owned());
+            }
+        }
+        Some(SplitFile { sections })
+    }
+
+    /// Returns the contents of the section that holds `id` in a split file (e.g.
+    /// `.debug_info.dwo`), which is empty if the file has none.
+    pub fn section(&self, id: gimli::SectionId) -> &[u8] {
+        id.dwo_name()
+            .and_then(|name| self.sections.get(name))
+            .map_or(&[][..], |data| &data[..])
+    }
+}
@@ -0,0 +1,246 @@
+//! Unwinding the stack with the call frame information in `.eh_frame` and `.debug_frame`, so
+//! that backtraces don't depend on frame pointers and can go through libc.
+
+use crate::inferior::Mapping;
+use gimli::{CfaRule, RegisterRule, UnwindSection};
+use nix::libc::user_regs_struct;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::{borrow, fs};
+
+/// DWARF numbers rax..r15 as 0..15 and uses 16 for the return address, i.e. the caller's rip.
+const NUM_REGISTERS: usize = 17;
+const RBP: usize = 6;
+const RSP: usize = 7;
+const RIP: usize = 16;
+/// The System V ABI lets a function clobber rax, rdx, rcx, rsi, rdi and r8-r11, so their values
+/// in its callers are unknown unless the unwind rules say where they were saved.
+const CALLER_SAVED: [usize; 9] = [0, 1, 2, 4, 5, 8, 9, 10, 11];
+
+/// Registers of a frame, indexed by DWARF register number. Unwinding can only recover some of a
+/// caller's registers, so the others are unknown.
+#[derive(Clone)]
+pub struct FrameRegisters([Option<u64>; NUM_REGISTERS]);
+
+impl FrameRegisters {
+    pub fn from_regs(regs: &user_regs_struct) -> FrameRegisters {
+        FrameRegisters([
+            Some(regs.rax),
+            Some(regs.rdx),
+            Some(regs.rcx),
+            Some(regs.rbx),
+            Some(regs.rsi),
+            Some(regs.rdi),
+            Some(regs.rbp),
+            Some(regs.rsp),
+            Some(regs.r8),
+            Some(regs.r9),
+            Some(regs.r10),
+            Some(regs.r11),
+            Some(regs.r12),
+            Some(regs.r13),
+            Some(regs.r14),
+            Some(regs.r15),
+            Some(regs.rip),
+        ])
+    }
+
+    pub fn rip(&self) -> Option<u64> {
+        self.0[RIP]
+    }
+
+    pub fn rsp(&self) -> Option<u64> {
+        self.0[RSP]
+    }
+
+    pub fn rbp(&self) -> Option<u64> {
+        self.0[RBP]
+    }
+
+    /// Looks up a register by its DWARF number.
+    pub fn get(&self, register: u16) -> Option<u64> {
+        self.0.get(register as usize).copied().flatten()
+    }
+
+    /// The registers as they are in the caller, before applying any unwind rules.
+    fn for_caller(&self) -> FrameRegisters {
+        let mut caller = self.clone();
+        for &index in CALLER_SAVED.iter() {
+            caller.0[index] = None;
+        }
+        caller
+    }
+}
+
+/// The call frame information of one object file.
+pub struct CallFrameInfo {
+    endian: gimli::RunTimeEndian,
+    eh_frame: Option<Vec<u8>>,
+    eh_frame_bases: gimli::BaseAddresses,
+    debug_frame: Option<Vec<u8>>,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File) -> CallFrameInfo {
+        let endian = if object.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+        let section_data = |name: &str| {
+            let section = object.section_by_name(name)?;
+            Some(section.uncompressed_data().unwrap_or(borrow::Cow::Borrowed(&[][..])).into_owned())
+        };
+        let section_address = |name: &str| object.section_by_name(name).map(|section| section.address());
+        // Pointers in .eh_frame can be relative to these sections
+        let mut bases = gimli::BaseAddresses::default();
+        if let Some(address) = section_address(".eh_frame") {
+            bases = bases.set_eh_frame(address);
+        }
+        if let Some(address) = section_address(".eh_frame_hdr") {
+            bases = bases.set_eh_frame_hdr(address);
+        }
+        if let Some(address) = section_address(".text") {
+            bases = bases.set_text(address);
+        }
+        if let Some(address) = section_address(".got") {
+            bases = bases.set_got(address);
+        }
+        CallFrameInfo {
+            endian,
+            eh_frame: section_data(".eh_frame"),
+            eh_frame_bases: bases,
+            debug_frame: section_data(".debug_frame"),
+        }
+    }
+
+    /// Finds the unwind rules that apply at `addr` (an address in the object file).
+    fn row(&self, addr: u64) -> Option<gimli::UnwindTableRow<usize>> {
+        let mut ctx = gimli::UnwindContext::new();
+        if let Some(data) = &self.eh_frame {
+            let mut eh_frame = gimli::EhFrame::new(data, self.endian);
+            eh_frame.set_address_size(8);
+            let row = eh_frame.unwind_info_for_address(
+                &self.eh_frame_bases,
+                &mut ctx,
+                addr,
+                gimli::EhFrame::cie_from_offset,
+            );
+            if let Ok(row) = row {
+                return Some(row.clone());
+            }
+        }
+        let mut debug_frame = gimli::DebugFrame::new(self.debug_frame.as_ref()?, self.endian);
+        debug_frame.set_address_size(8);
+        let bases = gimli::BaseAddresses::default();
+        debug_frame
+            .unwind_info_for_address(&bases, &mut ctx, addr, gimli::DebugFrame::cie_from_offset)
+            .ok()
+            .cloned()
+    }
+}
+
+/// An object file mapped into the inferior.
+struct Module {
+    start: u64,
+    end: u64,
+    /// Difference between where the file is loaded and the addresses it was linked at.
+    bias: u64,
+    cfi: CallFrameInfo,
+}
+
+/// The object files mapped into a process.
+pub struct Modules {
+    modules: Vec<Module>,
+}
+
+impl Modules {
+    /// Loads the object files of a process's file mappings.
+    pub fn from_mappings(mappings: &[Mapping]) -> Modules {
+        let mut modules: Vec<Module> = Vec::new();
+        // Each file has several mappings: note where the whole file starts and ends
+        let mut files: Vec<(&str, u64, u64)> = Vec::new();
+        for mapping in mappings {
+            match files.iter_mut().find(|(path, _, _)| *path == mapping.path) {
+                Some(file) => file.2 = file.2.max(mapping.end),
+                None if mapping.offset == 0 => files.push((&mapping.path, mapping.start, mapping.end)),
+                None => {}
+            }
+        }
+        for (path, start, end) in files {
+            if let Some(module) = Module::load(path, start, end) {
+                modules.push(module);
+            }
+        }
+        Modules { modules }
+    }
+
+    /// Computes the registers of the caller of the frame with registers `regs`. `pc` is the
+    /// address to look up unwind rules for, and `read` reads a word of inferior memory. Code
+    /// without call frame information is assumed to keep a frame pointer in rbp.
+    pub fn unwind<F>(&self, regs: &FrameRegisters, pc: u64, read: F) -> Option<FrameRegisters>
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let row = self
+            .modules
+            .iter()
+            .find(|module| module.start <= pc && pc < module.end)
+            .and_then(|module| module.cfi.row(pc - module.bias));
+        let row = match row {
+            Some(row) => row,
+            None => {
+                let rbp = regs.rbp().filter(|&rbp| rbp != 0)?;
+                let mut caller = regs.for_caller();
+                caller.0[RIP] = Some(read(rbp + 8)?);
+                caller.0[RBP] = Some(read(rbp)?);
+                caller.0[RSP] = Some(rbp + 16);
+                return Some(caller);
+            }
+        };
+        let cfa = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                (regs.0.get(register.0 as usize).copied().flatten()? as i64 + offset) as u64
+            }
+            CfaRule::Expression(_) => return None,
+        };
+        let mut caller = regs.for_caller();
+        // The return address is only known if a rule says where it was saved
+        caller.0[RIP] = None;
+        caller.0[RSP] = Some(cfa);
+        for (register, rule) in row.registers() {
+            let index = register.0 as usize;
+            if index >= NUM_REGISTERS {
+                continue;
+            }
+            caller.0[index] = match rule {
+                RegisterRule::Undefined => None,
+                RegisterRule::SameValue => regs.0[index],
+                RegisterRule::Offset(offset) => read((cfa as i64 + offset) as u64),
+                RegisterRule::ValOffset(offset) => Some((cfa as i64 + offset) as u64),
+                RegisterRule::Register(other) => regs.0.get(other.0 as usize).copied().flatten(),
+                _ => None,
+            };
+        }
+        Some(caller)
+    }
+}
+
+impl Module {
+    fn load(path: &str, start: u64, end: u64) -> Option<Module> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+        let object = object::read::File::parse(&*mmap).ok()?;
+        // The segment at file offset 0 is the one mapped at `start`
+        let linked = object
+            .segments()
+            .find(|segment| segment.file_range().0 == 0)
+            .map(|segment| segment.address())
+            .unwrap_or(0);
+        Some(Module {
+            start,
+            end,
+            bias: start.wrapping_sub(linked),
+            cfi: CallFrameInfo::load(&object),
+        })
+    }
+}
@@ -0,0 +1,91 @@
+//! Hardware watchpoints, implemented with the x86-64 debug registers. DR0-DR3 hold the watched
+//! addresses, DR7 enables them and says what kind of access to trap on, and DR6 tells us which
+//! of them fired.
+
+use crate::dwarf_data::Type;
+use nix::unistd::Pid;
+
+/// Number of address debug registers (DR0-DR3).
+pub const NUM_DEBUG_REGISTERS: usize = 4;
+pub const DR_STATUS: usize = 6;
+pub const DR_CONTROL: usize = 7;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// Break when the value is written (`watch`).
+    Write,
+    /// Break when the value is read (`rwatch`). x86 can't trap on reads alone, so this traps on
+    /// any access, like gdb does.
+    Read,
+}
+
+pub struct Watchpoint {
+    pub number: usize,
+    /// The expression the user asked to watch (`var` or `*addr`).
+    pub expr: String,
+    pub addr: usize,
+    pub entity_type: Type,
+    pub kind: WatchKind,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Debug register slot (0-3) while the watchpoint is enabled.
+    pub slot: Option<usize>,
+    /// Contents of the watched memory as of the last stop.
+    pub old_value: Vec<u8>,
+    /// The frame the expression was evaluated in, if it refers to its locals.
+    pub scope: Option<WatchScope>,
+}
+
+/// The frame a watchpoint on a local variable belongs to. Once it returns, the variable's
+/// stack slot gets reused, so the watchpoint is deleted.
+#[derive(Clone, Copy)]
+pub struct WatchScope {
+    /// The thread whose stack the frame is on.
+    pub tid: Pid,
+    /// The frame's CFA, which is the stack pointer after it returns.
+    pub cfa: usize,
+    /// Where the frame returns to, which gets an internal breakpoint.
+    pub ret_addr: usize,
+}
+
+/// A watchpoint that triggered, with the values to report to the user.
+pub struct WatchHit {
+    pub number: usize,
+    pub expr: String,
+    pub kind: WatchKind,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl Watchpoint {
+    /// Returns the DR7 bits enabling this watchpoint in its slot.
+    pub fn control_bits(&self) -> u64 {
+        let slot = match self.slot {
+            Some(slot) => slot as u64,
+            None => return 0,
+        };
+        let rw: u64 = match self.kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read => 0b11,
+        };
+        let len: u64 = match self.entity_type.size {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        // Local enable bit, then the R/W and LEN fields for this slot.
+        (1 << (2 * slot)) | (rw << (16 + 4 * slot)) | (len << (18 + 4 * slot))
+    }
+}
+
+/// Checks that a value of `size` bytes at `addr` can be watched by a single debug register.
+pub fn check_watchable(addr: usize, size: usize) -> Result<(), String> {
+    if ![1, 2, 4, 8].contains(&size) {
+        return Err(format!("cannot watch a value of {} bytes", size));
+    }
+    if !addr.is_multiple_of(size) {
+        return Err(format!("address {:#x} is not aligned to {} bytes", addr, size));
+    }
+    Ok(())
+}